@@ -1,3 +1,10 @@
 fn main() {
+    // clockblocks-cli has no Tauri context to find the app's directories with
+    let config = std::fs::read_to_string("tauri.conf.json").expect("Failed to read tauri.conf.json");
+    let config: serde_json::Value = serde_json::from_str(&config).expect("Invalid tauri.conf.json");
+    let identifier = config["tauri"]["bundle"]["identifier"].as_str().expect("No identifier in tauri.conf.json");
+    println!("cargo:rustc-env=CLOCKBLOCKS_IDENTIFIER={}", identifier);
+    println!("cargo:rerun-if-changed=tauri.conf.json");
+
     tauri_build::build()
 }
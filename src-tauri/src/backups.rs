@@ -0,0 +1,324 @@
+//! Snapshots of the SQLite database, taken with the online backup API so they
+//! are consistent even while the webview is writing. One is taken on launch and
+//! another whenever the newest is a day old; the oldest are rotated out.
+//! Snapshots taken before the database is replaced are named and rotated on
+//! their own, so undoing restores doesn't push out the daily ones.
+
+use crate::db;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tauri::Manager;
+
+const BACKUP_DIR_NAME: &str = "backups";
+const BACKUP_PREFIX: &str = "clockblocks-";
+const BEFORE_RESTORE_PREFIX: &str = "clockblocks-before-restore-";
+const BACKUP_EXTENSION: &str = "db";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// How many snapshots to keep, the oldest are deleted first
+const BACKUP_ROTATIONS: usize = 14;
+const BEFORE_RESTORE_ROTATIONS: usize = 5;
+const BACKUP_INTERVAL_HOURS: i64 = 24;
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const REQUIRED_TABLES: [&str; 4] = ["timeBoxes", "sessions", "sessionEvents", "metadata"];
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum BackupKind {
+    /// Taken on launch and daily
+    Scheduled,
+    /// The database as it was before a restore or import replaced it
+    BeforeRestore,
+}
+
+impl BackupKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            BackupKind::Scheduled => BACKUP_PREFIX,
+            BackupKind::BeforeRestore => BEFORE_RESTORE_PREFIX,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub file_name: String,
+    pub kind: BackupKind,
+    pub created_at: String,
+    pub size_bytes: u64,
+}
+
+pub fn get_backup_dir(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(BACKUP_DIR_NAME)
+}
+
+/// What kind of snapshot a file is and when it was taken, read back from its
+/// file name.
+fn parse_backup_name(file_name: &str) -> Option<(BackupKind, DateTime<Utc>)> {
+    // The scheduled prefix is also the start of the other
+    let kind = [BackupKind::BeforeRestore, BackupKind::Scheduled]
+        .into_iter()
+        .find(|kind| file_name.starts_with(kind.prefix()))?;
+    let stamp = file_name.strip_prefix(kind.prefix())?.strip_suffix(&format!(".{}", BACKUP_EXTENSION))?;
+    let naive = NaiveDateTime::parse_from_str(stamp, BACKUP_TIME_FORMAT).ok()?;
+    let created_at = Local.from_local_datetime(&naive).earliest()?.with_timezone(&Utc);
+    Some((kind, created_at))
+}
+
+/// Snapshots in the directory, newest first.
+pub fn list_backups_in(directory: &Path) -> Result<Vec<BackupInfo>, String> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups: Vec<(DateTime<Utc>, BackupInfo)> = fs::read_dir(directory)
+        .map_err(|e| format!("Failed to read {}: {}", directory.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let (kind, created_at) = parse_backup_name(&file_name)?;
+            let size_bytes = entry.metadata().ok()?.len();
+            Some((created_at, BackupInfo { file_name, kind, created_at: db::format_datetime(&created_at), size_bytes }))
+        })
+        .collect();
+    backups.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(backups.into_iter().map(|(_, info)| info).collect())
+}
+
+/// Copies the live database into `directory` through a temporary file, so a
+/// half written snapshot never shows up in the list.
+pub fn create_backup_in(source: &Connection, directory: &Path, kind: BackupKind) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+    let file_name = format!("{}{}.{}", kind.prefix(), Local::now().format(BACKUP_TIME_FORMAT), BACKUP_EXTENSION);
+    let path = directory.join(&file_name);
+    let partial_path = directory.join(format!("{}.partial", file_name));
+
+    source
+        .backup(DatabaseName::Main, &partial_path, None)
+        .map_err(|e| format!("Failed to back up database: {}", e))?;
+    fs::rename(&partial_path, &path).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// Deletes all but the newest `keep` snapshots of the kind.
+pub fn rotate_backups(directory: &Path, kind: BackupKind, keep: usize) -> Result<(), String> {
+    let backups = list_backups_in(directory)?.into_iter().filter(|backup| backup.kind == kind);
+    for backup in backups.skip(keep) {
+        let path = directory.join(&backup.file_name);
+        fs::remove_file(&path).map_err(|e| format!("Failed to delete {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Checks a snapshot before it replaces the database: it has to open as
+/// SQLite, pass the integrity check and contain the app's tables.
+pub fn validate_backup(path: &Path) -> Result<(), String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open backup: {}", e))?;
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| format!("Backup is not a readable database: {}", e))?;
+    if integrity != "ok" {
+        return Err(format!("Backup failed the integrity check: {}", integrity));
+    }
+
+    for table in REQUIRED_TABLES {
+        let exists: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+                [table],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if !exists {
+            return Err(format!("Backup has no {} table", table));
+        }
+    }
+    Ok(())
+}
+
+/// Takes a snapshot unless the database hasn't been created yet.
+fn backup_now(app_handle: &tauri::AppHandle) -> Result<Option<PathBuf>, String> {
+    if !db::get_db_path(app_handle).exists() {
+        return Ok(None);
+    }
+    let directory = get_backup_dir(app_handle);
+    let conn = db::open_connection(app_handle)?;
+    let path = create_backup_in(&conn, &directory, BackupKind::Scheduled)?;
+    rotate_backups(&directory, BackupKind::Scheduled, BACKUP_ROTATIONS)?;
+    Ok(Some(path))
+}
+
+/// Keeps the database as it is before something replaces it, so that can be
+/// undone with `restore_backup`.
+pub fn backup_before_restore(conn: &Connection, app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let directory = get_backup_dir(app_handle);
+    let path = create_backup_in(conn, &directory, BackupKind::BeforeRestore)?;
+    rotate_backups(&directory, BackupKind::BeforeRestore, BEFORE_RESTORE_ROTATIONS)?;
+    Ok(path)
+}
+
+fn is_backup_due(app_handle: &tauri::AppHandle) -> Result<bool, String> {
+    let newest = list_backups_in(&get_backup_dir(app_handle))?
+        .into_iter()
+        .find(|backup| backup.kind == BackupKind::Scheduled)
+        .and_then(|backup| parse_backup_name(&backup.file_name))
+        .map(|(_, created_at)| created_at);
+    Ok(newest.map_or(true, |newest| Utc::now() - newest >= chrono::Duration::hours(BACKUP_INTERVAL_HOURS)))
+}
+
+/// Backs up on launch, then checks hourly whether the daily snapshot is due,
+/// which also catches up after the machine was asleep.
+pub fn start_backup_schedule(app_handle: tauri::AppHandle) {
+    thread::spawn(move || {
+        match backup_now(&app_handle) {
+            Ok(Some(path)) => println!("Backed up database to {}", path.display()),
+            Ok(None) => println!("No database to back up yet"),
+            Err(e) => println!("Failed to back up database on launch: {}", e),
+        }
+        loop {
+            thread::sleep(SCHEDULE_CHECK_INTERVAL);
+            match is_backup_due(&app_handle).and_then(|due| if due { backup_now(&app_handle) } else { Ok(None) }) {
+                Ok(Some(path)) => println!("Backed up database to {}", path.display()),
+                Ok(None) => {}
+                Err(e) => println!("Failed to back up database: {}", e),
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn list_backups(app_handle: tauri::AppHandle) -> Result<Vec<BackupInfo>, String> {
+    list_backups_in(&get_backup_dir(&app_handle))
+}
+
+#[tauri::command]
+pub async fn backup_database(app_handle: tauri::AppHandle) -> Result<Option<String>, String> {
+    Ok(backup_now(&app_handle)?.map(|path| path.display().to_string()))
+}
+
+/// Replaces the database with a snapshot from `list_backups`. The current state
+/// is backed up first, so a restore can itself be undone. The snapshot is copied
+/// in with the backup API rather than swapping files, as the webview keeps its
+/// connection to the database open; it reloads on the `database-restored` event.
+#[tauri::command]
+pub async fn restore_backup(app_handle: tauri::AppHandle, file_name: String) -> Result<(), String> {
+    let directory = get_backup_dir(&app_handle);
+    let is_listed = list_backups_in(&directory)?.iter().any(|backup| backup.file_name == file_name);
+    if !is_listed {
+        return Err(format!("Unknown backup {}", file_name));
+    }
+    let path = directory.join(&file_name);
+    validate_backup(&path)?;
+
+    let mut conn = db::open_connection(&app_handle)?;
+    backup_before_restore(&conn, &app_handle)?;
+    conn.restore(DatabaseName::Main, &path, None::<fn(rusqlite::backup::Progress)>)
+        .map_err(|e| format!("Failed to restore {}: {}", file_name, e))?;
+
+    println!("Restored database from {}", path.display());
+    app_handle.emit_all("database-restored", file_name).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("clockblocks-backups-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn backup_name(kind: BackupKind, day: u32) -> String {
+        format!("{}202408{:02}-090000.{}", kind.prefix(), day, BACKUP_EXTENSION)
+    }
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        list_backups_in(directory).unwrap().into_iter().map(|backup| backup.file_name).collect()
+    }
+
+    #[test]
+    fn reads_kind_and_time_from_file_names() {
+        let (kind, _) = parse_backup_name("clockblocks-20240801-090000.db").unwrap();
+        assert_eq!(kind, BackupKind::Scheduled);
+        let (kind, _) = parse_backup_name("clockblocks-before-restore-20240801-090000.db").unwrap();
+        assert_eq!(kind, BackupKind::BeforeRestore);
+        assert!(parse_backup_name("clockblocks-20240801-090000.db.partial").is_none());
+        assert!(parse_backup_name("notes.txt").is_none());
+    }
+
+    #[test]
+    fn rotates_out_the_oldest_of_a_kind() {
+        let directory = TempDir::new();
+        for day in [3, 1, 4, 2] {
+            fs::write(directory.0.join(backup_name(BackupKind::Scheduled, day)), "").unwrap();
+        }
+        fs::write(directory.0.join(backup_name(BackupKind::BeforeRestore, 5)), "").unwrap();
+        fs::write(directory.0.join("notes.txt"), "").unwrap();
+
+        rotate_backups(&directory.0, BackupKind::Scheduled, 2).unwrap();
+        assert_eq!(
+            file_names(&directory.0),
+            vec![
+                backup_name(BackupKind::BeforeRestore, 5),
+                backup_name(BackupKind::Scheduled, 4),
+                backup_name(BackupKind::Scheduled, 3),
+            ]
+        );
+        assert!(directory.0.join("notes.txt").exists());
+
+        rotate_backups(&directory.0, BackupKind::BeforeRestore, 0).unwrap();
+        assert_eq!(file_names(&directory.0).len(), 2);
+    }
+
+    #[test]
+    fn snapshots_validate_and_round_trip() {
+        let directory = TempDir::new();
+        let conn = Connection::open_in_memory().unwrap();
+        db::create_tables(&conn).unwrap();
+        conn.execute("INSERT INTO metadata (name, value) VALUES ('version', '1')", []).unwrap();
+
+        let path = create_backup_in(&conn, &directory.0, BackupKind::BeforeRestore).unwrap();
+        validate_backup(&path).unwrap();
+        assert_eq!(list_backups_in(&directory.0).unwrap()[0].kind, BackupKind::BeforeRestore);
+        let copy = Connection::open(&path).unwrap();
+        let version: String = copy.query_row("SELECT value FROM metadata", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, "1");
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_sqlite() {
+        let directory = TempDir::new();
+        let path = directory.0.join(backup_name(BackupKind::Scheduled, 1));
+        fs::write(&path, "not a database, just some text that is long enough to have a header").unwrap();
+        assert!(validate_backup(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_databases_without_the_app_tables() {
+        let directory = TempDir::new();
+        let conn = Connection::open_in_memory().unwrap();
+        db::create_tables(&conn).unwrap();
+        conn.execute("DROP TABLE sessionEvents", []).unwrap();
+
+        let path = create_backup_in(&conn, &directory.0, BackupKind::Scheduled).unwrap();
+        assert_eq!(validate_backup(&path), Err("Backup has no sessionEvents table".to_string()));
+    }
+}
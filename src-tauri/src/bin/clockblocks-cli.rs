@@ -0,0 +1,367 @@
+//! Headless companion to the app, working on the same database:
+//!
+//! ```text
+//! clockblocks-cli switch <time box id or name>
+//! clockblocks-cli end
+//! clockblocks-cli status
+//! clockblocks-cli report [--today | --week | --month | --from YYYY-MM-DD --to YYYY-MM-DD]
+//! clockblocks-cli export --csv [--dir DIR] [range]
+//! ```
+//!
+//! While the app is running, `switch`, `end` and `status` go through its control
+//! API so the timer in the webview stays the only one. With the API turned off,
+//! `switch` and `end` are handed to the app like the arguments of a second
+//! launch. Only when the app isn't running do they write to the database
+//! directly, through `cli_timer`, and the app ends that session when it
+//! launches. The binary isn't called `clockblocks` because it would overwrite
+//! `ClockBlocks` on case-insensitive file systems.
+
+use chrono::{Datelike, Duration, Local, NaiveDate, Utc};
+use clockblocks::cli_timer::{self, RunningTimer};
+use clockblocks::db::{self, Session, TimeBox};
+use clockblocks::paths::{self, APP_IDENTIFIER};
+use clockblocks::report::{self, DateRange, ReportData};
+use clockblocks::single_instance;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process;
+
+const CONTROL_API_FILE_NAME: &str = "control_api.json";
+const GUI_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+const USAGE: &str = "Usage:
+  clockblocks-cli switch <time box>
+  clockblocks-cli end
+  clockblocks-cli status
+  clockblocks-cli report [--today | --week | --month | --from YYYY-MM-DD --to YYYY-MM-DD]
+  clockblocks-cli export --csv [--dir DIR] [--today | --week | --month | --from YYYY-MM-DD --to YYYY-MM-DD]";
+
+/// Command line options by name, with the value for those that take one
+type Options = HashMap<String, Option<String>>;
+
+/// The part of the app's `control_api.json` the CLI needs.
+#[derive(Deserialize)]
+struct ControlApiSettings {
+    enabled: bool,
+    port: u16,
+    token: String,
+}
+
+fn get_data_dir() -> Result<PathBuf, String> {
+    paths::data_dir(APP_IDENTIFIER)
+}
+
+fn open_database() -> Result<Connection, String> {
+    let path = db::db_path(APP_IDENTIFIER)?;
+    if !path.exists() {
+        return Err(format!("No database at {}, open the app once to create it", path.display()));
+    }
+    db::open_connection_at(&path)
+}
+
+/// The running app, reached through its control API.
+struct Gui {
+    client: reqwest::blocking::Client,
+    base_url: String,
+    token: String,
+}
+
+impl Gui {
+    /// Returns `None` when the control API is off or nothing is listening.
+    fn connect() -> Result<Option<Gui>, String> {
+        let path = get_data_dir()?.join(CONTROL_API_FILE_NAME);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        let settings: ControlApiSettings =
+            serde_json::from_str(&contents).map_err(|e| format!("Invalid control API settings: {}", e))?;
+        if !settings.enabled {
+            return Ok(None);
+        }
+
+        let client = reqwest::blocking::Client::builder().timeout(GUI_TIMEOUT).build().map_err(|e| e.to_string())?;
+        let gui = Gui { client, base_url: format!("http://127.0.0.1:{}", settings.port), token: settings.token };
+        match gui.request(reqwest::Method::GET, "/state") {
+            Ok(_) => Ok(Some(gui)),
+            Err(GuiError::Unreachable) => Ok(None),
+            Err(GuiError::Failed(e)) => Err(e),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> Result<Value, GuiError> {
+        let response = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path))
+            .bearer_auth(&self.token)
+            .send()
+            .map_err(|e| if e.is_connect() { GuiError::Unreachable } else { GuiError::Failed(e.to_string()) })?;
+        let status = response.status();
+        let body: Value = response.json().map_err(|e| GuiError::Failed(e.to_string()))?;
+        if !status.is_success() {
+            let message = body["error"].as_str().map(str::to_string).unwrap_or_else(|| status.to_string());
+            return Err(GuiError::Failed(message));
+        }
+        Ok(body)
+    }
+
+    fn call(&self, method: reqwest::Method, path: &str) -> Result<Value, String> {
+        self.request(method, path).map_err(|e| match e {
+            GuiError::Unreachable => "The app stopped responding".to_string(),
+            GuiError::Failed(e) => e,
+        })
+    }
+}
+
+enum GuiError {
+    Unreachable,
+    Failed(String),
+}
+
+/// Hands the arguments to the running app through the single instance socket,
+/// returning `None` when the app isn't running.
+fn forward_to_app(args: &[&str]) -> Result<Option<Result<(), String>>, String> {
+    let Some(transport) = single_instance::default_transport(&get_data_dir()?) else {
+        return Ok(None);
+    };
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    match single_instance::send_args(transport.as_ref(), &args) {
+        Ok(result) => Ok(Some(result)),
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused) => Ok(None),
+        Err(e) => Err(format!("Failed to reach the app: {}", e)),
+    }
+}
+
+fn is_app_running() -> Result<bool, String> {
+    Ok(single_instance::default_transport(&get_data_dir()?)
+        .is_some_and(|transport| transport.connect().is_ok()))
+}
+
+fn find_time_box(conn: &Connection, key: &str) -> Result<TimeBox, String> {
+    db::find_time_box(conn, key)?.ok_or_else(|| format!("No time box {}", key))
+}
+
+fn time_box_name(conn: &Connection, time_box_id: &str) -> Result<String, String> {
+    Ok(db::find_time_box(conn, time_box_id)?.map_or_else(|| "Unknown".to_string(), |time_box| time_box.name))
+}
+
+/// Switches inside an immediate transaction, so a concurrent switch waits for
+/// this one instead of both reading the same running event.
+fn switch_in_database(conn: &mut Connection, key: &str) -> Result<String, String> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    let time_box = find_time_box(&tx, key)?;
+    let now = Utc::now();
+
+    let session_id = match cli_timer::load(&tx)? {
+        Some(running) if running.time_box_id == time_box.id => return Ok(format!("Already on {}", time_box.name)),
+        Some(running) => {
+            cli_timer::finish_event(&tx, &running, &now)?;
+            running.session_id
+        }
+        None => {
+            let session = Session {
+                id: uuid::Uuid::new_v4().to_string(),
+                start_datetime: db::format_datetime(&now),
+                end_datetime: None,
+                duration: Some(0),
+            };
+            db::insert_session(&tx, &session)?;
+            session.id
+        }
+    };
+    let running = RunningTimer { time_box_id: time_box.id, session_id, event_start: db::format_datetime(&now) };
+    cli_timer::save(&tx, Some(&running))?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(format!("Switched to {}", time_box.name))
+}
+
+fn end_in_database(conn: &mut Connection) -> Result<String, String> {
+    Ok(match cli_timer::end(conn)? {
+        Some(_) => "Session ended",
+        None => "No session running",
+    }
+    .to_string())
+}
+
+fn elapsed_since(start: Option<&str>) -> String {
+    let seconds = start
+        .and_then(db::parse_datetime)
+        .map_or(0, |start| (Utc::now() - start).num_seconds().max(0));
+    report::format_duration(seconds)
+}
+
+fn describe_running(time_box_name: &str, event_start: Option<&str>, session_start: Option<&str>) -> String {
+    format!(
+        "{} for {} (session {})",
+        time_box_name,
+        elapsed_since(event_start),
+        elapsed_since(session_start)
+    )
+}
+
+fn switch(key: &str) -> Result<String, String> {
+    if let Some(gui) = Gui::connect()? {
+        let path = format!("/switch/{}", percent_encoding::utf8_percent_encode(key, percent_encoding::NON_ALPHANUMERIC));
+        let state = gui.call(reqwest::Method::POST, &path)?;
+        return Ok(format!("Switched to {}", state["activeTimeBoxName"].as_str().unwrap_or(key)));
+    }
+    if let Some(result) = forward_to_app(&["--switch", key])? {
+        return result.map(|_| format!("Switched to {}", key));
+    }
+    switch_in_database(&mut open_database()?, key)
+}
+
+fn end() -> Result<String, String> {
+    if let Some(gui) = Gui::connect()? {
+        gui.call(reqwest::Method::POST, "/session/end")?;
+        return Ok("Session ended".to_string());
+    }
+    if let Some(result) = forward_to_app(&["--end-session"])? {
+        return result.map(|_| "Session ended".to_string());
+    }
+    end_in_database(&mut open_database()?)
+}
+
+fn status() -> Result<String, String> {
+    if let Some(gui) = Gui::connect()? {
+        let state = gui.call(reqwest::Method::GET, "/state")?;
+        return Ok(match state["activeTimeBoxName"].as_str() {
+            Some(name) => describe_running(name, state["eventStart"].as_str(), state["sessionStart"].as_str()),
+            None => "Not running".to_string(),
+        });
+    }
+    if is_app_running()? {
+        return Err("The app is running with its control API turned off, turn it on to see the timer".to_string());
+    }
+
+    let conn = open_database()?;
+    let Some(running) = cli_timer::load(&conn)? else {
+        return Ok("Not running".to_string());
+    };
+    let session_start: Option<String> = conn
+        .query_row("SELECT startDatetime FROM sessions WHERE id = ?1", params![running.session_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let name = time_box_name(&conn, &running.time_box_id)?;
+    Ok(describe_running(&name, Some(&running.event_start), session_start.as_deref()))
+}
+
+/// Reads `--today`, `--week`, `--month` or `--from`/`--to`; weeks start on
+/// Monday.
+fn parse_range(options: &Options, default: DateRange) -> Result<DateRange, String> {
+    let today = Local::now().date_naive();
+    if options.contains_key("today") {
+        return Ok(DateRange { from: Some(today), to: Some(today) });
+    }
+    if options.contains_key("week") {
+        let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+        return Ok(DateRange { from: Some(monday), to: Some(today) });
+    }
+    if options.contains_key("month") {
+        return Ok(DateRange { from: NaiveDate::from_ymd_opt(today.year(), today.month(), 1), to: Some(today) });
+    }
+    if options.contains_key("from") || options.contains_key("to") {
+        let value = |name: &str| options.get(name).cloned().flatten();
+        return DateRange::parse(value("from"), value("to"));
+    }
+    Ok(default)
+}
+
+fn report(options: &Options) -> Result<String, String> {
+    let today = Local::now().date_naive();
+    let range = parse_range(options, DateRange { from: Some(today), to: Some(today) })?;
+    let conn = open_database()?;
+    let data = ReportData::load(&conn, range)?;
+
+    let mut totals: HashMap<String, i64> = HashMap::new();
+    for (_, durations) in report::durations_by_date(&data) {
+        for (name, seconds) in durations {
+            *totals.entry(name).or_insert(0) += seconds;
+        }
+    }
+    let break_seconds = totals.remove(report::BREAK_NAME).unwrap_or(0);
+    let tracked: i64 = totals.values().sum();
+
+    let mut rows: Vec<(String, i64)> = data
+        .event_time_box_names()
+        .into_iter()
+        .filter_map(|name| totals.get(&name).map(|seconds| (name, *seconds)))
+        .filter(|(_, seconds)| *seconds > 0)
+        .collect();
+    rows.push(("Tracked".to_string(), tracked));
+    rows.push((report::BREAK_NAME.to_string(), break_seconds));
+
+    let width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+    let mut lines = vec![range.title()];
+    lines.extend(rows.iter().map(|(name, seconds)| format!("{:<width$}  {:>9}", name, report::format_duration(*seconds))));
+    let focus_intervals = data
+        .session_events
+        .iter()
+        .filter(|event| event.pomodoro_phase.as_deref() == Some("focus"))
+        .count();
+    if focus_intervals > 0 {
+        lines.push(format!("{} focus intervals completed", focus_intervals));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn export(options: &Options) -> Result<String, String> {
+    if !options.contains_key("csv") {
+        return Err("Pick a format, only --csv is supported".to_string());
+    }
+    let range = parse_range(options, DateRange::default())?;
+    let directory = PathBuf::from(options.get("dir").cloned().flatten().unwrap_or_else(|| ".".to_string()));
+    std::fs::create_dir_all(&directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+    let conn = open_database()?;
+    let data = ReportData::load(&conn, range)?;
+    Ok(report::write_csv_files(&data, &directory)?.join("\n"))
+}
+
+/// Splits `--name value` and `--flag` options from positional arguments.
+fn parse_options(args: &[String]) -> Result<(Vec<String>, Options), String> {
+    const WITH_VALUE: [&str; 3] = ["from", "to", "dir"];
+    const FLAGS: [&str; 4] = ["today", "week", "month", "csv"];
+    let mut positional = Vec::new();
+    let mut options = Options::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(name) if WITH_VALUE.contains(&name) => {
+                let value = args.next().ok_or_else(|| format!("--{} needs a value", name))?;
+                options.insert(name.to_string(), Some(value.clone()));
+            }
+            Some(name) if FLAGS.contains(&name) => {
+                options.insert(name.to_string(), None);
+            }
+            Some(name) => return Err(format!("Unknown option --{}\n\n{}", name, USAGE)),
+            None => positional.push(arg.clone()),
+        }
+    }
+    Ok((positional, options))
+}
+
+fn run(args: &[String]) -> Result<String, String> {
+    let (positional, options) = parse_options(args)?;
+    match positional.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["switch", key @ ..] if !key.is_empty() => switch(&key.join(" ")),
+        ["end"] => end(),
+        ["status"] => status(),
+        ["report"] => report(&options),
+        ["export"] => export(&options),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
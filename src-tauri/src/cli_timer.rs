@@ -0,0 +1,79 @@
+//! The event `clockblocks-cli` runs while the app is closed. It only gets a
+//! row once it ends, so until then it is kept in the `metadata` table the way
+//! the webview keeps its own in memory. The app ends it on launch, as the
+//! webview can't pick up a session it didn't start.
+
+use crate::db::{self, SessionEvent};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+const RUNNING_TIMER_KEY: &str = "cli_running_timer";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunningTimer {
+    pub time_box_id: String,
+    pub session_id: String,
+    pub event_start: String,
+}
+
+pub fn load(conn: &Connection) -> Result<Option<RunningTimer>, String> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM metadata WHERE name = ?1", params![RUNNING_TIMER_KEY], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    value
+        .map(|value| serde_json::from_str(&value).map_err(|e| format!("Invalid running timer: {}", e)))
+        .transpose()
+}
+
+pub fn save(conn: &Connection, running: Option<&RunningTimer>) -> Result<(), String> {
+    match running {
+        Some(running) => {
+            let value = serde_json::to_string(running).map_err(|e| e.to_string())?;
+            conn.execute(
+                "INSERT INTO metadata (name, value) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value",
+                params![RUNNING_TIMER_KEY, value],
+            )
+        }
+        None => conn.execute("DELETE FROM metadata WHERE name = ?1", params![RUNNING_TIMER_KEY]),
+    }
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// Writes the running event as ending now, which also updates its session's
+/// duration.
+pub fn finish_event(conn: &Connection, running: &RunningTimer, now: &DateTime<Utc>) -> Result<(), String> {
+    let start = db::parse_datetime(&running.event_start).ok_or("Invalid start of the running event")?;
+    db::upsert_session_event(conn, &SessionEvent {
+        id: uuid::Uuid::new_v4().to_string(),
+        time_box_id: running.time_box_id.clone(),
+        session_id: running.session_id.clone(),
+        start_datetime: running.event_start.clone(),
+        end_datetime: db::format_datetime(now),
+        seconds: (*now - start).num_seconds().max(0),
+        pomodoro_phase: None,
+        pomodoro_cycle: None,
+    })
+}
+
+/// Ends the running event and its session, returning the timer that was
+/// running.
+pub fn end(conn: &mut Connection) -> Result<Option<RunningTimer>, String> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    let Some(running) = load(&tx)? else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    finish_event(&tx, &running, &now)?;
+    tx.execute(
+        "UPDATE sessions SET endDatetime = ?1 WHERE id = ?2",
+        params![db::format_datetime(&now), running.session_id],
+    )
+    .map_err(|e| e.to_string())?;
+    save(&tx, None)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(Some(running))
+}
@@ -0,0 +1,342 @@
+//! Opt-in REST API on loopback to drive the timer from scripts, editor plugins
+//! and macro pads. Every request needs the generated bearer token:
+//!
+//! - `GET /state`
+//! - `POST /switch/{time box id or name}`
+//! - `POST /session/end`
+//! - `GET /summary?date=YYYY-MM-DD` (today by default)
+//! - `GET /events`, a Server-Sent Events stream of the events in `events.rs`.
+//!   Browsers can't set headers on an `EventSource`, so this route also takes
+//!   the token as `?access_token=`.
+
+use crate::db;
+use crate::events;
+use crate::report::{self, DateRange, ReportData};
+use crate::timer::{self, TimerState};
+use crate::AppState;
+use chrono::{Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tauri::Manager;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const CONTROL_API_FILE_NAME: &str = "control_api.json";
+const DEFAULT_CONTROL_API_PORT: u16 = 3012;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ControlApiSettings {
+    enabled: bool,
+    port: u16,
+    token: String,
+}
+
+impl Default for ControlApiSettings {
+    fn default() -> Self {
+        ControlApiSettings { enabled: false, port: DEFAULT_CONTROL_API_PORT, token: generate_token() }
+    }
+}
+
+/// The running server. Requests are checked against `token`, which can be
+/// replaced without restarting.
+pub struct ControlApiServer {
+    server: Arc<Server>,
+    token: Arc<Mutex<String>>,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlApiStatus {
+    pub enabled: bool,
+    pub running: bool,
+    pub url: String,
+    pub token: String,
+}
+
+fn generate_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn get_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(CONTROL_API_FILE_NAME)
+}
+
+fn load_settings(app_handle: &tauri::AppHandle) -> Result<ControlApiSettings, String> {
+    let path = get_settings_path(app_handle);
+    if !path.exists() {
+        // Saved right away so the token shown before enabling stays the same
+        let settings = ControlApiSettings::default();
+        save_settings(app_handle, &settings)?;
+        return Ok(settings);
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid control API settings: {}", e))
+}
+
+fn save_settings(app_handle: &tauri::AppHandle, settings: &ControlApiSettings) -> Result<(), String> {
+    let path = get_settings_path(app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn status(app_handle: &tauri::AppHandle, settings: &ControlApiSettings) -> Result<ControlApiStatus, String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let running = state.control_api.lock().map_err(|_| "Failed to lock control API state".to_string())?.is_some();
+    Ok(ControlApiStatus {
+        enabled: settings.enabled,
+        running,
+        url: format!("http://127.0.0.1:{}", settings.port),
+        token: settings.token.clone(),
+    })
+}
+
+/// Compares without returning early, so response times don't reveal how much
+/// of a guessed token was right.
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn parse_url(request: &Request) -> Option<url::Url> {
+    url::Url::parse(&format!("http://127.0.0.1{}", request.url())).ok()
+}
+
+fn is_event_stream(request: &Request) -> bool {
+    *request.method() == Method::Get && parse_url(request).is_some_and(|url| url.path().trim_end_matches('/') == "/events")
+}
+
+fn is_authorized(request: &Request, token: &Mutex<String>) -> bool {
+    let Ok(token) = token.lock() else {
+        return false;
+    };
+    let given = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .map(|given| given.trim().to_string())
+        .or_else(|| {
+            let url = parse_url(request).filter(|_| is_event_stream(request))?;
+            let given = url.query_pairs().find(|(key, _)| key == "access_token")?.1;
+            Some(given.into_owned())
+        });
+    given.is_some_and(|given| tokens_match(&token, &given))
+}
+
+fn json_response(status: u16, body: &Value) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+}
+
+fn error_response(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+/// The timer state with the running box's name, for clients that show it.
+fn state_json(app_handle: &tauri::AppHandle, state: &TimerState) -> Value {
+    let time_box_name = state
+        .active_time_box_id
+        .as_deref()
+        .and_then(|id| timer::find_time_box(app_handle, id).ok().flatten())
+        .map(|time_box| time_box.name);
+    json!({
+        "running": state.active_time_box_id.is_some(),
+        "activeTimeBoxId": state.active_time_box_id,
+        "activeTimeBoxName": time_box_name,
+        "sessionId": state.session_id,
+        "sessionStart": state.session_start,
+        "eventStart": state.event_start,
+    })
+}
+
+/// Seconds per box and Break on one day, like a row of SummaryByDate. The
+/// running event isn't in the database yet, so its time so far is moved from
+/// Break to its box.
+fn summary_json(app_handle: &tauri::AppHandle, date: NaiveDate) -> Result<Value, String> {
+    let conn = db::open_connection(app_handle)?;
+    let data = ReportData::load(&conn, DateRange { from: Some(date), to: Some(date) })?;
+    let mut durations: HashMap<String, i64> = report::durations_by_date(&data)
+        .into_iter()
+        .find(|(day, _)| *day == date)
+        .map(|(_, durations)| durations)
+        .unwrap_or_default();
+
+    let state = timer::current_state(app_handle);
+    let is_running_today = state
+        .session_id
+        .as_ref()
+        .is_some_and(|id| data.sessions.iter().any(|session| session.id == *id));
+    if let (true, Some(time_box_id), Some(event_start)) =
+        (is_running_today, &state.active_time_box_id, state.event_start.as_deref().and_then(db::parse_datetime))
+    {
+        let elapsed = (Utc::now() - event_start).num_seconds().max(0);
+        *durations.entry(data.time_box_name(time_box_id)).or_insert(0) += elapsed;
+        *durations.entry(report::BREAK_NAME.to_string()).or_insert(0) -= elapsed;
+    }
+
+    let break_seconds = durations.remove(report::BREAK_NAME).unwrap_or(0).max(0);
+    let tracked: i64 = durations.values().sum();
+    Ok(json!({
+        "date": date.format("%Y-%m-%d").to_string(),
+        "timeBoxes": durations,
+        "trackedSeconds": tracked,
+        "breakSeconds": break_seconds,
+    }))
+}
+
+fn percent_decode(value: &str) -> String {
+    percent_encoding::percent_decode_str(value).decode_utf8_lossy().into_owned()
+}
+
+fn handle_request(app_handle: &tauri::AppHandle, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+    let Some(url) = parse_url(request) else {
+        return error_response(400, "Invalid URL");
+    };
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| segments.map(percent_decode).collect())
+        .unwrap_or_default();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).filter(|segment| !segment.is_empty()).collect();
+
+    let result = match (request.method(), segments.as_slice()) {
+        (Method::Get, ["state"]) => Ok(state_json(app_handle, &timer::current_state(app_handle))),
+        (Method::Post, ["switch", key]) => match timer::find_time_box(app_handle, key) {
+            Ok(Some(time_box)) => timer::switch_to(app_handle, &time_box.id).map(|state| state_json(app_handle, &state)),
+            Ok(None) => return error_response(404, &format!("No time box {}", key)),
+            Err(e) => Err(e),
+        },
+        (Method::Post, ["session", "end"]) => timer::end_session(app_handle).map(|state| state_json(app_handle, &state)),
+        (Method::Get, ["summary"]) => {
+            let date = url.query_pairs().find(|(key, _)| key == "date").map(|(_, value)| value.into_owned());
+            match date.map(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d")) {
+                None => summary_json(app_handle, Local::now().date_naive()),
+                Some(Ok(date)) => summary_json(app_handle, date),
+                Some(Err(_)) => return error_response(400, "Expected date=YYYY-MM-DD"),
+            }
+        }
+        (_, ["state"] | ["switch", _] | ["session", "end"] | ["summary"] | ["events"]) => return error_response(405, "Method not allowed"),
+        _ => return error_response(404, "Not found"),
+    };
+
+    match result {
+        Ok(body) => json_response(200, &body),
+        Err(e) => error_response(503, &e),
+    }
+}
+
+fn start_server(app_handle: &tauri::AppHandle, settings: &ControlApiSettings) -> Result<(), String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let mut control_api = state.control_api.lock().map_err(|_| "Failed to lock control API state".to_string())?;
+    if control_api.is_some() {
+        return Ok(());
+    }
+
+    let server = Arc::new(
+        Server::http(("127.0.0.1", settings.port)).map_err(|e| format!("Failed to start control API: {}", e))?,
+    );
+    let server_clone = server.clone();
+    let handle = app_handle.clone();
+    let token = Arc::new(Mutex::new(settings.token.clone()));
+    let token_clone = token.clone();
+    let thread = thread::spawn(move || {
+        for request in server_clone.incoming_requests() {
+            if !is_authorized(&request, &token_clone) {
+                let _ = request.respond(error_response(401, "Missing or wrong bearer token"));
+            } else if is_event_stream(&request) {
+                let state = state_json(&handle, &timer::current_state(&handle));
+                if let Err(e) = events::serve_stream(&handle, request, state) {
+                    println!("Failed to open event stream: {}", e);
+                }
+            } else {
+                // Commands wait for the webview, which shouldn't hold up other clients
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    let response = handle_request(&handle, &request);
+                    let _ = request.respond(response);
+                });
+            }
+        }
+    });
+
+    println!("Control API listening on 127.0.0.1:{}", settings.port);
+    *control_api = Some(ControlApiServer { server, token, thread });
+    Ok(())
+}
+
+/// Stops the server and waits for its thread, so the port is free again.
+fn stop_server(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let running = state.control_api.lock().map_err(|_| "Failed to lock control API state".to_string())?.take();
+    if let Some(running) = running {
+        running.server.unblock();
+        running.thread.join().map_err(|_| "Control API thread panicked".to_string())?;
+        events::close_streams(app_handle);
+    }
+    Ok(())
+}
+
+/// Starts the API on launch when it was left enabled.
+pub fn start_if_enabled(app_handle: &tauri::AppHandle) {
+    match load_settings(app_handle) {
+        Ok(settings) if settings.enabled => {
+            if let Err(e) = start_server(app_handle, &settings) {
+                println!("{}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => println!("{}", e),
+    }
+}
+
+#[tauri::command]
+pub fn get_control_api(app_handle: tauri::AppHandle) -> Result<ControlApiStatus, String> {
+    status(&app_handle, &load_settings(&app_handle)?)
+}
+
+#[tauri::command]
+pub async fn enable_control_api(app_handle: tauri::AppHandle, port: Option<u16>) -> Result<ControlApiStatus, String> {
+    let mut settings = load_settings(&app_handle)?;
+    if let Some(port) = port.filter(|port| *port != settings.port) {
+        stop_server(&app_handle)?;
+        settings.port = port;
+    }
+    start_server(&app_handle, &settings)?;
+    settings.enabled = true;
+    save_settings(&app_handle, &settings)?;
+    status(&app_handle, &settings)
+}
+
+#[tauri::command]
+pub async fn disable_control_api(app_handle: tauri::AppHandle) -> Result<ControlApiStatus, String> {
+    stop_server(&app_handle)?;
+    let mut settings = load_settings(&app_handle)?;
+    settings.enabled = false;
+    save_settings(&app_handle, &settings)?;
+    status(&app_handle, &settings)
+}
+
+/// Replaces the token, locking out every client that has the old one.
+#[tauri::command]
+pub fn regenerate_control_api_token(app_handle: tauri::AppHandle) -> Result<ControlApiStatus, String> {
+    let mut settings = load_settings(&app_handle)?;
+    settings.token = generate_token();
+    save_settings(&app_handle, &settings)?;
+
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let control_api = state.control_api.lock().map_err(|_| "Failed to lock control API state".to_string())?;
+    if let Some(running) = control_api.as_ref() {
+        *running.token.lock().map_err(|_| "Failed to lock control API token".to_string())? = settings.token.clone();
+    }
+    drop(control_api);
+    status(&app_handle, &settings)
+}
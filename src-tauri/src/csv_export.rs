@@ -0,0 +1,32 @@
+use crate::db;
+use crate::report::{self, DateRange, ReportData};
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+
+/// Exports the four sync tables, and any Pomodoro intervals, as CSV files.
+/// Without a `directory` the user is asked to pick one; returns `None` if they
+/// cancel, otherwise the written files.
+#[tauri::command]
+pub async fn export_csv(
+    app_handle: tauri::AppHandle,
+    directory: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<Vec<String>>, String> {
+    let range = DateRange::parse(from, to)?;
+
+    let directory = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => match FileDialogBuilder::new().set_title("Export CSV to folder").pick_folder() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        },
+    };
+
+    let conn = db::open_connection(&app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    let written = report::write_csv_files(&data, &directory)?;
+
+    println!("Exported {} CSV files to {}", written.len(), directory.display());
+    Ok(Some(written))
+}
@@ -0,0 +1,331 @@
+use crate::paths;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tauri::Manager;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeBox {
+    pub id: String,
+    pub name: String,
+    pub is_hidden: bool,
+    pub is_deleted: bool,
+    pub colour: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: String,
+    pub start_datetime: String,
+    pub end_datetime: Option<String>,
+    pub duration: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEvent {
+    pub id: String,
+    pub time_box_id: String,
+    pub session_id: String,
+    pub start_datetime: String,
+    pub end_datetime: String,
+    pub seconds: i64,
+    /// Set on the event that completed a Pomodoro interval
+    #[serde(default)]
+    pub pomodoro_phase: Option<String>,
+    #[serde(default)]
+    pub pomodoro_cycle: Option<i64>,
+}
+
+/// Mirrors the file name chosen in `dbInteraction.ts`.
+pub fn db_file_name() -> &'static str {
+    if cfg!(debug_assertions) {
+        "clockblocks_dev.db"
+    } else {
+        "clockblocks.db"
+    }
+}
+
+/// The SQL plugin resolves `sqlite:` paths against the app config directory,
+/// so the backend has to look in the same place as the frontend.
+pub fn db_path(identifier: &str) -> Result<PathBuf, String> {
+    Ok(paths::config_dir(identifier)?.join(db_file_name()))
+}
+
+pub fn get_db_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    db_path(&app_handle.config().tauri.bundle.identifier).expect("Failed to get app config directory")
+}
+
+pub fn open_connection(app_handle: &tauri::AppHandle) -> Result<Connection, String> {
+    open_connection_at(&get_db_path(app_handle))
+}
+
+pub fn open_connection_at(path: &PathBuf) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(|e| format!("Failed to open database: {}", e))?;
+    // The webview holds its own connection to the same file
+    conn.busy_timeout(std::time::Duration::from_secs(5)).map_err(|e| e.to_string())?;
+    migrate(&conn)?;
+    Ok(conn)
+}
+
+fn session_event_columns(conn: &Connection) -> Result<Vec<String>, String> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('sessionEvents')").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+/// Adds the columns of later versions to tables the webview created, as
+/// `migrateSchema` in `dbInteraction.ts` does. Whichever opens the file first
+/// migrates it.
+fn migrate(conn: &Connection) -> Result<(), String> {
+    let columns = session_event_columns(conn)?;
+    // Tables that don't exist yet are created with every column
+    if columns.is_empty() || columns.iter().any(|column| column == "pomodoroPhase") {
+        return Ok(());
+    }
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    if !session_event_columns(&tx)?.iter().any(|column| column == "pomodoroPhase") {
+        tx.execute_batch(
+            "ALTER TABLE sessionEvents ADD COLUMN pomodoroPhase TEXT;
+             ALTER TABLE sessionEvents ADD COLUMN pomodoroCycle INTEGER;",
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+/// The tables `createTables` in `dbInteraction.ts` makes, for databases the
+/// webview never opened, such as in-memory ones in tests.
+pub fn create_tables(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS timeBoxes (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            isHidden BOOLEAN DEFAULT FALSE,
+            isDeleted BOOLEAN DEFAULT FALSE,
+            colour TEXT NOT NULL DEFAULT '#D82726'
+        );
+        CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            startDatetime TEXT NOT NULL,
+            endDatetime TEXT,
+            duration INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS sessionEvents (
+            id TEXT PRIMARY KEY,
+            timeBoxId TEXT NOT NULL,
+            sessionId TEXT NOT NULL,
+            startDatetime TEXT NOT NULL,
+            endDatetime TEXT NOT NULL,
+            seconds INTEGER NOT NULL,
+            pomodoroPhase TEXT,
+            pomodoroCycle INTEGER,
+            FOREIGN KEY (timeBoxId) REFERENCES timeBoxes(id),
+            FOREIGN KEY (sessionId) REFERENCES sessions(id)
+        );
+        CREATE TABLE IF NOT EXISTS metadata (
+            name TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    )
+    .map_err(|e| e.to_string())
+}
+
+pub fn get_time_boxes(conn: &Connection) -> Result<Vec<TimeBox>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, name, isHidden, isDeleted, colour FROM timeBoxes")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(TimeBox {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                is_hidden: row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+                is_deleted: row.get::<_, Option<bool>>(3)?.unwrap_or(false),
+                colour: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+/// Finds a box that isn't deleted by id, or else by case-insensitive name.
+pub fn find_time_box(conn: &Connection, key: &str) -> Result<Option<TimeBox>, String> {
+    let time_boxes: Vec<TimeBox> = get_time_boxes(conn)?.into_iter().filter(|time_box| !time_box.is_deleted).collect();
+    Ok(time_boxes
+        .iter()
+        .find(|time_box| time_box.id == key)
+        .or_else(|| time_boxes.iter().find(|time_box| time_box.name.eq_ignore_ascii_case(key.trim())))
+        .cloned())
+}
+
+pub fn get_sessions(conn: &Connection) -> Result<Vec<Session>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, startDatetime, endDatetime, duration FROM sessions")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(Session {
+                id: row.get(0)?,
+                start_datetime: row.get(1)?,
+                end_datetime: row.get(2)?,
+                duration: row.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+pub fn get_session_events(conn: &Connection) -> Result<Vec<SessionEvent>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, timeBoxId, sessionId, startDatetime, endDatetime, seconds, pomodoroPhase, pomodoroCycle FROM sessionEvents")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(SessionEvent {
+                id: row.get(0)?,
+                time_box_id: row.get(1)?,
+                session_id: row.get(2)?,
+                start_datetime: row.get(3)?,
+                end_datetime: row.get(4)?,
+                seconds: row.get(5)?,
+                pomodoro_phase: row.get(6)?,
+                pomodoro_cycle: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+pub fn get_session_event(conn: &Connection, id: &str) -> Result<Option<SessionEvent>, String> {
+    conn.query_row(
+        "SELECT id, timeBoxId, sessionId, startDatetime, endDatetime, seconds, pomodoroPhase, pomodoroCycle FROM sessionEvents WHERE id = ?1",
+        params![id],
+        |row| {
+            Ok(SessionEvent {
+                id: row.get(0)?,
+                time_box_id: row.get(1)?,
+                session_id: row.get(2)?,
+                start_datetime: row.get(3)?,
+                end_datetime: row.get(4)?,
+                seconds: row.get(5)?,
+                pomodoro_phase: row.get(6)?,
+                pomodoro_cycle: row.get(7)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn insert_time_box(conn: &Connection, time_box: &TimeBox) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO timeBoxes (id, name, isHidden, isDeleted, colour) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![time_box.id, time_box.name, time_box.is_hidden, time_box.is_deleted, time_box.colour],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+pub fn insert_session(conn: &Connection, session: &Session) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO sessions (id, startDatetime, endDatetime, duration) VALUES (?1, ?2, ?3, ?4)",
+        params![session.id, session.start_datetime, session.end_datetime, session.duration],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+pub fn insert_session_event(conn: &Connection, event: &SessionEvent) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO sessionEvents (id, timeBoxId, sessionId, startDatetime, endDatetime, seconds, pomodoroPhase, pomodoroCycle)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            event.id,
+            event.time_box_id,
+            event.session_id,
+            event.start_datetime,
+            event.end_datetime,
+            event.seconds,
+            event.pomodoro_phase,
+            event.pomodoro_cycle
+        ],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// Keeps the Pomodoro tag of an existing event when `event` has none, as edits
+/// rebuild events without it.
+pub fn upsert_session_event(conn: &Connection, event: &SessionEvent) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO sessionEvents (id, timeBoxId, sessionId, startDatetime, endDatetime, seconds, pomodoroPhase, pomodoroCycle)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (id) DO UPDATE SET
+           timeBoxId = EXCLUDED.timeBoxId,
+           startDatetime = EXCLUDED.startDatetime,
+           endDatetime = EXCLUDED.endDatetime,
+           seconds = EXCLUDED.seconds,
+           pomodoroPhase = COALESCE(EXCLUDED.pomodoroPhase, pomodoroPhase),
+           pomodoroCycle = COALESCE(EXCLUDED.pomodoroCycle, pomodoroCycle)",
+        params![
+            event.id,
+            event.time_box_id,
+            event.session_id,
+            event.start_datetime,
+            event.end_datetime,
+            event.seconds,
+            event.pomodoro_phase,
+            event.pomodoro_cycle
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    update_session_duration(conn, &event.session_id)
+}
+
+/// Same calculation as `updateSessionDuration` in `dbInteraction.ts`: the sum of
+/// the event lengths, not the wall-clock span of the session.
+pub fn update_session_duration(conn: &Connection, session_id: &str) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("SELECT startDatetime, endDatetime FROM sessionEvents WHERE sessionId = ?1")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![session_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut duration = 0;
+    for row in rows {
+        let (start, end) = row.map_err(|e| e.to_string())?;
+        if let (Some(start), Some(end)) = (parse_datetime(&start), parse_datetime(&end)) {
+            duration += (end - start).num_seconds();
+        }
+    }
+
+    conn.execute(
+        "UPDATE sessions SET duration = ?1 WHERE id = ?2",
+        params![duration, session_id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Timestamps are written by the frontend with `Date.toISOString()`.
+pub fn parse_datetime(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    let value = value.trim();
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.with_timezone(&chrono::Utc));
+    }
+    // Spreadsheets and hand edits tend to drop the `T` and the offset
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|naive| naive.and_utc())
+}
+
+pub fn format_datetime(datetime: &chrono::DateTime<chrono::Utc>) -> String {
+    datetime.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
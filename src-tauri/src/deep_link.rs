@@ -0,0 +1,221 @@
+//! `clockblocks://` links for bookmarks, launchers and shortcuts:
+//!
+//! - `clockblocks://switch/{time box id or name}`
+//! - `clockblocks://end`
+//! - `clockblocks://open/{timer|chart|settings}`, where `stats` also opens the
+//!   chart and `?range=week|month|all` picks its grouping
+//!
+//! Parsing is kept apart from handling so it can be tested without the OS
+//! registration or a running timer.
+
+use crate::timer;
+use serde::Serialize;
+use tauri::Manager;
+
+pub const SCHEME: &str = "clockblocks";
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Page {
+    Timer,
+    Chart,
+    Settings,
+}
+
+/// The grouping of the chart page, named as in `ChartPage.tsx`.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ChartRange {
+    Week,
+    Month,
+    All,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeepLink {
+    /// A time box id or name, looked up when the link is handled
+    Switch(String),
+    EndSession,
+    Open { page: Page, range: Option<ChartRange> },
+}
+
+/// Payload of the `navigate` event that moves the webview to a page.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Navigate {
+    page: Page,
+    range: Option<ChartRange>,
+}
+
+pub fn is_deep_link(value: &str) -> bool {
+    value
+        .get(..SCHEME.len() + 1)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", SCHEME)))
+}
+
+fn parse_page(name: &str) -> Result<Page, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "timer" => Ok(Page::Timer),
+        "chart" | "stats" => Ok(Page::Chart),
+        "settings" => Ok(Page::Settings),
+        _ => Err(format!("Unknown page {}", name)),
+    }
+}
+
+fn parse_range(name: &str) -> Result<ChartRange, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "week" => Ok(ChartRange::Week),
+        "month" => Ok(ChartRange::Month),
+        "all" => Ok(ChartRange::All),
+        _ => Err(format!("Unknown range {}, expected week, month or all", name)),
+    }
+}
+
+fn validate_time_box_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("The link names no time box".to_string());
+    }
+    if key.chars().any(char::is_control) {
+        return Err("Time box names can't contain control characters".to_string());
+    }
+    Ok(key.to_string())
+}
+
+pub fn parse(link: &str) -> Result<DeepLink, String> {
+    let url = url::Url::parse(link.trim()).map_err(|e| format!("Invalid link {}: {}", link, e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("Not a {}:// link: {}", SCHEME, link));
+    }
+
+    // `clockblocks://switch/Code` has the action as its host, while
+    // `clockblocks:switch/Code` has it as the first path segment
+    let mut segments: Vec<String> = url.host_str().into_iter().map(str::to_string).collect();
+    segments.extend(url.path().split('/').map(str::to_string));
+    let segments: Vec<String> = segments
+        .iter()
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let range = url.query_pairs().find(|(key, _)| key == "range").map(|(_, value)| value.into_owned());
+
+    let Some((action, arguments)) = segments.split_first() else {
+        return Err(format!("The link has no action: {}", link));
+    };
+    match (action.to_ascii_lowercase().as_str(), arguments) {
+        ("switch", [key]) => Ok(DeepLink::Switch(validate_time_box_key(key)?)),
+        ("switch", []) => Err("The link names no time box".to_string()),
+        ("switch", _) => Err("Time box names in links can't contain /, use %2F".to_string()),
+        ("end", []) => Ok(DeepLink::EndSession),
+        ("open", [page]) => {
+            let page = parse_page(page)?;
+            let range = range.as_deref().map(parse_range).transpose()?;
+            if range.is_some() && page != Page::Chart {
+                return Err("Only the chart page takes a range".to_string());
+            }
+            Ok(DeepLink::Open { page, range })
+        }
+        ("open", []) => Err("The link names no page to open".to_string()),
+        ("end" | "open", _) => Err(format!("Too many parts in {}", link)),
+        _ => Err(format!("Unknown action {}", action)),
+    }
+}
+
+/// Runs a parsed link. Switching waits for the webview to confirm, so this
+/// should be called off the main thread.
+pub fn handle(app_handle: &tauri::AppHandle, link: &DeepLink) -> Result<(), String> {
+    match link {
+        DeepLink::Switch(key) => {
+            let time_box = timer::find_time_box(app_handle, key)?.ok_or_else(|| format!("No time box {}", key))?;
+            timer::switch_to(app_handle, &time_box.id)?;
+        }
+        DeepLink::EndSession => {
+            timer::end_session(app_handle)?;
+        }
+        DeepLink::Open { page, range } => {
+            crate::show_main_window(app_handle);
+            app_handle
+                .emit_all("navigate", Navigate { page: *page, range: *range })
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_switch_by_name_or_id() {
+        assert_eq!(parse("clockblocks://switch/Code"), Ok(DeepLink::Switch("Code".to_string())));
+        assert_eq!(
+            parse("clockblocks://switch/0b7c2a54-1c1e-4f0e-9d7e-3f3c1a6b9e21"),
+            Ok(DeepLink::Switch("0b7c2a54-1c1e-4f0e-9d7e-3f3c1a6b9e21".to_string()))
+        );
+    }
+
+    #[test]
+    fn decodes_percent_encoded_names() {
+        assert_eq!(parse("clockblocks://switch/Deep%20Work"), Ok(DeepLink::Switch("Deep Work".to_string())));
+        assert_eq!(parse("clockblocks://switch/R%26D%2FOps"), Ok(DeepLink::Switch("R&D/Ops".to_string())));
+        assert_eq!(parse("clockblocks://switch/Caf%C3%A9"), Ok(DeepLink::Switch("Café".to_string())));
+    }
+
+    #[test]
+    fn accepts_links_without_slashes_and_trailing_slashes() {
+        assert_eq!(parse("clockblocks:switch/Code"), Ok(DeepLink::Switch("Code".to_string())));
+        assert_eq!(parse("clockblocks://switch/Code/"), Ok(DeepLink::Switch("Code".to_string())));
+        assert_eq!(parse("clockblocks://end/"), Ok(DeepLink::EndSession));
+    }
+
+    #[test]
+    fn actions_and_scheme_are_case_insensitive() {
+        assert_eq!(parse("ClockBlocks://SWITCH/Code"), Ok(DeepLink::Switch("Code".to_string())));
+        assert_eq!(parse("clockblocks://End"), Ok(DeepLink::EndSession));
+        assert!(is_deep_link("CLOCKBLOCKS://end"));
+        assert!(!is_deep_link("--switch"));
+    }
+
+    #[test]
+    fn rejects_switch_without_a_valid_time_box() {
+        assert!(parse("clockblocks://switch").is_err());
+        assert!(parse("clockblocks://switch/%20%20").is_err());
+        assert!(parse("clockblocks://switch/Code/Read").is_err());
+        assert!(parse("clockblocks://switch/Co%0Ade").is_err());
+    }
+
+    #[test]
+    fn parses_end() {
+        assert_eq!(parse("clockblocks://end"), Ok(DeepLink::EndSession));
+        assert!(parse("clockblocks://end/now").is_err());
+    }
+
+    #[test]
+    fn parses_open_with_range() {
+        assert_eq!(
+            parse("clockblocks://open/stats?range=week"),
+            Ok(DeepLink::Open { page: Page::Chart, range: Some(ChartRange::Week) })
+        );
+        assert_eq!(
+            parse("clockblocks://open/chart?range=ALL"),
+            Ok(DeepLink::Open { page: Page::Chart, range: Some(ChartRange::All) })
+        );
+        assert_eq!(parse("clockblocks://open/settings"), Ok(DeepLink::Open { page: Page::Settings, range: None }));
+    }
+
+    #[test]
+    fn rejects_bad_open_links() {
+        assert!(parse("clockblocks://open").is_err());
+        assert!(parse("clockblocks://open/reports").is_err());
+        assert!(parse("clockblocks://open/stats?range=year").is_err());
+        assert!(parse("clockblocks://open/timer?range=week").is_err());
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_unknown_actions() {
+        assert!(parse("https://switch/Code").is_err());
+        assert!(parse("clockblocks://").is_err());
+        assert!(parse("clockblocks://pause").is_err());
+        assert!(parse("not a link").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::profiles::resolve_profile_dir;
+use crate::{create_sheet_if_not_exists, get_valid_auth_token, save_sheet_id};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+
+/// Tabs the sync writes to, in the order they are created in a new spreadsheet.
+pub const SHEET_TAB_NAMES: [&str; 4] = ["SummaryByDate", "SummaryBySession", "DetailsSessions", "DetailsSessionEvents"];
+
+const SPREADSHEET_MIME_TYPE: &str = "application/vnd.google-apps.spreadsheet";
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DriveFile {
+    id: String,
+    name: String,
+    modified_time: Option<String>,
+    web_view_link: Option<String>,
+}
+
+/// Escapes a value for use inside a quoted Drive query string.
+fn escape_query_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+async fn list_drive_files(
+    app_handle: &tauri::AppHandle,
+    mime_type: &str,
+    name_contains: Option<String>,
+    parent_id: Option<String>,
+    profile: Option<&str>,
+) -> Result<Vec<DriveFile>, String> {
+    let auth_token = get_valid_auth_token(app_handle, profile).await?;
+
+    let mut query = format!("mimeType = '{}' and trashed = false", mime_type);
+    if let Some(name) = name_contains.filter(|name| !name.trim().is_empty()) {
+        query.push_str(&format!(" and name contains '{}'", escape_query_value(name.trim())));
+    }
+    if let Some(parent_id) = parent_id {
+        query.push_str(&format!(" and '{}' in parents", escape_query_value(&parent_id)));
+    }
+
+    let client = Client::new();
+    let mut files = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut params = vec![
+            ("q", query.clone()),
+            ("fields", "nextPageToken, files(id, name, modifiedTime, webViewLink)".to_string()),
+            ("orderBy", "modifiedTime desc".to_string()),
+            ("pageSize", "100".to_string()),
+            // Include spreadsheets in shared drives so teams can sync into a shared folder
+            ("supportsAllDrives", "true".to_string()),
+            ("includeItemsFromAllDrives", "true".to_string()),
+        ];
+        if let Some(token) = &page_token {
+            params.push(("pageToken", token.clone()));
+        }
+
+        let response = client
+            .get("https://www.googleapis.com/drive/v3/files")
+            .bearer_auth(&auth_token.access_token)
+            .query(&params)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to list Drive files: {}", e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to list Drive files: {} - {}", status, error_body));
+        }
+
+        let body: Value = response.json().await.map_err(|e| e.to_string())?;
+        for file in body["files"].as_array().cloned().unwrap_or_default() {
+            files.push(DriveFile {
+                id: file["id"].as_str().unwrap_or_default().to_string(),
+                name: file["name"].as_str().unwrap_or_default().to_string(),
+                modified_time: file["modifiedTime"].as_str().map(String::from),
+                web_view_link: file["webViewLink"].as_str().map(String::from),
+            });
+        }
+
+        page_token = body["nextPageToken"].as_str().map(String::from);
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(files)
+}
+
+/// With the `drive.file` scope this only returns spreadsheets the app created or
+/// that were opened with it, not everything in the user's Drive.
+#[tauri::command]
+pub async fn list_spreadsheets(
+    app_handle: tauri::AppHandle,
+    name_contains: Option<String>,
+    profile: Option<String>,
+) -> Result<Vec<DriveFile>, String> {
+    list_drive_files(&app_handle, SPREADSHEET_MIME_TYPE, name_contains, None, profile.as_deref()).await
+}
+
+#[tauri::command]
+pub async fn list_drive_folders(
+    app_handle: tauri::AppHandle,
+    parent_id: Option<String>,
+    profile: Option<String>,
+) -> Result<Vec<DriveFile>, String> {
+    list_drive_files(&app_handle, FOLDER_MIME_TYPE, None, parent_id, profile.as_deref()).await
+}
+
+/// Makes an existing spreadsheet the sync target. Missing tabs are added so the
+/// next sync can write to it; tabs that already exist are left untouched.
+#[tauri::command]
+pub async fn select_spreadsheet(app_handle: tauri::AppHandle, sheet_id: String, profile: Option<String>) -> Result<String, String> {
+    let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
+    let client = Client::new();
+
+    let response = client
+        .get(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}?fields=properties(title),sheets(properties(title))",
+            sheet_id
+        ))
+        .bearer_auth(&auth_token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to open spreadsheet: {}", e))?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::FORBIDDEN {
+        // `drive.file` hides spreadsheets made outside the app
+        return Err(format!(
+            "Spreadsheet {} is not accessible. ClockBlocks can only use spreadsheets it created or that were opened with it",
+            sheet_id
+        ));
+    }
+    if !status.is_success() {
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(format!("Spreadsheet {} is not accessible: {} - {}", sheet_id, status, error_body));
+    }
+
+    let spreadsheet: Value = response.json().await.map_err(|e| e.to_string())?;
+    let existing_tabs: Vec<String> = spreadsheet["sheets"].as_array()
+        .map(|sheets| sheets.iter()
+            .filter_map(|sheet| sheet["properties"]["title"].as_str().map(String::from))
+            .collect())
+        .unwrap_or_default();
+
+    for tab_name in SHEET_TAB_NAMES.iter().filter(|name| !existing_tabs.iter().any(|tab| tab == *name)) {
+        create_sheet_if_not_exists(app_handle.clone(), sheet_id.clone(), tab_name.to_string(), profile.clone()).await?;
+        println!("Added missing sheet {} to {}", tab_name, sheet_id);
+    }
+
+    save_sheet_id(app_handle, sheet_id.clone(), profile).await?;
+
+    Ok(spreadsheet["properties"]["title"].as_str().unwrap_or_default().to_string())
+}
+
+/// Moves a file out of the Drive root into the given folder.
+pub async fn move_file_to_folder(client: &Client, access_token: &str, file_id: &str, folder_id: &str) -> Result<(), String> {
+    let response = client
+        .patch(&format!("https://www.googleapis.com/drive/v3/files/{}", file_id))
+        .bearer_auth(access_token)
+        .query(&[
+            ("addParents", folder_id),
+            ("removeParents", "root"),
+            ("supportsAllDrives", "true"),
+        ])
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to move file to folder: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to move file to folder: {} - {}", status, error_body));
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn save_sheet_folder_id(
+    app_handle: tauri::AppHandle,
+    folder_id: Option<String>,
+    profile: Option<String>,
+) -> Result<(), String> {
+    let profile_dir = resolve_profile_dir(&app_handle, profile.as_deref())?;
+    let path = profile_dir.join("sheet_folder_id.txt");
+    match folder_id {
+        Some(folder_id) => {
+            fs::create_dir_all(&profile_dir).map_err(|e| e.to_string())?;
+            fs::write(path, folder_id).map_err(|e| e.to_string())
+        }
+        None if path.exists() => fs::remove_file(path).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn load_sheet_folder_id(app_handle: tauri::AppHandle, profile: Option<String>) -> Result<Option<String>, String> {
+    let path = resolve_profile_dir(&app_handle, profile.as_deref())?.join("sheet_folder_id.txt");
+    if path.exists() {
+        fs::read_to_string(path).map(Some).map_err(|e| e.to_string())
+    } else {
+        Ok(None)
+    }
+}
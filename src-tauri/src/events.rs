@@ -0,0 +1,119 @@
+//! Changes to the timer and the results of syncs. Each one is emitted to the
+//! webview as a Tauri event, queued for the webhooks, and written with the same
+//! name and payload to the Server-Sent Events streams of the control API.
+
+use crate::AppState;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tauri::Manager;
+use tiny_http::Request;
+
+pub const TIME_BOX_SWITCHED: &str = "time-box-switched";
+pub const SESSION_STARTED: &str = "session-started";
+pub const SESSION_ENDED: &str = "session-ended";
+pub const SYNC_FINISHED: &str = "sync-finished";
+/// Sent once when a stream opens, with the same body as `GET /state`
+pub const TIMER_STATE: &str = "timer-state";
+
+/// A comment line is sent this often, so proxies keep the stream open and a
+/// client that went away is noticed on the next write
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeBoxSwitched {
+    pub time_box_id: String,
+    /// `None` when the switch started the session
+    pub previous_time_box_id: Option<String>,
+    pub session_id: Option<String>,
+    pub at: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionChanged {
+    pub session_id: Option<String>,
+    pub time_box_id: Option<String>,
+    pub at: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncFinished {
+    pub sheet_id: Option<String>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub at: String,
+}
+
+fn format_frame(name: &str, data: &Value) -> String {
+    // serde_json writes no newlines, so the payload fits on one data line
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+/// Emits `payload` to the webview and every open event stream.
+pub fn publish<S: Serialize + Clone>(app_handle: &tauri::AppHandle, name: &str, payload: S) {
+    if let Err(e) = app_handle.emit_all(name, payload.clone()) {
+        println!("Failed to emit {}: {}", name, e);
+    }
+
+    let data = match serde_json::to_value(&payload) {
+        Ok(data) => data,
+        Err(e) => return println!("Failed to serialize {}: {}", name, e),
+    };
+    crate::webhooks::notify(app_handle, name, &data);
+
+    let frame = format_frame(name, &data);
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    if let Ok(mut streams) = state.event_streams.lock() {
+        // Streams whose client disconnected have dropped their receiver
+        streams.retain(|stream| stream.send(frame.clone()).is_ok());
+    };
+}
+
+fn subscribe(app_handle: &tauri::AppHandle) -> Result<Receiver<String>, String> {
+    let (sender, receiver) = mpsc::channel();
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    state.event_streams.lock().map_err(|_| "Failed to lock event streams".to_string())?.push(sender);
+    Ok(receiver)
+}
+
+/// Ends every open stream, used when the control API is turned off.
+pub fn close_streams(app_handle: &tauri::AppHandle) {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    if let Ok(mut streams) = state.event_streams.lock() {
+        streams.clear();
+    };
+}
+
+/// Answers `request` with an event stream on its own thread, starting with a
+/// `timer-state` event holding `initial_state`. The response is written to the
+/// socket directly, as tiny_http would buffer a streamed body.
+pub fn serve_stream(app_handle: &tauri::AppHandle, request: Request, initial_state: Value) -> Result<(), String> {
+    let receiver = subscribe(app_handle)?;
+    thread::spawn(move || {
+        let mut writer = request.into_writer();
+        let head = "HTTP/1.1 200 OK\r\n\
+            Content-Type: text/event-stream\r\n\
+            Cache-Control: no-cache\r\n\
+            Access-Control-Allow-Origin: *\r\n\
+            Connection: close\r\n\r\n";
+        let mut next = Some(format!("{}{}", head, format_frame(TIMER_STATE, &initial_state)));
+        while let Some(chunk) = next.take() {
+            if writer.write_all(chunk.as_bytes()).and_then(|_| writer.flush()).is_err() {
+                break;
+            }
+            next = match receiver.recv_timeout(KEEP_ALIVE_INTERVAL) {
+                Ok(frame) => Some(frame),
+                Err(RecvTimeoutError::Timeout) => Some(": keep-alive\n\n".to_string()),
+                Err(RecvTimeoutError::Disconnected) => None,
+            };
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use crate::db;
+use crate::report::{DateRange, ReportData};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::Manager;
+use tiny_http::{Header, Response, Server};
+
+const FEED_FILE_NAME: &str = "ics_feed.json";
+const DEFAULT_FEED_PORT: u16 = 3011;
+
+/// Kept across launches so the URL calendar apps subscribed to stays valid.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IcsFeedSettings {
+    enabled: bool,
+    port: u16,
+    token: String,
+}
+
+impl Default for IcsFeedSettings {
+    fn default() -> Self {
+        IcsFeedSettings { enabled: false, port: DEFAULT_FEED_PORT, token: uuid::Uuid::new_v4().simple().to_string() }
+    }
+}
+
+pub struct IcsFeedServer {
+    server: Arc<Server>,
+    url: String,
+    thread: JoinHandle<()>,
+}
+
+/// Escapes TEXT values as required by RFC 5545 section 3.3.11.
+fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Content lines longer than 75 octets are folded onto continuation lines
+/// starting with a space, without splitting a UTF-8 character.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_length = 0;
+    for c in line.chars() {
+        if line_length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_length = 1;
+        }
+        folded.push(c);
+        line_length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn format_ical_datetime(datetime: &DateTime<Utc>) -> String {
+    datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// One VEVENT per session event: the box name as summary, its colour as a
+/// category and the event id as UID so re-exports update instead of duplicate.
+pub fn render_calendar(data: &ReportData) -> String {
+    let stamp = format_ical_datetime(&Utc::now());
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//ClockBlocks//ClockBlocks//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "X-WR-CALNAME:ClockBlocks".to_string(),
+    ];
+
+    for event in &data.session_events {
+        let (Some(start), Some(end)) = (db::parse_datetime(&event.start_datetime), db::parse_datetime(&event.end_datetime)) else {
+            continue;
+        };
+        let time_box = data.time_boxes.iter().find(|time_box| time_box.id == event.time_box_id);
+
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}@clockblocks", event.id));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("DTSTART:{}", format_ical_datetime(&start)));
+        lines.push(format!("DTEND:{}", format_ical_datetime(&end)));
+        lines.push(format!("SUMMARY:{}", escape_text(&data.time_box_name(&event.time_box_id))));
+        if let Some(time_box) = time_box {
+            lines.push(format!("CATEGORIES:{}", escape_text(&time_box.colour)));
+        }
+        lines.push("TRANSP:TRANSPARENT".to_string());
+        lines.push("END:VEVENT".to_string());
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+#[tauri::command]
+pub async fn export_ics(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<String>, String> {
+    let range = DateRange::parse(from, to)?;
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => match FileDialogBuilder::new()
+            .set_title("Export calendar")
+            .set_file_name("clockblocks.ics")
+            .add_filter("iCalendar", &["ics"])
+            .save_file()
+        {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let conn = db::open_connection(&app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    fs::write(&path, render_calendar(&data)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    println!("Exported {} events to {}", data.session_events.len(), path.display());
+    Ok(Some(path.display().to_string()))
+}
+
+/// Why a feed request failed: a range the client got wrong, or a database
+/// that couldn't be read.
+enum FeedError {
+    BadRequest(String),
+    Internal(String),
+}
+
+fn get_feed_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(FEED_FILE_NAME)
+}
+
+fn load_feed_settings(app_handle: &tauri::AppHandle) -> Result<IcsFeedSettings, String> {
+    let path = get_feed_settings_path(app_handle);
+    if !path.exists() {
+        return Ok(IcsFeedSettings::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid calendar feed settings: {}", e))
+}
+
+fn save_feed_settings(app_handle: &tauri::AppHandle, settings: &IcsFeedSettings) -> Result<(), String> {
+    let path = get_feed_settings_path(app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn feed_path(settings: &IcsFeedSettings) -> String {
+    format!("/{}/clockblocks.ics", settings.token)
+}
+
+fn feed_url(settings: &IcsFeedSettings) -> String {
+    format!("http://127.0.0.1:{}{}", settings.port, feed_path(settings))
+}
+
+fn render_feed(app_handle: &tauri::AppHandle, url: &url::Url) -> Result<String, FeedError> {
+    let query = |name: &str| url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned());
+
+    let range = DateRange::parse(query("from"), query("to")).map_err(FeedError::BadRequest)?;
+    let conn = db::open_connection(app_handle).map_err(FeedError::Internal)?;
+    let data = ReportData::load(&conn, range).map_err(FeedError::Internal)?;
+    Ok(render_calendar(&data))
+}
+
+/// Serves the calendar on loopback so calendar apps can subscribe to it. The
+/// random path segment keeps other local users from reading the feed.
+fn start_feed(app_handle: &tauri::AppHandle, settings: &IcsFeedSettings) -> Result<String, String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let mut feed = state.ics_feed.lock().map_err(|_| "Failed to lock calendar feed state".to_string())?;
+    if let Some(running) = feed.as_ref() {
+        return Ok(running.url.clone());
+    }
+
+    let server = Arc::new(
+        Server::http(("127.0.0.1", settings.port)).map_err(|e| format!("Failed to start calendar feed: {}", e))?,
+    );
+    let path = feed_path(settings);
+    let url = feed_url(settings);
+
+    let server_clone = server.clone();
+    let handle = app_handle.clone();
+    let thread = thread::spawn(move || {
+        for request in server_clone.incoming_requests() {
+            let request_url = format!("http://127.0.0.1{}", request.url());
+            let parsed_url = match url::Url::parse(&request_url) {
+                Ok(parsed_url) => parsed_url,
+                Err(_) => continue,
+            };
+            if parsed_url.path() != path {
+                let _ = request.respond(Response::from_string("Not found").with_status_code(404));
+                continue;
+            }
+
+            let response = match render_feed(&handle, &parsed_url) {
+                Ok(body) => Response::from_string(body).with_header(
+                    Header::from_bytes(&b"Content-Type"[..], &b"text/calendar; charset=utf-8"[..]).unwrap(),
+                ),
+                Err(FeedError::BadRequest(e)) => Response::from_string(e).with_status_code(400),
+                Err(FeedError::Internal(e)) => Response::from_string(e).with_status_code(500),
+            };
+            let _ = request.respond(response);
+        }
+    });
+
+    println!("Serving calendar feed at {}", url);
+    *feed = Some(IcsFeedServer { server, url: url.clone(), thread });
+    Ok(url)
+}
+
+/// Stops the server and waits for its thread, so the port is free again.
+fn stop_feed(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let running = state.ics_feed.lock().map_err(|_| "Failed to lock calendar feed state".to_string())?.take();
+    if let Some(running) = running {
+        running.server.unblock();
+        running.thread.join().map_err(|_| "Calendar feed thread panicked".to_string())?;
+    }
+    Ok(())
+}
+
+/// Serves the feed on launch when it was left running, at the same URL so
+/// subscribed calendars keep updating.
+pub fn start_if_enabled(app_handle: &tauri::AppHandle) {
+    match load_feed_settings(app_handle) {
+        Ok(settings) if settings.enabled => {
+            if let Err(e) = start_feed(app_handle, &settings) {
+                println!("{}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => println!("{}", e),
+    }
+}
+
+#[tauri::command]
+pub async fn start_ics_feed(app_handle: tauri::AppHandle, port: Option<u16>) -> Result<String, String> {
+    let mut settings = load_feed_settings(&app_handle)?;
+    if let Some(port) = port.filter(|port| *port != settings.port) {
+        stop_feed(&app_handle)?;
+        settings.port = port;
+    }
+    let url = start_feed(&app_handle, &settings)?;
+    settings.enabled = true;
+    save_feed_settings(&app_handle, &settings)?;
+    Ok(url)
+}
+
+#[tauri::command]
+pub async fn stop_ics_feed(app_handle: tauri::AppHandle) -> Result<(), String> {
+    stop_feed(&app_handle)?;
+    let mut settings = load_feed_settings(&app_handle)?;
+    settings.enabled = false;
+    save_feed_settings(&app_handle, &settings)
+}
@@ -0,0 +1,459 @@
+//! Notices time away from the computer. There is deliberately no pause button,
+//! so leaving without switching keeps the active box running. When input
+//! resumes after at least the threshold, the away period is kept as pending
+//! and the webview asks whether to keep it, move it to the Rest box, or assign
+//! it to another box. The split itself is done by the webview, which owns the
+//! running event.
+//!
+//! Idle time comes from an `IdleSource`: GNOME's idle monitor over D-Bus
+//! (Wayland and X11), the freedesktop screen saver interface on KDE,
+//! `xprintidle` on other X11 desktops, and `ioreg` on macOS. Where none works,
+//! such as on Sway, Hyprland and Windows, idle detection is off.
+
+use crate::db;
+use crate::timer;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tauri::Manager;
+
+const IDLE_FILE_NAME: &str = "idle.json";
+const DEFAULT_THRESHOLD_MINUTES: u32 = 5;
+const MAX_THRESHOLD_MINUTES: u32 = 240;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub trait IdleSource: Send {
+    /// Time since the last keyboard or pointer input.
+    fn idle_time(&self) -> Result<Duration, String>;
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program).args(args).output().map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads a `gdbus call` reply of milliseconds, such as `(uint64 1234,)`.
+fn parse_gdbus_idle_time(output: &str) -> Option<Duration> {
+    let value = output.trim().strip_prefix('(')?.strip_suffix(",)")?;
+    let millis = value.strip_prefix("uint64 ").or_else(|| value.strip_prefix("uint32 "))?;
+    millis.parse().ok().map(Duration::from_millis)
+}
+
+/// GNOME's `org.gnome.Mutter.IdleMonitor`.
+pub struct MutterIdleSource;
+
+impl IdleSource for MutterIdleSource {
+    fn idle_time(&self) -> Result<Duration, String> {
+        let output = run("gdbus", &[
+            "call",
+            "--session",
+            "--dest",
+            "org.gnome.Mutter.IdleMonitor",
+            "--object-path",
+            "/org/gnome/Mutter/IdleMonitor/Core",
+            "--method",
+            "org.gnome.Mutter.IdleMonitor.GetIdletime",
+        ])?;
+        parse_gdbus_idle_time(&output).ok_or_else(|| format!("Unexpected idle monitor reply: {}", output.trim()))
+    }
+}
+
+/// `org.freedesktop.ScreenSaver`, whose session idle time KDE implements.
+pub struct ScreenSaverIdleSource;
+
+impl IdleSource for ScreenSaverIdleSource {
+    fn idle_time(&self) -> Result<Duration, String> {
+        let output = run("gdbus", &[
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.ScreenSaver",
+            "--object-path",
+            "/org/freedesktop/ScreenSaver",
+            "--method",
+            "org.freedesktop.ScreenSaver.GetSessionIdleTime",
+        ])?;
+        parse_gdbus_idle_time(&output).ok_or_else(|| format!("Unexpected screen saver reply: {}", output.trim()))
+    }
+}
+
+/// The X11 screen saver extension through `xprintidle`, which prints
+/// milliseconds.
+pub struct XPrintIdleSource;
+
+impl IdleSource for XPrintIdleSource {
+    fn idle_time(&self) -> Result<Duration, String> {
+        let output = run("xprintidle", &[])?;
+        output
+            .trim()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| format!("Unexpected xprintidle output: {}", output.trim()))
+    }
+}
+
+/// `HIDIdleTime` from the IOKit registry, in nanoseconds.
+pub struct IoregIdleSource;
+
+fn parse_ioreg_idle_time(output: &str) -> Option<Duration> {
+    let line = output.lines().find(|line| line.contains("\"HIDIdleTime\""))?;
+    let nanos = line.split('=').nth(1)?.trim();
+    nanos.parse().ok().map(Duration::from_nanos)
+}
+
+impl IdleSource for IoregIdleSource {
+    fn idle_time(&self) -> Result<Duration, String> {
+        let output = run("ioreg", &["-c", "IOHIDSystem", "-d", "4", "-r", "-k", "HIDIdleTime"])?;
+        parse_ioreg_idle_time(&output).ok_or_else(|| "ioreg reported no HIDIdleTime".to_string())
+    }
+}
+
+/// The first source that works on this desktop.
+pub fn default_source() -> Option<Box<dyn IdleSource>> {
+    let candidates: Vec<Box<dyn IdleSource>> = if cfg!(target_os = "macos") {
+        vec![Box::new(IoregIdleSource)]
+    } else if cfg!(target_os = "linux") {
+        vec![Box::new(MutterIdleSource), Box::new(ScreenSaverIdleSource), Box::new(XPrintIdleSource)]
+    } else {
+        Vec::new()
+    };
+    candidates.into_iter().find(|source| source.idle_time().is_ok())
+}
+
+/// A stretch without input, from the last input before it to the first after.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AwayPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Turns idle time samples into away periods. Polls that are further apart
+/// than the threshold mean the computer was asleep, which counts as away even
+/// if the idle time was reset on waking.
+pub struct IdleTracker {
+    threshold: Duration,
+    away_since: Option<DateTime<Utc>>,
+    last_sample: Option<DateTime<Utc>>,
+}
+
+impl IdleTracker {
+    pub fn new(threshold: Duration) -> Self {
+        IdleTracker { threshold, away_since: None, last_sample: None }
+    }
+
+    /// Returns the away period once input resumes after one.
+    pub fn observe(&mut self, now: DateTime<Utc>, idle: Duration) -> Option<AwayPeriod> {
+        let threshold = chrono::Duration::from_std(self.threshold).ok()?;
+        let last_input = now - chrono::Duration::from_std(idle).ok()?;
+
+        if let Some(last_sample) = self.last_sample.replace(now) {
+            if now - last_sample > threshold {
+                self.mark_away(last_sample);
+            }
+        }
+        if idle >= self.threshold {
+            self.mark_away(last_input);
+            return None;
+        }
+
+        let start = self.away_since.take()?;
+        (last_input - start >= threshold).then_some(AwayPeriod { start, end: last_input })
+    }
+
+    fn mark_away(&mut self, since: DateTime<Utc>) {
+        self.away_since = Some(self.away_since.map_or(since, |away_since| away_since.min(since)));
+    }
+}
+
+fn check(tracker: &mut IdleTracker, source: &dyn IdleSource, now: DateTime<Utc>) -> Result<Option<AwayPeriod>, String> {
+    Ok(tracker.observe(now, source.idle_time()?))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IdleSettings {
+    pub enabled: bool,
+    pub threshold_minutes: u32,
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        IdleSettings { enabled: true, threshold_minutes: DEFAULT_THRESHOLD_MINUTES }
+    }
+}
+
+impl IdleSettings {
+    fn threshold(&self) -> Duration {
+        Duration::from_secs(u64::from(self.threshold_minutes) * 60)
+    }
+}
+
+/// Time away that the webview has yet to ask about.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IdlePeriod {
+    pub id: String,
+    /// The box that was running while away
+    pub time_box_id: String,
+    pub session_id: Option<String>,
+    pub start: String,
+    pub end: String,
+    pub seconds: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum IdleResolution {
+    Keep,
+    Break,
+    Assign {
+        #[serde(rename = "timeBoxId")]
+        time_box_id: String,
+    },
+}
+
+fn get_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(IDLE_FILE_NAME)
+}
+
+fn load_settings(app_handle: &tauri::AppHandle) -> Result<IdleSettings, String> {
+    let path = get_settings_path(app_handle);
+    if !path.exists() {
+        return Ok(IdleSettings::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid idle settings: {}", e))
+}
+
+fn save_settings(app_handle: &tauri::AppHandle, settings: &IdleSettings) -> Result<(), String> {
+    let path = get_settings_path(app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+/// Keeps the part of `away` spent in the running box as pending and asks the
+/// webview about it. Time away without a running box needs no answer.
+fn on_return(app_handle: &tauri::AppHandle, away: AwayPeriod, threshold: Duration) -> Result<(), String> {
+    let state = timer::current_state(app_handle);
+    let Some(time_box_id) = state.active_time_box_id else {
+        return Ok(());
+    };
+    // A switch made while away, such as through the control API, was meant
+    let start = match state.event_start.as_deref().and_then(db::parse_datetime) {
+        Some(event_start) => away.start.max(event_start),
+        None => away.start,
+    };
+    let seconds = (away.end - start).num_seconds();
+    if seconds < threshold.as_secs() as i64 {
+        return Ok(());
+    }
+
+    let period = IdlePeriod {
+        id: uuid::Uuid::new_v4().to_string(),
+        time_box_id,
+        session_id: state.session_id,
+        start: db::format_datetime(&start),
+        end: db::format_datetime(&away.end),
+        seconds,
+    };
+    let app_state: tauri::State<Arc<AppState>> = app_handle.state();
+    // An unanswered earlier period is kept in its box
+    *app_state.idle_period.lock().map_err(|_| "Failed to lock idle state".to_string())? = Some(period.clone());
+    crate::show_main_window(app_handle);
+    app_handle.emit_all("idle-detected", period).map_err(|e| e.to_string())
+}
+
+/// Polls the idle source for as long as the app runs. The sources are tried
+/// on the thread, as each one runs a command.
+pub fn start_monitor(app_handle: tauri::AppHandle) {
+    thread::spawn(move || {
+        let Some(source) = default_source() else {
+            println!("Idle detection is not available on this desktop");
+            return;
+        };
+        let mut tracker: Option<IdleTracker> = None;
+        let mut last_error = None;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let settings = load_settings(&app_handle).unwrap_or_default();
+            if !settings.enabled {
+                tracker = None;
+                continue;
+            }
+
+            let threshold = settings.threshold();
+            let tracker = tracker.get_or_insert_with(|| IdleTracker::new(threshold));
+            tracker.threshold = threshold;
+            let result = check(tracker, source.as_ref(), Utc::now())
+                .and_then(|away| away.map_or(Ok(()), |away| on_return(&app_handle, away, threshold)));
+            // Logged once, as a missing tool would fail on every poll
+            if let Err(e) = &result {
+                if last_error.as_ref() != Some(e) {
+                    println!("Idle detection failed: {}", e);
+                }
+            }
+            last_error = result.err();
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_idle_settings(app_handle: tauri::AppHandle) -> Result<IdleSettings, String> {
+    load_settings(&app_handle)
+}
+
+#[tauri::command]
+pub async fn save_idle_settings(app_handle: tauri::AppHandle, settings: IdleSettings) -> Result<IdleSettings, String> {
+    if settings.threshold_minutes == 0 || settings.threshold_minutes > MAX_THRESHOLD_MINUTES {
+        return Err(format!("The threshold must be between 1 and {} minutes", MAX_THRESHOLD_MINUTES));
+    }
+    save_settings(&app_handle, &settings)?;
+    Ok(settings)
+}
+
+/// The period waiting for an answer, for a webview that loaded after it was
+/// detected.
+#[tauri::command]
+pub fn get_idle_period(app_handle: tauri::AppHandle) -> Result<Option<IdlePeriod>, String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let period = state.idle_period.lock().map_err(|_| "Failed to lock idle state".to_string())?.clone();
+    Ok(period)
+}
+
+/// Answers the pending period. Returns the box the webview should move the
+/// time to, or `None` to keep it where it is.
+#[tauri::command]
+pub async fn resolve_idle_period(
+    app_handle: tauri::AppHandle,
+    id: String,
+    resolution: IdleResolution,
+) -> Result<Option<String>, String> {
+    let period = get_idle_period(app_handle.clone())?
+        .filter(|period| period.id == id)
+        .ok_or("This idle time was already handled")?;
+
+    let target = match resolution {
+        IdleResolution::Keep => None,
+        IdleResolution::Break => Some(timer::break_time_box_id(&app_handle)?),
+        IdleResolution::Assign { time_box_id } => {
+            let conn = db::open_connection(&app_handle)?;
+            let exists = db::get_time_boxes(&conn)?.iter().any(|time_box| time_box.id == time_box_id && !time_box.is_deleted);
+            if !exists {
+                return Err(format!("No time box {}", time_box_id));
+            }
+            Some(time_box_id)
+        }
+    };
+
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let mut pending = state.idle_period.lock().map_err(|_| "Failed to lock idle state".to_string())?;
+    if pending.as_ref().is_some_and(|pending| pending.id == id) {
+        *pending = None;
+    }
+    Ok(target.filter(|target| *target != period.time_box_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Replays scripted idle times, one per poll.
+    struct StubIdleSource {
+        samples: Mutex<VecDeque<Duration>>,
+    }
+
+    impl StubIdleSource {
+        fn new(samples: &[u64]) -> Self {
+            StubIdleSource { samples: Mutex::new(samples.iter().copied().map(Duration::from_secs).collect()) }
+        }
+    }
+
+    impl IdleSource for StubIdleSource {
+        fn idle_time(&self) -> Result<Duration, String> {
+            self.samples.lock().unwrap().pop_front().ok_or_else(|| "No more samples".to_string())
+        }
+    }
+
+    const THRESHOLD: Duration = Duration::from_secs(300);
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    /// Polls every 5s from t=0 and returns the periods reported.
+    fn replay(samples: &[u64]) -> Vec<AwayPeriod> {
+        let source = StubIdleSource::new(samples);
+        let mut tracker = IdleTracker::new(THRESHOLD);
+        (0..samples.len() as i64)
+            .filter_map(|poll| check(&mut tracker, &source, at(poll * 5)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reports_nothing_while_active() {
+        assert!(replay(&[0, 3, 1, 4, 0]).is_empty());
+    }
+
+    #[test]
+    fn reports_nothing_for_pauses_under_the_threshold() {
+        let samples: Vec<u64> = (0..50).map(|poll| poll * 5).chain([2]).collect();
+        assert!(replay(&samples).is_empty());
+    }
+
+    #[test]
+    fn reports_the_away_period_when_input_resumes() {
+        // Last input at t=10, idle until input at t=413
+        let mut samples: Vec<u64> = vec![0, 0, 0];
+        samples.extend((3..=82).map(|poll| poll * 5 - 10));
+        samples.push(2);
+        assert_eq!(replay(&samples), vec![AwayPeriod { start: at(10), end: at(413) }]);
+    }
+
+    #[test]
+    fn reports_each_period_once() {
+        let mut samples: Vec<u64> = vec![0];
+        samples.extend((1..=70).map(|poll| poll * 5));
+        samples.extend([0, 1, 0]);
+        assert_eq!(replay(&samples).len(), 1);
+    }
+
+    #[test]
+    fn counts_sleep_between_polls_as_away() {
+        let source = StubIdleSource::new(&[0, 1, 0]);
+        let mut tracker = IdleTracker::new(THRESHOLD);
+        assert_eq!(check(&mut tracker, &source, at(0)).unwrap(), None);
+        // Woke an hour later, with the idle time reset by waking
+        assert_eq!(check(&mut tracker, &source, at(3600)).unwrap(), Some(AwayPeriod { start: at(0), end: at(3599) }));
+        assert_eq!(check(&mut tracker, &source, at(3605)).unwrap(), None);
+    }
+
+    #[test]
+    fn passes_on_source_errors() {
+        let source = StubIdleSource::new(&[]);
+        let mut tracker = IdleTracker::new(THRESHOLD);
+        assert!(check(&mut tracker, &source, at(0)).is_err());
+    }
+
+    #[test]
+    fn parses_source_output() {
+        assert_eq!(parse_gdbus_idle_time("(uint64 61234,)\n"), Some(Duration::from_millis(61234)));
+        assert_eq!(parse_gdbus_idle_time("(uint32 1500,)\n"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_gdbus_idle_time("Error: no such interface"), None);
+        let ioreg = "    | |   \"HIDIdleTime\" = 2500000000\n    | |   \"HIDKeyboardModifierMappingPairs\" = ()";
+        assert_eq!(parse_ioreg_idle_time(ioreg), Some(Duration::from_millis(2500)));
+    }
+}
@@ -0,0 +1,119 @@
+//! Clockify exports: the "Detailed report" CSV, and JSON as returned by the
+//! detailed report API (`{"timeentries": [...]}`) or the time entries API (a
+//! plain array). CSV times are local, JSON times carry their own offset.
+
+use super::{
+    column_index, optional_column, parse_local_datetime, pick_import_file, read_csv, run_import, ImportReport,
+    ImportedEntry, ProjectMapping, SessionGrouping,
+};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::io::Read;
+
+const SOURCE: &str = "Clockify detailed";
+
+/// Reads all entries of a CSV export. Rows that can't be read are returned as
+/// messages instead of failing the whole import.
+pub fn parse_csv(reader: impl Read) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let rows = read_csv(reader)?;
+    let headers = &rows.headers;
+
+    let project = column_index(headers, "Project", SOURCE)?;
+    let start_date = column_index(headers, "Start Date", SOURCE)?;
+    let start_time = column_index(headers, "Start Time", SOURCE)?;
+    let end_date = column_index(headers, "End Date", SOURCE)?;
+    let end_time = column_index(headers, "End Time", SOURCE)?;
+    let task = optional_column(headers, "Task");
+    let order = rows.date_order(&[start_date, end_date]);
+
+    Ok(rows.parse_entries(|record| {
+        let field = |column: usize| record.get(column).unwrap_or_default();
+        let start = parse_local_datetime(field(start_date), field(start_time), order);
+        let end = parse_local_datetime(field(end_date), field(end_time), order);
+        match (start, end) {
+            (Some(start), Some(end)) if end > start => Ok(ImportedEntry {
+                project: field(project).to_string(),
+                task: task.map(|task| field(task).to_string()),
+                start,
+                end,
+                laid_out: false,
+            }),
+            (Some(_), Some(_)) => Err("end is not after start".to_string()),
+            _ => Err("invalid start or end".to_string()),
+        }
+    }))
+}
+
+/// The report API flattens names (`projectName`), the time entries API nests
+/// them (`project.name`) when hydrated.
+fn name_of(entry: &Value, flat_key: &str, nested_key: &str) -> Option<String> {
+    entry[flat_key]
+        .as_str()
+        .or_else(|| entry[nested_key]["name"].as_str())
+        .map(|name| name.to_string())
+}
+
+fn parse_instant(value: &Value) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.as_str()?).ok().map(|datetime| datetime.with_timezone(&Utc))
+}
+
+pub fn parse_json(reader: impl Read) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let json: Value = serde_json::from_reader(reader).map_err(|e| format!("Invalid Clockify JSON: {}", e))?;
+    let time_entries = json
+        .as_array()
+        .or_else(|| json["timeentries"].as_array())
+        .ok_or("Expected a list of time entries or a detailed report".to_string())?;
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (index, entry) in time_entries.iter().enumerate() {
+        let label = entry["_id"]
+            .as_str()
+            .or_else(|| entry["id"].as_str())
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| format!("#{}", index + 1));
+        let start = parse_instant(&entry["timeInterval"]["start"]);
+        let end = parse_instant(&entry["timeInterval"]["end"]);
+        match (start, end) {
+            (Some(start), Some(end)) if end > start => entries.push(ImportedEntry {
+                project: name_of(entry, "projectName", "project").unwrap_or_default(),
+                task: name_of(entry, "taskName", "task"),
+                start,
+                end,
+                laid_out: false,
+            }),
+            (Some(_), Some(_)) => skipped.push(format!("Entry {}: end is not after start", label)),
+            // Running timers have no end yet
+            (Some(_), None) if entry["timeInterval"]["end"].is_null() => {
+                skipped.push(format!("Entry {}: still running", label))
+            }
+            _ => skipped.push(format!("Entry {}: invalid start or end", label)),
+        }
+    }
+
+    Ok((entries, skipped))
+}
+
+/// Imports a Clockify CSV or JSON export, told apart by the file extension.
+/// Run with `dry_run` first to review the mapping and what would be created.
+#[tauri::command]
+pub async fn import_clockify(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    mapping: Option<ProjectMapping>,
+    grouping: SessionGrouping,
+    dry_run: bool,
+) -> Result<Option<ImportReport>, String> {
+    let Some(path) = pick_import_file(path, "Import Clockify export", &["csv", "json"]) else {
+        return Ok(None);
+    };
+
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let is_json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let (entries, skipped_rows) = if is_json { parse_json(file)? } else { parse_csv(file)? };
+    let report = run_import(&app_handle, entries, skipped_rows, mapping, grouping, dry_run)?;
+
+    println!("Clockify import from {}: {:?}", path.display(), report);
+    Ok(Some(report))
+}
@@ -0,0 +1,168 @@
+//! Harvest "Detailed time" CSV exports. Harvest records hours per day rather
+//! than intervals, so unless the export carries "Started At"/"Ended At" columns
+//! the entries of a day are laid out back to back from 09:00 local time, in
+//! file order. Those times shift when rows are added or removed, so re-imports
+//! match laid out entries by day, time box and length instead.
+
+use super::{
+    column_index, local_to_utc, optional_column, parse_date, parse_time, pick_import_file, read_csv, run_import,
+    ImportReport, ImportedEntry, ProjectMapping, SessionGrouping,
+};
+use chrono::{Duration, NaiveDate, NaiveTime};
+use std::collections::HashMap;
+use std::io::Read;
+
+const SOURCE: &str = "Harvest detailed time";
+
+fn day_start() -> NaiveTime {
+    NaiveTime::from_hms_opt(9, 0, 0).unwrap_or_default()
+}
+
+/// Hours are decimal ("1.5"), or "1:30" in accounts set to show hours:minutes.
+fn parse_hours(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let seconds = match value.split_once(':') {
+        Some((hours, minutes)) => hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60,
+        None => (value.parse::<f64>().ok()? * 3600.0).round() as i64,
+    };
+    Some(seconds)
+}
+
+pub fn parse_csv(reader: impl Read) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let rows = read_csv(reader)?;
+    let headers = &rows.headers;
+
+    let date = column_index(headers, "Date", SOURCE)?;
+    let project = column_index(headers, "Project", SOURCE)?;
+    let hours = column_index(headers, "Hours", SOURCE)?;
+    let task = optional_column(headers, "Task");
+    let started_at = optional_column(headers, "Started At");
+    let ended_at = optional_column(headers, "Ended At");
+    let order = rows.date_order(&[date]);
+
+    // Where the next entry without timestamps starts on each day
+    let mut next_start: HashMap<NaiveDate, NaiveTime> = HashMap::new();
+
+    Ok(rows.parse_entries(|record| {
+        let field = |column: usize| record.get(column).unwrap_or_default();
+        let day = parse_date(field(date), order).ok_or("invalid date".to_string())?;
+
+        let timestamps = started_at
+            .zip(ended_at)
+            .and_then(|(started_at, ended_at)| Some((parse_time(field(started_at))?, parse_time(field(ended_at))?)));
+        let laid_out = timestamps.is_none();
+        let interval = match timestamps {
+            Some((start, end)) => local_to_utc(day, start).zip(local_to_utc(day, end)),
+            None => match parse_hours(field(hours)) {
+                Some(seconds) if seconds > 0 => {
+                    let start = *next_start.entry(day).or_insert_with(day_start);
+                    let (end, wrapped) = start.overflowing_add_signed(Duration::seconds(seconds));
+                    if wrapped != 0 {
+                        return Err("hours run past midnight".to_string());
+                    }
+                    next_start.insert(day, end);
+                    local_to_utc(day, start).zip(local_to_utc(day, end))
+                }
+                Some(_) => return Err("no hours recorded".to_string()),
+                None => None,
+            },
+        };
+
+        match interval {
+            Some((start, end)) if end > start => Ok(ImportedEntry {
+                project: field(project).to_string(),
+                task: task.map(|task| field(task).to_string()),
+                start,
+                end,
+                laid_out,
+            }),
+            Some(_) => Err("end is not after start".to_string()),
+            None => Err("invalid hours or times".to_string()),
+        }
+    }))
+}
+
+/// Imports a Harvest CSV. Run with `dry_run` first to review the mapping and
+/// what would be created.
+#[tauri::command]
+pub async fn import_harvest_csv(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    mapping: Option<ProjectMapping>,
+    grouping: SessionGrouping,
+    dry_run: bool,
+) -> Result<Option<ImportReport>, String> {
+    let Some(path) = pick_import_file(path, "Import Harvest export", &["csv"]) else {
+        return Ok(None);
+    };
+
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let (entries, skipped_rows) = parse_csv(file)?;
+    let report = run_import(&app_handle, entries, skipped_rows, mapping, grouping, dry_run)?;
+
+    println!("Harvest import from {}: {:?}", path.display(), report);
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::importers::{commit_plan, plan_import, summarize};
+    use rusqlite::Connection;
+
+    const HEADER: &str = "Date,Client,Project,Task,Notes,Hours\n";
+
+    fn import(conn: &mut Connection, rows: &[&str]) -> ImportReport {
+        let csv = format!("{}{}\n", HEADER, rows.join("\n"));
+        let (entries, skipped_rows) = parse_csv(csv.as_bytes()).unwrap();
+        let plan = plan_import(conn, entries, &ProjectMapping::new(), SessionGrouping::ByDay).unwrap();
+        commit_plan(conn, &plan).unwrap();
+        summarize(plan, skipped_rows, false)
+    }
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        db::create_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn lays_out_a_day_from_nine() {
+        let csv = format!("{}2024-04-03,,Code,,,1.5\n2024-04-03,,Read,,,0:30\n", HEADER);
+        let (entries, skipped) = parse_csv(csv.as_bytes()).unwrap();
+        assert!(skipped.is_empty());
+        let times: Vec<String> =
+            entries.iter().map(|entry| entry.start.with_timezone(&chrono::Local).format("%H:%M").to_string()).collect();
+        assert_eq!(times, vec!["09:00", "10:30"]);
+        assert!(entries.iter().all(|entry| entry.laid_out));
+    }
+
+    #[test]
+    fn reimporting_a_subset_adds_nothing() {
+        let mut conn = database();
+        let first = import(&mut conn, &["2024-04-03,,Code,,,1", "2024-04-03,,Read,,,0.5", "2024-04-04,,Code,,,2"]);
+        assert_eq!(first.session_events, 3);
+
+        // Without the first row, Read is laid out at 09:00 rather than 10:00
+        let subset = import(&mut conn, &["2024-04-03,,Read,,,0.5", "2024-04-04,,Code,,,2"]);
+        assert_eq!(subset.session_events, 0);
+        assert_eq!(subset.duplicates_skipped, 2);
+        assert_eq!(db::get_session_events(&conn).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn reimporting_a_superset_adds_only_the_new_rows() {
+        let mut conn = database();
+        import(&mut conn, &["2024-04-03,,Code,,,1", "2024-04-03,,Read,,,0.5"]);
+
+        let superset = import(
+            &mut conn,
+            &["2024-04-03,,Code,,,1", "2024-04-03,,Code,,,1", "2024-04-03,,Read,,,0.5", "2024-04-05,,Read,,,3"],
+        );
+        assert_eq!(superset.duplicates_skipped, 2);
+        assert_eq!(superset.session_events, 2);
+        assert_eq!(superset.total_seconds, 3600 + 3 * 3600);
+        assert_eq!(db::get_session_events(&conn).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,493 @@
+//! Importing time entries tracked in other tools. Each source only has to turn
+//! its export into `ImportedEntry` values; mapping projects to time boxes,
+//! skipping entries that were imported before and grouping entries into
+//! sessions is shared.
+
+pub mod clockify;
+pub mod harvest;
+pub mod toggl;
+
+use crate::db::{self, Session, SessionEvent, TimeBox};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use uuid::Uuid;
+
+/// Same palette the settings page offers for time boxes.
+const TIME_BOX_COLOURS: [&str; 8] = ["#1673FF", "#25D1DA", "#91E94B", "#6B5EFF", "#FF4040", "#F1FF53", "#F14EFF", "#FF9E1D"];
+
+const NO_PROJECT_NAME: &str = "No Project";
+
+/// A single tracked interval from another tool.
+#[derive(Clone, Debug)]
+pub struct ImportedEntry {
+    pub project: String,
+    pub task: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Start and end were made up by the importer because the source only had
+    /// a duration, so they can't identify the entry on a re-import
+    pub laid_out: bool,
+}
+
+impl ImportedEntry {
+    /// The key used in a `ProjectMapping`: "Project / Task", or just the project.
+    pub fn source_label(&self) -> String {
+        match self.task.as_deref().map(str::trim).filter(|task| !task.is_empty()) {
+            Some(task) => format!("{} / {}", project_name(&self.project), task),
+            None => project_name(&self.project),
+        }
+    }
+}
+
+/// Source label ("Project" or "Project / Task") to time box name. A task label
+/// takes precedence over its project; unmapped projects become boxes of the
+/// same name.
+pub type ProjectMapping = HashMap<String, String>;
+
+/// How imported entries are grouped into ClockBlocks sessions.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SessionGrouping {
+    /// One session per local calendar day
+    ByDay,
+    /// A new session starts whenever the gap to the previous entry exceeds this
+    Gap { minutes: i64 },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MappedSource {
+    pub source: String,
+    pub time_box: String,
+    pub entries: usize,
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// How every source label in the file was mapped, to review before committing
+    pub mapping: Vec<MappedSource>,
+    pub time_boxes_created: Vec<String>,
+    pub sessions: usize,
+    pub session_events: usize,
+    pub total_seconds: i64,
+    /// Entries already in the database with the same start, end and time box
+    pub duplicates_skipped: usize,
+    pub first_start: Option<String>,
+    pub last_end: Option<String>,
+    /// Rows of the source file that could not be read, with the reason
+    pub skipped_rows: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Everything an import would write, so it can be reported before committing.
+pub struct ImportPlan {
+    pub mapping: Vec<MappedSource>,
+    pub time_boxes: Vec<TimeBox>,
+    pub sessions: Vec<Session>,
+    pub session_events: Vec<SessionEvent>,
+    pub duplicates_skipped: usize,
+}
+
+fn project_name(project: &str) -> String {
+    let project = project.trim();
+    if project.is_empty() {
+        NO_PROJECT_NAME.to_string()
+    } else {
+        project.to_string()
+    }
+}
+
+fn mapped_time_box_name(entry: &ImportedEntry, mapping: &ProjectMapping) -> String {
+    mapping
+        .get(&entry.source_label())
+        .or_else(|| mapping.get(&project_name(&entry.project)))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| project_name(&entry.project))
+}
+
+/// Finds the time box by case-insensitive name, creating one with the next
+/// unused palette colour when there is none.
+pub fn resolve_time_box(existing: &[TimeBox], created: &mut Vec<TimeBox>, name: String) -> String {
+    let matches = |time_box: &&TimeBox| !time_box.is_deleted && time_box.name.eq_ignore_ascii_case(&name);
+    if let Some(time_box) = existing.iter().find(matches).or_else(|| created.iter().find(matches)) {
+        return time_box.id.clone();
+    }
+
+    let used_colours: Vec<&str> = existing.iter().chain(created.iter()).map(|time_box| time_box.colour.as_str()).collect();
+    let colour = TIME_BOX_COLOURS
+        .iter()
+        .find(|colour| !used_colours.contains(colour))
+        .unwrap_or(&TIME_BOX_COLOURS[created.len() % TIME_BOX_COLOURS.len()]);
+
+    let time_box = TimeBox {
+        id: Uuid::new_v4().to_string(),
+        name,
+        is_hidden: false,
+        is_deleted: false,
+        colour: colour.to_string(),
+    };
+    let id = time_box.id.clone();
+    created.push(time_box);
+    id
+}
+
+fn local_date(datetime: &DateTime<Utc>) -> NaiveDate {
+    datetime.with_timezone(&Local).date_naive()
+}
+
+/// Splits entries, each paired with its time box id and sorted by start, into
+/// runs that become one session each.
+fn group_entries(mut entries: Vec<(String, ImportedEntry)>, grouping: SessionGrouping) -> Vec<Vec<(String, ImportedEntry)>> {
+    entries.sort_by_key(|(_, entry)| entry.start);
+
+    let mut groups: Vec<Vec<(String, ImportedEntry)>> = Vec::new();
+    for (time_box_id, entry) in entries {
+        let starts_new_group = match groups.last().and_then(|group| group.last()) {
+            None => true,
+            Some((_, previous)) => match grouping {
+                SessionGrouping::ByDay => local_date(&previous.start) != local_date(&entry.start),
+                SessionGrouping::Gap { minutes } => (entry.start - previous.end).num_minutes() > minutes,
+            },
+        };
+        if starts_new_group {
+            groups.push(vec![(time_box_id, entry)]);
+        } else if let Some(group) = groups.last_mut() {
+            group.push((time_box_id, entry));
+        }
+    }
+    groups
+}
+
+/// Identifies an event independent of its id, so re-importing the same file
+/// finds the events created the first time.
+fn dedupe_key(time_box_id: &str, start: &DateTime<Utc>, end: &DateTime<Utc>) -> (String, i64, i64) {
+    (time_box_id.to_string(), start.timestamp(), end.timestamp())
+}
+
+/// Identifies an entry with laid out times by what the source did record.
+fn laid_out_key(time_box_id: &str, start: &DateTime<Utc>, end: &DateTime<Utc>) -> (NaiveDate, String, i64) {
+    (local_date(start), time_box_id.to_string(), (*end - *start).num_seconds())
+}
+
+pub fn plan_import(
+    conn: &Connection,
+    entries: Vec<ImportedEntry>,
+    mapping: &ProjectMapping,
+    grouping: SessionGrouping,
+) -> Result<ImportPlan, String> {
+    let existing_time_boxes = db::get_time_boxes(conn)?;
+    let existing_events: Vec<(String, DateTime<Utc>, DateTime<Utc>)> = db::get_session_events(conn)?
+        .into_iter()
+        .filter_map(|event| {
+            let start = db::parse_datetime(&event.start_datetime)?;
+            let end = db::parse_datetime(&event.end_datetime)?;
+            Some((event.time_box_id, start, end))
+        })
+        .collect();
+    let mut seen: HashSet<(String, i64, i64)> =
+        existing_events.iter().map(|(time_box_id, start, end)| dedupe_key(time_box_id, start, end)).collect();
+    // How many events of each length a box has per day, each matching one
+    // laid out entry, so a day's entries count once however the file changed
+    let mut unmatched: HashMap<(NaiveDate, String, i64), usize> = HashMap::new();
+    for (time_box_id, start, end) in &existing_events {
+        *unmatched.entry(laid_out_key(time_box_id, start, end)).or_insert(0) += 1;
+    }
+
+    let mut plan = ImportPlan {
+        mapping: Vec::new(),
+        time_boxes: Vec::new(),
+        sessions: Vec::new(),
+        session_events: Vec::new(),
+        duplicates_skipped: 0,
+    };
+
+    // Map every entry first so duplicates can be dropped before grouping
+    let mut mapped_entries = Vec::new();
+    for entry in entries {
+        let time_box_name = mapped_time_box_name(&entry, mapping);
+        let source = entry.source_label();
+        match plan.mapping.iter_mut().find(|mapped| mapped.source == source) {
+            Some(mapped) => mapped.entries += 1,
+            None => plan.mapping.push(MappedSource { source, time_box: time_box_name.clone(), entries: 1 }),
+        }
+
+        let time_box_id = resolve_time_box(&existing_time_boxes, &mut plan.time_boxes, time_box_name);
+        let is_duplicate = if entry.laid_out {
+            match unmatched.get_mut(&laid_out_key(&time_box_id, &entry.start, &entry.end)) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    true
+                }
+                _ => false,
+            }
+        } else {
+            !seen.insert(dedupe_key(&time_box_id, &entry.start, &entry.end))
+        };
+        if is_duplicate {
+            plan.duplicates_skipped += 1;
+            continue;
+        }
+        mapped_entries.push((time_box_id, entry));
+    }
+
+    // Boxes only needed by duplicates don't have to be created
+    plan.time_boxes.retain(|time_box| mapped_entries.iter().any(|(time_box_id, _)| *time_box_id == time_box.id));
+    plan.mapping.sort_by(|a, b| a.source.cmp(&b.source));
+
+    for group in group_entries(mapped_entries, grouping) {
+        let session_id = Uuid::new_v4().to_string();
+        let session_start = group.iter().map(|(_, entry)| entry.start).min().unwrap_or_default();
+        let session_end = group.iter().map(|(_, entry)| entry.end).max().unwrap_or_default();
+        let mut duration = 0;
+
+        for (time_box_id, entry) in &group {
+            let seconds = (entry.end - entry.start).num_seconds();
+            duration += seconds;
+            plan.session_events.push(SessionEvent {
+                id: Uuid::new_v4().to_string(),
+                time_box_id: time_box_id.clone(),
+                session_id: session_id.clone(),
+                start_datetime: db::format_datetime(&entry.start),
+                end_datetime: db::format_datetime(&entry.end),
+                seconds,
+                pomodoro_phase: None,
+                pomodoro_cycle: None,
+            });
+        }
+
+        plan.sessions.push(Session {
+            id: session_id,
+            start_datetime: db::format_datetime(&session_start),
+            end_datetime: Some(db::format_datetime(&session_end)),
+            duration: Some(duration),
+        });
+    }
+
+    Ok(plan)
+}
+
+pub fn summarize(plan: ImportPlan, skipped_rows: Vec<String>, dry_run: bool) -> ImportReport {
+    ImportReport {
+        total_seconds: plan.session_events.iter().map(|event| event.seconds).sum(),
+        duplicates_skipped: plan.duplicates_skipped,
+        time_boxes_created: plan.time_boxes.iter().map(|time_box| time_box.name.clone()).collect(),
+        sessions: plan.sessions.len(),
+        session_events: plan.session_events.len(),
+        first_start: plan.sessions.first().map(|session| session.start_datetime.clone()),
+        last_end: plan.sessions.last().and_then(|session| session.end_datetime.clone()),
+        mapping: plan.mapping,
+        skipped_rows,
+        dry_run,
+    }
+}
+
+pub fn commit_plan(conn: &mut Connection, plan: &ImportPlan) -> Result<(), String> {
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    for time_box in &plan.time_boxes {
+        db::insert_time_box(&tx, time_box)?;
+    }
+    for session in &plan.sessions {
+        db::insert_session(&tx, session)?;
+    }
+    for event in &plan.session_events {
+        db::insert_session_event(&tx, event)?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+const DATE_FORMATS: [&str; 2] = ["%Y-%m-%d", "%d.%m.%Y"];
+const TIME_FORMATS: [&str; 6] = ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p", "%I:%M:%S%p", "%I:%M%p"];
+
+/// Order of the fields in dates like 03/04/2024.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DateOrder {
+    MonthFirst,
+    DayFirst,
+}
+
+impl DateOrder {
+    /// Tells the order from every date in a file. Files are month first, the
+    /// default of the US-based exporters, unless a first field above 12 shows
+    /// they are day first.
+    pub fn detect<'a>(dates: impl IntoIterator<Item = &'a str>) -> DateOrder {
+        let day_first = dates.into_iter().any(|date| {
+            let mut fields = date.trim().split('/');
+            let first = fields.next().and_then(|field| field.parse::<u32>().ok());
+            fields.next().is_some() && first.is_some_and(|first| first > 12)
+        });
+        if day_first {
+            DateOrder::DayFirst
+        } else {
+            DateOrder::MonthFirst
+        }
+    }
+
+    fn slash_format(&self) -> &'static str {
+        match self {
+            DateOrder::MonthFirst => "%m/%d/%Y",
+            DateOrder::DayFirst => "%d/%m/%Y",
+        }
+    }
+}
+
+/// Reads a date as the other tools write it: ISO, dotted European, or with
+/// slashes in the order detected for the file.
+pub fn parse_date(value: &str, order: DateOrder) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .chain([order.slash_format()].iter())
+        .find_map(|format| NaiveDate::parse_from_str(value.trim(), format).ok())
+}
+
+/// Reads 24 hour times and 12 hour times with an am/pm suffix.
+pub fn parse_time(value: &str) -> Option<NaiveTime> {
+    TIME_FORMATS.iter().find_map(|format| NaiveTime::parse_from_str(value.trim(), format).ok())
+}
+
+/// Converts a wall-clock time in the local time zone of this machine to UTC.
+pub fn local_to_utc(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&NaiveDateTime::new(date, time))
+        .earliest()
+        .map(|datetime| datetime.with_timezone(&Utc))
+}
+
+pub fn parse_local_datetime(date: &str, time: &str, order: DateOrder) -> Option<DateTime<Utc>> {
+    local_to_utc(parse_date(date, order)?, parse_time(time)?)
+}
+
+/// The rows of a CSV export, read up front so the date order can be told from
+/// the whole file.
+pub struct CsvRows {
+    pub headers: csv::StringRecord,
+    /// Row numbers as shown in a spreadsheet, counting the header
+    rows: Vec<(usize, Result<csv::StringRecord, String>)>,
+}
+
+pub fn read_csv(reader: impl Read) -> Result<CsvRows, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let rows = reader
+        .records()
+        .enumerate()
+        .map(|(index, record)| (index + 2, record.map_err(|e| e.to_string())))
+        .collect();
+    Ok(CsvRows { headers, rows })
+}
+
+impl CsvRows {
+    /// The order of the dates in `columns` across all rows.
+    pub fn date_order(&self, columns: &[usize]) -> DateOrder {
+        DateOrder::detect(
+            self.rows
+                .iter()
+                .filter_map(|(_, record)| record.as_ref().ok())
+                .flat_map(|record| columns.iter().filter_map(|&column| record.get(column))),
+        )
+    }
+
+    /// Turns each row into an entry. Rows that can't be read are returned as
+    /// messages instead of failing the whole import.
+    pub fn parse_entries<F>(self, mut parse_row: F) -> (Vec<ImportedEntry>, Vec<String>)
+    where
+        F: FnMut(&csv::StringRecord) -> Result<ImportedEntry, String>,
+    {
+        let mut entries = Vec::new();
+        let mut skipped = Vec::new();
+        for (row_number, record) in self.rows {
+            let result = record.and_then(|record| parse_row(&record));
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(reason) => skipped.push(format!("Row {}: {}", row_number, reason)),
+            }
+        }
+        (entries, skipped)
+    }
+}
+
+/// Finds a CSV column by case-insensitive header name.
+pub fn column_index(headers: &csv::StringRecord, name: &str, source: &str) -> Result<usize, String> {
+    optional_column(headers, name).ok_or(format!("Column \"{}\" not found, is this a {} export?", name, source))
+}
+
+pub fn optional_column(headers: &csv::StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name))
+}
+
+/// Resolves the file to import, asking the user when no path was given.
+pub fn pick_import_file(path: Option<String>, title: &str, extensions: &[&str]) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(PathBuf::from(path)),
+        None => FileDialogBuilder::new()
+            .set_title(title)
+            .add_filter("Export", extensions)
+            .pick_file(),
+    }
+}
+
+/// Plans the import and, unless this is a dry run, writes it in one transaction.
+pub fn run_import(
+    app_handle: &tauri::AppHandle,
+    entries: Vec<ImportedEntry>,
+    skipped_rows: Vec<String>,
+    mapping: Option<ProjectMapping>,
+    grouping: SessionGrouping,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    let mut conn = db::open_connection(app_handle)?;
+    let plan = plan_import(&conn, entries, &mapping.unwrap_or_default(), grouping)?;
+    if !dry_run {
+        commit_plan(&mut conn, &plan)?;
+    }
+    Ok(summarize(plan, skipped_rows, dry_run))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slash_dates_are_month_first_unless_a_day_shows_otherwise() {
+        assert_eq!(DateOrder::detect(["03/04/2024", "12/31/2024"]), DateOrder::MonthFirst);
+        assert_eq!(DateOrder::detect(["03/04/2024", "25/04/2024"]), DateOrder::DayFirst);
+        assert_eq!(DateOrder::detect(["2024-04-25", "25.04.2024"]), DateOrder::MonthFirst);
+    }
+
+    #[test]
+    fn parses_dates_in_the_detected_order() {
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day);
+        assert_eq!(parse_date("03/04/2024", DateOrder::MonthFirst), date(2024, 3, 4));
+        assert_eq!(parse_date("03/04/2024", DateOrder::DayFirst), date(2024, 4, 3));
+        assert_eq!(parse_date("2024-04-03", DateOrder::DayFirst), date(2024, 4, 3));
+        assert_eq!(parse_date("03.04.2024", DateOrder::MonthFirst), date(2024, 4, 3));
+        assert_eq!(parse_date("25/04/2024", DateOrder::MonthFirst), None);
+    }
+
+    #[test]
+    fn day_first_files_import_ambiguous_rows_day_first() {
+        let csv = "Project,Start date,Start time,End date,End time\n\
+            Code,03/04/2024,09:00:00,03/04/2024,10:00:00\n\
+            Read,25/04/2024,09:00:00,25/04/2024,10:00:00\n";
+        let (entries, skipped) = toggl::parse_csv(csv.as_bytes()).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(local_date(&entries[0].start), NaiveDate::from_ymd_opt(2024, 4, 3).unwrap());
+    }
+
+    #[test]
+    fn unreadable_rows_are_reported_in_file_order() {
+        let csv = "Project,Start date,Start time,End date,End time\n\
+            Code,03/04/2024,09:00:00,03/04/2024,08:00:00\n\
+            Read,03/04/2024,09:00:00\n\
+            Write,03/04/2024,09:00:00,03/04/2024,10:00:00\n";
+        let (entries, skipped) = toggl::parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(skipped, vec!["Row 2: end is not after start".to_string(), "Row 3: invalid start or end".to_string()]);
+    }
+}
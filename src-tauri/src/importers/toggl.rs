@@ -0,0 +1,63 @@
+//! Toggl Track "Detailed report" CSV exports. Dates and times are in the
+//! local time zone of the exporting user.
+
+use super::{
+    column_index, optional_column, parse_local_datetime, pick_import_file, read_csv, run_import, ImportReport,
+    ImportedEntry, ProjectMapping, SessionGrouping,
+};
+use std::io::Read;
+
+const SOURCE: &str = "Toggl detailed";
+
+/// Reads all entries of the export. Rows that can't be read are returned as
+/// messages instead of failing the whole import.
+pub fn parse_csv(reader: impl Read) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let rows = read_csv(reader)?;
+    let headers = &rows.headers;
+
+    let project = column_index(headers, "Project", SOURCE)?;
+    let start_date = column_index(headers, "Start date", SOURCE)?;
+    let start_time = column_index(headers, "Start time", SOURCE)?;
+    let end_date = column_index(headers, "End date", SOURCE)?;
+    let end_time = column_index(headers, "End time", SOURCE)?;
+    let task = optional_column(headers, "Task");
+    let order = rows.date_order(&[start_date, end_date]);
+
+    Ok(rows.parse_entries(|record| {
+        let field = |column: usize| record.get(column).unwrap_or_default();
+        let start = parse_local_datetime(field(start_date), field(start_time), order);
+        let end = parse_local_datetime(field(end_date), field(end_time), order);
+        match (start, end) {
+            (Some(start), Some(end)) if end > start => Ok(ImportedEntry {
+                project: field(project).to_string(),
+                task: task.map(|task| field(task).to_string()),
+                start,
+                end,
+                laid_out: false,
+            }),
+            (Some(_), Some(_)) => Err("end is not after start".to_string()),
+            _ => Err("invalid start or end".to_string()),
+        }
+    }))
+}
+
+/// Imports a Toggl CSV. Run with `dry_run` first to show what would be created.
+#[tauri::command]
+pub async fn import_toggl_csv(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    mapping: Option<ProjectMapping>,
+    grouping: SessionGrouping,
+    dry_run: bool,
+) -> Result<Option<ImportReport>, String> {
+    let Some(path) = pick_import_file(path, "Import Toggl Track export", &["csv"]) else {
+        return Ok(None);
+    };
+
+    let file = std::fs::File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let (entries, skipped_rows) = parse_csv(file)?;
+    let report = run_import(&app_handle, entries, skipped_rows, mapping, grouping, dry_run)?;
+
+    println!("Toggl import from {}: {:?}", path.display(), report);
+    Ok(Some(report))
+}
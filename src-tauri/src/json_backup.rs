@@ -0,0 +1,378 @@
+use crate::backups;
+use crate::db::{self, Session, SessionEvent, TimeBox};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::Manager;
+
+const BACKUP_FORMAT: &str = "clockblocks-backup";
+/// Bump when the shape of `Backup` changes; older versions must stay importable.
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MetadataEntry {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub time_boxes: Vec<TimeBox>,
+    pub sessions: Vec<Session>,
+    pub session_events: Vec<SessionEvent>,
+    pub metadata: Vec<MetadataEntry>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Add what is missing, keep everything already in the database
+    Merge,
+    /// Wipe the four tables and load the backup in their place
+    Replace,
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub time_boxes_added: usize,
+    pub sessions_added: usize,
+    pub session_events_added: usize,
+    /// Rows already present with identical content
+    pub unchanged: usize,
+    /// Ids present on both sides with different content; nothing is imported while there are any
+    pub collisions: Vec<String>,
+    pub dry_run: bool,
+}
+
+fn get_metadata(conn: &Connection) -> Result<Vec<MetadataEntry>, String> {
+    let mut stmt = conn.prepare("SELECT name, value FROM metadata").map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| Ok(MetadataEntry { name: row.get(0)?, value: row.get(1)? }))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+pub fn create_backup(conn: &Connection) -> Result<Backup, String> {
+    Ok(Backup {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        exported_at: db::format_datetime(&chrono::Utc::now()),
+        time_boxes: db::get_time_boxes(conn)?,
+        sessions: db::get_sessions(conn)?,
+        session_events: db::get_session_events(conn)?,
+        metadata: get_metadata(conn)?,
+    })
+}
+
+/// Checks the backup on its own: known format, unique ids and every event
+/// pointing at a box and session that exist in the backup or in `conn`.
+fn validate(conn: &Connection, backup: &Backup, mode: ImportMode) -> Result<(), String> {
+    if backup.format != BACKUP_FORMAT {
+        return Err(format!("Not a ClockBlocks backup (format \"{}\")", backup.format));
+    }
+    if backup.version == 0 || backup.version > BACKUP_VERSION {
+        return Err(format!("Unsupported backup version {}", backup.version));
+    }
+
+    fn unique_ids<'a>(table: &str, ids: impl Iterator<Item = &'a String>) -> Result<HashSet<String>, String> {
+        let mut seen = HashSet::new();
+        for id in ids {
+            if !seen.insert(id.clone()) {
+                return Err(format!("Duplicate id {} in {}", id, table));
+            }
+        }
+        Ok(seen)
+    }
+
+    let mut time_box_ids = unique_ids("timeBoxes", backup.time_boxes.iter().map(|time_box| &time_box.id))?;
+    let mut session_ids = unique_ids("sessions", backup.sessions.iter().map(|session| &session.id))?;
+    unique_ids("sessionEvents", backup.session_events.iter().map(|event| &event.id))?;
+
+    // When merging, events may also reference rows that are only in the database
+    if mode == ImportMode::Merge {
+        time_box_ids.extend(db::get_time_boxes(conn)?.into_iter().map(|time_box| time_box.id));
+        session_ids.extend(db::get_sessions(conn)?.into_iter().map(|session| session.id));
+    }
+
+    for event in &backup.session_events {
+        if !time_box_ids.contains(&event.time_box_id) {
+            return Err(format!("Event {} references unknown time box {}", event.id, event.time_box_id));
+        }
+        if !session_ids.contains(&event.session_id) {
+            return Err(format!("Event {} references unknown session {}", event.id, event.session_id));
+        }
+        if db::parse_datetime(&event.start_datetime).is_none() || db::parse_datetime(&event.end_datetime).is_none() {
+            return Err(format!("Event {} has an invalid timestamp", event.id));
+        }
+    }
+
+    Ok(())
+}
+
+trait Row: PartialEq {
+    fn id(&self) -> &str;
+}
+
+impl Row for TimeBox {
+    fn id(&self) -> &str { &self.id }
+}
+
+impl Row for Session {
+    fn id(&self) -> &str { &self.id }
+}
+
+impl Row for SessionEvent {
+    fn id(&self) -> &str { &self.id }
+}
+
+/// Decides for each row of the backup whether it is new, identical to the
+/// database or a collision, and records the outcome in the report.
+fn plan_rows<'a, T: Row>(incoming: &'a [T], existing: &[T], report: &mut ImportReport) -> Vec<&'a T> {
+    let existing: HashMap<&str, &T> = existing.iter().map(|row| (row.id(), row)).collect();
+    let mut new_rows = Vec::new();
+    for row in incoming {
+        match existing.get(row.id()).copied() {
+            None => new_rows.push(row),
+            Some(current) if current == row => report.unchanged += 1,
+            Some(_) => report.collisions.push(row.id().to_string()),
+        }
+    }
+    new_rows
+}
+
+pub fn import_backup(conn: &mut Connection, backup: &Backup, mode: ImportMode, dry_run: bool) -> Result<ImportReport, String> {
+    validate(conn, backup, mode)?;
+
+    let mut report = ImportReport { dry_run, ..Default::default() };
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    if mode == ImportMode::Replace {
+        tx.execute_batch("DELETE FROM sessionEvents; DELETE FROM sessions; DELETE FROM timeBoxes; DELETE FROM metadata;")
+            .map_err(|e| e.to_string())?;
+    }
+
+    let time_boxes = plan_rows(&backup.time_boxes, &db::get_time_boxes(&tx)?, &mut report);
+    let sessions = plan_rows(&backup.sessions, &db::get_sessions(&tx)?, &mut report);
+    let session_events = plan_rows(&backup.session_events, &db::get_session_events(&tx)?, &mut report);
+
+    if !report.collisions.is_empty() || dry_run {
+        report.time_boxes_added = time_boxes.len();
+        report.sessions_added = sessions.len();
+        report.session_events_added = session_events.len();
+        // Dropping the transaction rolls back the delete of a replace
+        return Ok(report);
+    }
+
+    for time_box in &time_boxes {
+        db::insert_time_box(&tx, time_box)?;
+    }
+    for session in &sessions {
+        db::insert_session(&tx, session)?;
+    }
+    for event in &session_events {
+        db::insert_session_event(&tx, event)?;
+    }
+    // Existing metadata such as the schema version wins over the backup's when merging
+    for entry in &backup.metadata {
+        tx.execute(
+            "INSERT OR IGNORE INTO metadata (name, value) VALUES (?1, ?2)",
+            params![entry.name, entry.value],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+
+    report.time_boxes_added = time_boxes.len();
+    report.sessions_added = sessions.len();
+    report.session_events_added = session_events.len();
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn export_json(app_handle: tauri::AppHandle, path: Option<String>) -> Result<Option<String>, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let file_name = format!("clockblocks-backup-{}.json", chrono::Local::now().format("%Y-%m-%d"));
+            match FileDialogBuilder::new()
+                .set_title("Export backup")
+                .set_file_name(&file_name)
+                .add_filter("JSON", &["json"])
+                .save_file()
+            {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let conn = db::open_connection(&app_handle)?;
+    let backup = create_backup(&conn)?;
+    let contents = serde_json::to_string_pretty(&backup).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    println!("Exported backup to {}", path.display());
+    Ok(Some(path.display().to_string()))
+}
+
+#[tauri::command]
+pub async fn import_json(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    mode: ImportMode,
+    dry_run: bool,
+) -> Result<Option<ImportReport>, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => match FileDialogBuilder::new()
+            .set_title("Import backup")
+            .add_filter("JSON", &["json"])
+            .pick_file()
+        {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let backup: Backup = serde_json::from_str(&contents).map_err(|e| format!("Invalid backup file: {}", e))?;
+
+    let mut conn = db::open_connection(&app_handle)?;
+    let replaces = mode == ImportMode::Replace && !dry_run;
+    // A snapshot to restore if the replace was a mistake
+    if replaces {
+        backups::backup_before_restore(&conn, &app_handle)?;
+    }
+    let report = import_backup(&mut conn, &backup, mode, dry_run)?;
+
+    println!("Imported backup from {}: {:?}", path.display(), report);
+    // The webview reloads rather than writing back the rows it still holds
+    if replaces && report.collisions.is_empty() {
+        app_handle.emit_all("database-restored", path.display().to_string()).map_err(|e| e.to_string())?;
+    }
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_box(id: &str, name: &str) -> TimeBox {
+        TimeBox { id: id.to_string(), name: name.to_string(), is_hidden: false, is_deleted: false, colour: "#1673FF".to_string() }
+    }
+
+    fn session(id: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            start_datetime: "2024-08-01T09:00:00.000Z".to_string(),
+            end_datetime: Some("2024-08-01T10:00:00.000Z".to_string()),
+            duration: Some(3600),
+        }
+    }
+
+    fn event(id: &str, time_box_id: &str, session_id: &str) -> SessionEvent {
+        SessionEvent {
+            id: id.to_string(),
+            time_box_id: time_box_id.to_string(),
+            session_id: session_id.to_string(),
+            start_datetime: "2024-08-01T09:00:00.000Z".to_string(),
+            end_datetime: "2024-08-01T10:00:00.000Z".to_string(),
+            seconds: 3600,
+            pomodoro_phase: None,
+            pomodoro_cycle: None,
+        }
+    }
+
+    fn backup(time_boxes: Vec<TimeBox>, sessions: Vec<Session>, session_events: Vec<SessionEvent>) -> Backup {
+        Backup {
+            format: BACKUP_FORMAT.to_string(),
+            version: BACKUP_VERSION,
+            exported_at: "2024-08-02T00:00:00.000Z".to_string(),
+            time_boxes,
+            sessions,
+            session_events,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// A database with box b1, session s1 and its event e1.
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        db::create_tables(&conn).unwrap();
+        db::insert_time_box(&conn, &time_box("b1", "Code")).unwrap();
+        db::insert_session(&conn, &session("s1")).unwrap();
+        db::insert_session_event(&conn, &event("e1", "b1", "s1")).unwrap();
+        conn
+    }
+
+    #[test]
+    fn plans_new_unchanged_and_colliding_rows() {
+        let mut report = ImportReport::default();
+        let existing = vec![time_box("b1", "Code"), time_box("b2", "Read")];
+        let incoming = vec![time_box("b1", "Code"), time_box("b2", "Reading"), time_box("b3", "Calls")];
+        let new_rows = plan_rows(&incoming, &existing, &mut report);
+        assert_eq!(new_rows, vec![&incoming[2]]);
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.collisions, vec!["b2".to_string()]);
+    }
+
+    #[test]
+    fn rejects_collisions_without_writing() {
+        let mut conn = database();
+        let renamed = backup(vec![time_box("b1", "Coding"), time_box("b2", "Read")], Vec::new(), Vec::new());
+        let report = import_backup(&mut conn, &renamed, ImportMode::Merge, false).unwrap();
+        assert_eq!(report.collisions, vec!["b1".to_string()]);
+        assert_eq!(db::get_time_boxes(&conn).unwrap(), vec![time_box("b1", "Code")]);
+    }
+
+    #[test]
+    fn rejects_dangling_references() {
+        let mut conn = database();
+        let dangling = backup(Vec::new(), vec![session("s2")], vec![event("e2", "b9", "s2")]);
+        let error = import_backup(&mut conn, &dangling, ImportMode::Merge, false).unwrap_err();
+        assert!(error.contains("unknown time box b9"), "{}", error);
+
+        // Replacing drops the database's rows, so they can't be referenced
+        let on_existing = backup(Vec::new(), vec![session("s2")], vec![event("e2", "b1", "s2")]);
+        assert!(import_backup(&mut conn, &on_existing, ImportMode::Merge, true).is_ok());
+        assert!(import_backup(&mut conn, &on_existing, ImportMode::Replace, true).is_err());
+    }
+
+    #[test]
+    fn dry_runs_change_nothing() {
+        let mut conn = database();
+        let other = backup(vec![time_box("b2", "Read")], vec![session("s2")], vec![event("e2", "b2", "s2")]);
+        for mode in [ImportMode::Merge, ImportMode::Replace] {
+            let report = import_backup(&mut conn, &other, mode, true).unwrap();
+            assert!(report.dry_run);
+            assert_eq!((report.time_boxes_added, report.sessions_added, report.session_events_added), (1, 1, 1));
+        }
+        assert_eq!(db::get_session_events(&conn).unwrap(), vec![event("e1", "b1", "s1")]);
+    }
+
+    #[test]
+    fn merges_beside_and_replaces_the_existing_rows() {
+        let other = backup(vec![time_box("b2", "Read")], vec![session("s2")], vec![event("e2", "b2", "s2")]);
+
+        let mut merged = database();
+        let report = import_backup(&mut merged, &other, ImportMode::Merge, false).unwrap();
+        assert_eq!(report.session_events_added, 1);
+        let ids: Vec<String> = db::get_session_events(&merged).unwrap().into_iter().map(|event| event.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"e1".to_string()) && ids.contains(&"e2".to_string()));
+
+        let mut replaced = database();
+        import_backup(&mut replaced, &other, ImportMode::Replace, false).unwrap();
+        assert_eq!(db::get_time_boxes(&replaced).unwrap(), vec![time_box("b2", "Read")]);
+        assert_eq!(db::get_session_events(&replaced).unwrap(), vec![event("e2", "b2", "s2")]);
+    }
+}
@@ -0,0 +1,8 @@
+//! The parts of ClockBlocks shared by the app and `clockblocks-cli`: where the
+//! files live, the database, the report tables and the single instance socket.
+
+pub mod cli_timer;
+pub mod db;
+pub mod paths;
+pub mod report;
+pub mod single_instance;
@@ -5,14 +5,13 @@
 )]
 
 
-use tauri::Manager;
+use tauri::{CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, WindowEvent};
 use serde_json::Value;
 use std::fs;
 use tauri::api::shell;
 use tiny_http::{Server, Response};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Sender};
 use std::thread;
-use tauri::api::path::app_data_dir;
 use std::fs::File;
 use std::io::Read;
 use serde::{Deserialize, Serialize};
@@ -25,13 +24,49 @@ use oauth2::{
 use oauth2::basic::BasicClient;
 use url::Url;
 use oauth2::reqwest::async_http_client;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use serde_json::json;
 use std::path::PathBuf;
 use std::env;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use clockblocks::{cli_timer, db, paths, report, single_instance};
+
+mod backups;
+mod control_api;
+mod csv_export;
+mod deep_link;
+mod drive;
+mod events;
+mod ical;
+mod idle;
+mod importers;
+mod json_backup;
+mod pdf_export;
+mod pomodoro;
+mod profiles;
+mod sheet_sync;
+mod shortcuts;
+mod timesheet;
+mod timer;
+mod timewarrior;
+mod webhooks;
+mod xlsx_export;
 
 struct AppState {
     pkce_verifier: Mutex<Option<String>>,
+    ics_feed: Mutex<Option<ical::IcsFeedServer>>,
+    // Last state reported by the timer in the webview, None until it has loaded
+    timer: Mutex<Option<timer::TimerState>>,
+    // Signalled on every report, for commands waiting on the webview
+    timer_reported: Condvar,
+    control_api: Mutex<Option<control_api::ControlApiServer>>,
+    // Open Server-Sent Events streams of the control API
+    event_streams: Mutex<Vec<Sender<String>>>,
+    webhook_queue: Mutex<Sender<webhooks::Delivery>>,
+    webhook_log: Mutex<VecDeque<webhooks::DeliveryAttempt>>,
+    // Time away from the computer that the webview has yet to ask about
+    idle_period: Mutex<Option<idle::IdlePeriod>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,6 +74,36 @@ struct AuthToken {
     access_token: String,
     refresh_token: String,
     expiry: u64,
+    // Space separated scopes granted with the token, missing for tokens saved before it was recorded
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+// Only files the app created or the user opened with it, instead of the whole Drive
+const GOOGLE_OAUTH_SCOPE: &str = "https://www.googleapis.com/auth/drive.file";
+
+/// Tokens from before the move to `drive.file` were granted full Drive and Sheets
+/// access. Those users have to consent again so the broad grant can be dropped.
+fn has_narrow_scope(token: &AuthToken) -> bool {
+    match &token.scope {
+        Some(scope) => scope.split_whitespace().all(|granted| granted == GOOGLE_OAUTH_SCOPE),
+        None => false,
+    }
+}
+
+async fn revoke_token(token: &str) -> Result<(), String> {
+    let response = Client::new()
+        .post("https://oauth2.googleapis.com/revoke")
+        .form(&[("token", token)])
+        .send()
+        .await
+        .map_err(|e| format!("Failed to revoke token: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to revoke token: {}", response.status()));
+    }
+
+    Ok(())
 }
 
 fn get_oauth_config() -> Result<(String, String, String, String), String> {
@@ -78,8 +143,7 @@ async fn start_google_sign_in(window: tauri::Window, app_handle: tauri::AppHandl
 
     let (auth_url, csrf_token) = client
         .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("https://www.googleapis.com/auth/spreadsheets".to_string()))
-        .add_scope(Scope::new("https://www.googleapis.com/auth/drive".to_string()))
+        .add_scope(Scope::new(GOOGLE_OAUTH_SCOPE.to_string()))
         .set_pkce_challenge(pkce_challenge)
         .add_extra_param("access_type", "offline")  // Add this line
         .add_extra_param("prompt", "consent")       // Add this line
@@ -139,32 +203,200 @@ async fn start_google_sign_in(window: tauri::Window, app_handle: tauri::AppHandl
 }
 
 fn get_data_dir(app_handle: &tauri::AppHandle) -> PathBuf {
-    if cfg!(debug_assertions) {
-        // Development mode
-        app_handle.path_resolver()
-            .app_local_data_dir()
-            .expect("Failed to get app local data directory")
-            .join("dev_data")
-    } else {
-        // Production mode
-        app_data_dir(&app_handle.config())
-            .expect("Failed to get app data directory")
+    get_data_dir_for(&app_handle.config())
+}
+
+// Also needed before the app is built, to check for another instance
+fn get_data_dir_for(config: &tauri::Config) -> PathBuf {
+    paths::data_dir(&config.tauri.bundle.identifier).expect("Failed to get app data directory")
+}
+
+fn show_main_window(app_handle: &tauri::AppHandle) {
+    if let Some(window) = app_handle.get_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
     }
 }
 
+const TRAY_STATUS_ID: &str = "tray-status";
+const TRAY_SWITCH_PREFIX: &str = "tray-switch:";
+const TRAY_END_SESSION_ID: &str = "tray-end-session";
+const TRAY_SHOW_ID: &str = "tray-show";
+const TRAY_QUIT_ID: &str = "tray-quit";
+/// How often the elapsed time in the tray is updated
+const TRAY_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the tray reloads the boxes, which the webview edits directly
+const TRAY_TIME_BOXES_INTERVAL: Duration = Duration::from_secs(10);
+
+fn elapsed_since(start: Option<&str>) -> String {
+    start
+        .and_then(db::parse_datetime)
+        .map(|start| report::format_duration((Utc::now() - start).num_seconds()))
+        .unwrap_or_default()
+}
+
+fn tray_status(time_boxes: &[db::TimeBox], state: &timer::TimerState) -> String {
+    let Some(active_id) = &state.active_time_box_id else {
+        return "No session running".to_string();
+    };
+    let name = time_boxes
+        .iter()
+        .find(|time_box| &time_box.id == active_id)
+        .map_or("Unknown box", |time_box| time_box.name.as_str());
+    format!(
+        "{} {} · Session {}",
+        name,
+        elapsed_since(state.event_start.as_deref()),
+        elapsed_since(state.session_start.as_deref())
+    )
+}
+
+/// Lists the visible boxes with the running one checked.
+fn build_tray_menu(time_boxes: &[db::TimeBox], state: &timer::TimerState) -> SystemTrayMenu {
+    let mut menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new(TRAY_STATUS_ID, tray_status(time_boxes, state)).disabled())
+        .add_native_item(SystemTrayMenuItem::Separator);
+    for time_box in time_boxes.iter().filter(|time_box| !time_box.is_hidden) {
+        let mut item = CustomMenuItem::new(format!("{}{}", TRAY_SWITCH_PREFIX, time_box.id), time_box.name.clone());
+        if state.active_time_box_id.as_ref() == Some(&time_box.id) {
+            item = item.selected();
+        }
+        menu = menu.add_item(item);
+    }
+    let mut end_session = CustomMenuItem::new(TRAY_END_SESSION_ID, "End session");
+    if state.active_time_box_id.is_none() {
+        end_session = end_session.disabled();
+    }
+    menu.add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(end_session)
+        .add_item(CustomMenuItem::new(TRAY_SHOW_ID, "Show ClockBlocks"))
+        .add_item(CustomMenuItem::new(TRAY_QUIT_ID, "Quit"))
+}
+
+fn handle_tray_event(app_handle: &tauri::AppHandle, event: SystemTrayEvent) {
+    match event {
+        // Other platforms open the menu on a left click
+        SystemTrayEvent::LeftClick { .. } if cfg!(windows) => show_main_window(app_handle),
+        SystemTrayEvent::MenuItemClick { id, .. } => handle_tray_menu_click(app_handle, &id),
+        _ => {}
+    }
+}
+
+fn handle_tray_menu_click(app_handle: &tauri::AppHandle, id: &str) {
+    // Commands wait for the webview to confirm, so they run off the main thread
+    let handle = app_handle.clone();
+    match id {
+        TRAY_END_SESSION_ID => {
+            thread::spawn(move || {
+                if let Err(e) = timer::end_session(&handle) {
+                    println!("Failed to end the session from the tray: {}", e);
+                }
+            });
+        }
+        TRAY_SHOW_ID => show_main_window(app_handle),
+        TRAY_QUIT_ID => app_handle.exit(0),
+        id => {
+            if let Some(time_box_id) = id.strip_prefix(TRAY_SWITCH_PREFIX).map(str::to_string) {
+                thread::spawn(move || {
+                    if let Err(e) = timer::switch_to(&handle, &time_box_id) {
+                        println!("Failed to switch from the tray: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Keeps the elapsed time in the tray current, and rebuilds its menu when the
+/// boxes or the running box change.
+fn start_tray_updates(app_handle: tauri::AppHandle) {
+    thread::spawn(move || {
+        let mut time_boxes: Vec<db::TimeBox> = Vec::new();
+        let mut time_boxes_loaded: Option<Instant> = None;
+        let mut shown_menu = None;
+        loop {
+            if !matches!(time_boxes_loaded, Some(loaded) if loaded.elapsed() < TRAY_TIME_BOXES_INTERVAL) {
+                match db::open_connection(&app_handle).and_then(|conn| db::get_time_boxes(&conn)) {
+                    Ok(loaded) => time_boxes = loaded.into_iter().filter(|time_box| !time_box.is_deleted).collect(),
+                    Err(e) => println!("Failed to load time boxes for the tray: {}", e),
+                }
+                time_boxes_loaded = Some(Instant::now());
+            }
+
+            let state = timer::current_state(&app_handle);
+            let tray = app_handle.tray_handle();
+            let menu: Vec<(String, String, bool)> = time_boxes
+                .iter()
+                .map(|time_box| (time_box.id.clone(), time_box.name.clone(), time_box.is_hidden))
+                .collect();
+            let menu = (menu, state.active_time_box_id.clone());
+            if shown_menu.as_ref() != Some(&menu) {
+                if let Err(e) = tray.set_menu(build_tray_menu(&time_boxes, &state)) {
+                    println!("Failed to update the tray menu: {}", e);
+                }
+                shown_menu = Some(menu);
+            }
+            let status = tray_status(&time_boxes, &state);
+            let _ = tray.get_item(TRAY_STATUS_ID).set_title(&status);
+            // Tooltips aren't supported on Linux, where the status item shows it
+            let _ = tray.set_tooltip(&status);
+
+            thread::sleep(TRAY_REFRESH_INTERVAL);
+        }
+    });
+}
+
+/// Handles launch arguments such as `--switch Read`, `--end-session` or a
+/// `clockblocks://` link. A later launch without any brings the window to the
+/// front.
+fn handle_launch_args(app_handle: &tauri::AppHandle, args: &[String]) -> Result<(), String> {
+    let mut handled = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Windows and Linux open links by launching the app with the URL
+            link if deep_link::is_deep_link(link) => {
+                deep_link::handle(app_handle, &deep_link::parse(link)?)?;
+                handled = true;
+            }
+            "--switch" => {
+                let key = args.next().ok_or("--switch needs a time box")?;
+                let time_box = timer::find_time_box(app_handle, key)?.ok_or_else(|| format!("No time box {}", key))?;
+                timer::switch_to(app_handle, &time_box.id)?;
+                handled = true;
+            }
+            "--end-session" => {
+                timer::end_session(app_handle)?;
+                handled = true;
+            }
+            // Platforms pass their own arguments to apps, such as macOS's `-psn_`
+            _ => {}
+        }
+    }
+
+    if !handled {
+        show_main_window(app_handle);
+    }
+    Ok(())
+}
+
 #[tauri::command]
 async fn save_auth_token(
     app_handle: tauri::AppHandle,
     access_token: String,
     refresh_token: String,
     expiry: u64,
+    scope: Option<String>,
+    profile: Option<String>,
 ) -> Result<(), String> {
     let auth_token = AuthToken {
         access_token,
         refresh_token,
         expiry,
+        scope,
     };
-    let data_dir = get_data_dir(&app_handle);
+    let data_dir = profiles::resolve_profile_dir(&app_handle, profile.as_deref())?;
     fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
     let token_path = data_dir.join("auth_token.json");
     let file = File::create(token_path).map_err(|e| e.to_string())?;
@@ -173,8 +405,8 @@ async fn save_auth_token(
 }
 
 #[tauri::command]
-async fn load_auth_token(app_handle: tauri::AppHandle) -> Result<Option<AuthToken>, String> {
-    let data_dir = get_data_dir(&app_handle);
+async fn load_auth_token(app_handle: tauri::AppHandle, profile: Option<String>) -> Result<Option<AuthToken>, String> {
+    let data_dir = profiles::resolve_profile_dir(&app_handle, profile.as_deref())?;
     let token_path = data_dir.join("auth_token.json");
     if !token_path.exists() {
         return Ok(None);
@@ -186,13 +418,30 @@ async fn load_auth_token(app_handle: tauri::AppHandle) -> Result<Option<AuthToke
     Ok(Some(auth_token))
 }
 
+/// Revokes and deletes a token with the broader scopes of earlier versions.
+async fn sign_out_broad_token(app_handle: &tauri::AppHandle, profile: Option<&str>, token: &AuthToken) -> Result<(), String> {
+    // Revoking drops the broad grant on Google's side, so the next consent
+    // screen only asks for the narrow scope
+    println!("Auth token has broader scopes than needed, signing out to re-consent");
+    if let Err(e) = revoke_token(&token.refresh_token).await {
+        eprintln!("{}", e);
+    }
+    let token_path = profiles::resolve_profile_dir(app_handle, profile)?.join("auth_token.json");
+    fs::remove_file(token_path).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn check_auth_token(app_handle: tauri::AppHandle) -> Result<bool, String> {
-    if let Some(token) = load_auth_token(app_handle.clone()).await? {
+    if let Some(token) = load_auth_token(app_handle.clone(), None).await? {
+        if !has_narrow_scope(&token) {
+            sign_out_broad_token(&app_handle, None, &token).await?;
+            return Ok(false);
+        }
+
         let now = Utc::now().timestamp() as u64;
         if now >= token.expiry {
             // Token is expired, try to refresh it
-            match refresh_token(app_handle).await {
+            match refresh_token(app_handle, None).await {
                 Ok(_) => Ok(true),
                 Err(_) => Ok(false)
             }
@@ -255,6 +504,7 @@ async fn exchange_code_for_tokens(_window: tauri::Window, app_handle: tauri::App
         .to_string();
     let expires_in = token_response["expires_in"].as_u64()
         .ok_or("Expires in not found in response")?;
+    let scope = token_response["scope"].as_str().map(String::from);
 
     let now = chrono::Utc::now();
     let expiry = now.timestamp() as u64 + expires_in;
@@ -263,12 +513,13 @@ async fn exchange_code_for_tokens(_window: tauri::Window, app_handle: tauri::App
         access_token,
         refresh_token,
         expiry,
+        scope,
     })
 }
 
 #[tauri::command]
-async fn refresh_token(app_handle: tauri::AppHandle) -> Result<AuthToken, String> {
-    let current_token = load_auth_token(app_handle.clone()).await?
+async fn refresh_token(app_handle: tauri::AppHandle, profile: Option<String>) -> Result<AuthToken, String> {
+    let current_token = load_auth_token(app_handle.clone(), profile.clone()).await?
         .ok_or("No token found")?;
 
     let (client_id, client_secret, auth_uri, token_uri) = get_oauth_config()?;
@@ -292,9 +543,19 @@ async fn refresh_token(app_handle: tauri::AppHandle) -> Result<AuthToken, String
             .map(|rt| rt.secret().to_string())
             .unwrap_or_else(|| current_token.refresh_token.clone()),
         expiry: Utc::now().timestamp() as u64 + token_result.expires_in().unwrap_or_default().as_secs(),
+        scope: token_result.scopes()
+            .map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect::<Vec<_>>().join(" "))
+            .or_else(|| current_token.scope.clone()),
     };
 
-    save_auth_token(app_handle, new_token.access_token.clone(), new_token.refresh_token.clone(), new_token.expiry).await?;
+    save_auth_token(
+        app_handle,
+        new_token.access_token.clone(),
+        new_token.refresh_token.clone(),
+        new_token.expiry,
+        new_token.scope.clone(),
+        profile,
+    ).await?;
 
     Ok(new_token)
 }
@@ -305,16 +566,16 @@ fn is_dev() -> bool {
 }
 
 #[tauri::command]
-async fn save_sheet_id(app_handle: tauri::AppHandle, sheet_id: String) -> Result<(), String> {
-    let data_dir = get_data_dir(&app_handle);
+async fn save_sheet_id(app_handle: tauri::AppHandle, sheet_id: String, profile: Option<String>) -> Result<(), String> {
+    let data_dir = profiles::resolve_profile_dir(&app_handle, profile.as_deref())?;
     fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
     let path = data_dir.join("sheet_id.txt");
     fs::write(path, sheet_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn load_sheet_id(app_handle: tauri::AppHandle) -> Result<Option<String>, String> {
-    let data_dir = get_data_dir(&app_handle);
+async fn load_sheet_id(app_handle: tauri::AppHandle, profile: Option<String>) -> Result<Option<String>, String> {
+    let data_dir = profiles::resolve_profile_dir(&app_handle, profile.as_deref())?;
     let path = data_dir.join("sheet_id.txt");
     if path.exists() {
         fs::read_to_string(path).map(Some).map_err(|e| e.to_string())
@@ -324,17 +585,16 @@ async fn load_sheet_id(app_handle: tauri::AppHandle) -> Result<Option<String>, S
 }
 
 #[tauri::command]
-async fn get_or_create_new_sheet(app_handle: tauri::AppHandle, title: String) -> Result<String, String> {
+async fn get_or_create_new_sheet(
+    app_handle: tauri::AppHandle,
+    title: String,
+    folder_id: Option<String>,
+    profile: Option<String>,
+) -> Result<String, String> {
     // Try to load existing sheet ID
-    if let Some(sheet_id) = load_sheet_id(app_handle.clone()).await? {
+    if let Some(sheet_id) = load_sheet_id(app_handle.clone(), profile.clone()).await? {
         // Check if the sheet still exists
-        let mut auth_token = load_auth_token(app_handle.clone()).await?
-            .ok_or("No auth token found")?;
-
-        // Check if token is expired and refresh if necessary
-        if Utc::now().timestamp() as u64 >= auth_token.expiry {
-            auth_token = refresh_token(app_handle.clone()).await?;
-        }
+        let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
 
         let client = Client::new();
         let response = client
@@ -350,13 +610,7 @@ async fn get_or_create_new_sheet(app_handle: tauri::AppHandle, title: String) ->
     }
 
     // If we reach here, we need to create a new sheet
-    let mut auth_token = load_auth_token(app_handle.clone()).await?
-        .ok_or("No auth token found")?;
-
-    // Check if token is expired and refresh if necessary
-    if Utc::now().timestamp() as u64 >= auth_token.expiry {
-        auth_token = refresh_token(app_handle.clone()).await?;
-    }
+    let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
 
     let client = Client::new();
     let response = client
@@ -414,8 +668,7 @@ async fn get_or_create_new_sheet(app_handle: tauri::AppHandle, title: String) ->
     }
 
     // Create the other three sheets
-    let other_sheets = ["SummaryBySession", "DetailsSessions", "DetailsSessionEvents"];
-    for sheet_name in other_sheets.iter() {
+    for sheet_name in drive::SHEET_TAB_NAMES.iter().skip(1) {
         let add_sheet_request = json!({
             "requests": [{
                 "addSheet": {
@@ -446,8 +699,18 @@ async fn get_or_create_new_sheet(app_handle: tauri::AppHandle, title: String) ->
         }
     }
 
+    // New spreadsheets land in the Drive root unless a target folder was chosen
+    let folder_id = match folder_id {
+        Some(folder_id) => Some(folder_id),
+        None => drive::load_sheet_folder_id(app_handle.clone(), profile.clone()).await?,
+    };
+    if let Some(folder_id) = folder_id {
+        drive::move_file_to_folder(&client, &auth_token.access_token, &spreadsheet_id, &folder_id).await?;
+        println!("Moved sheet {} to folder {}", spreadsheet_id, folder_id);
+    }
+
     // Save the new sheet ID
-    save_sheet_id(app_handle, spreadsheet_id.clone()).await?;
+    save_sheet_id(app_handle, spreadsheet_id.clone(), profile).await?;
 
     Ok(spreadsheet_id)
 }
@@ -457,14 +720,9 @@ async fn create_sheet_if_not_exists(
     app_handle: tauri::AppHandle,
     spreadsheet_id: String,
     sheet_name: String,
+    profile: Option<String>,
 ) -> Result<(), String> {
-    let mut auth_token = load_auth_token(app_handle.clone()).await?
-        .ok_or("No auth token found")?;
-
-    // Check if token is expired and refresh if necessary
-    if Utc::now().timestamp() as u64 >= auth_token.expiry {
-        auth_token = refresh_token(app_handle.clone()).await?;
-    }
+    let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
 
     let client = Client::new();
     let request_body = json!({
@@ -505,18 +763,13 @@ async fn write_data_to_sheet(
     app_handle: tauri::AppHandle,
     sheet_id: String,
     sheet_name: String,
-    data: Vec<Vec<String>>
+    data: Vec<Vec<String>>,
+    profile: Option<String>,
 ) -> Result<(), String> {
     // First, try to create the sheet (this will do nothing if it already exists)
-    create_sheet_if_not_exists(app_handle.clone(), sheet_id.clone(), sheet_name.clone()).await?;
+    create_sheet_if_not_exists(app_handle.clone(), sheet_id.clone(), sheet_name.clone(), profile.clone()).await?;
 
-    let mut auth_token = load_auth_token(app_handle.clone()).await?
-        .ok_or("No auth token found")?;
-
-    // Check if token is expired and refresh if necessary
-    if Utc::now().timestamp() as u64 >= auth_token.expiry {
-        auth_token = refresh_token(app_handle.clone()).await?;
-    }
+    let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
 
     let client = Client::new();
     let request_body = json!({
@@ -546,14 +799,236 @@ async fn write_data_to_sheet(
 }
 
 #[tauri::command]
-async fn get_sheet_id(app_handle: tauri::AppHandle) -> Result<Option<String>, String> {
-    load_sheet_id(app_handle).await
+async fn get_sheet_id(app_handle: tauri::AppHandle, profile: Option<String>) -> Result<Option<String>, String> {
+    load_sheet_id(app_handle, profile).await
+}
+
+/// A current token of `profile`, or of the active profile when `None`.
+async fn get_valid_auth_token(app_handle: &tauri::AppHandle, profile: Option<&str>) -> Result<AuthToken, String> {
+    let auth_token = load_auth_token(app_handle.clone(), profile.map(str::to_string)).await?
+        .ok_or("No auth token found")?;
+
+    if !has_narrow_scope(&auth_token) {
+        sign_out_broad_token(app_handle, profile, &auth_token).await?;
+        return Err("Sign in with Google again to grant ClockBlocks its narrower Drive access".to_string());
+    }
+
+    // Check if token is expired and refresh if necessary
+    if Utc::now().timestamp() as u64 >= auth_token.expiry {
+        return refresh_token(app_handle.clone(), profile.map(str::to_string)).await;
+    }
+
+    Ok(auth_token)
+}
+
+async fn batch_update_sheet(client: &Client, access_token: &str, spreadsheet_id: &str, requests: Vec<Value>) -> Result<Value, String> {
+    let response = client
+        .post(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}:batchUpdate",
+            spreadsheet_id
+        ))
+        .bearer_auth(access_token)
+        .json(&json!({ "requests": requests }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(format!("Batch update failed: {} - {}", status, error_body));
+    }
+
+    response.json().await.map_err(|e| e.to_string())
+}
+
+const CHARTS_SHEET_NAME: &str = "Charts";
+const CHART_SOURCE_SHEET_NAME: &str = "SummaryByDate";
+
+/// Builds a stacked column chart with one series per column of the SummaryByDate tab
+/// (every time box plus Break), using the date column as the domain.
+fn build_daily_time_chart_spec(source_sheet_id: i64, row_count: usize, column_count: usize) -> Value {
+    let source_range = |column: usize| json!({
+        "sourceRange": {
+            "sources": [{
+                "sheetId": source_sheet_id,
+                "startRowIndex": 0,
+                "endRowIndex": row_count,
+                "startColumnIndex": column,
+                "endColumnIndex": column + 1
+            }]
+        }
+    });
+
+    let series: Vec<Value> = (1..column_count)
+        .map(|column| json!({
+            "series": source_range(column),
+            "targetAxis": "LEFT_AXIS"
+        }))
+        .collect();
+
+    json!({
+        "title": "Daily time per box",
+        "basicChart": {
+            "chartType": "COLUMN",
+            "stackedType": "STACKED",
+            "legendPosition": "BOTTOM_LEGEND",
+            "headerCount": 1,
+            "axis": [
+                { "position": "BOTTOM_AXIS", "title": "Date" },
+                { "position": "LEFT_AXIS", "title": "Time (hh:mm:ss)" }
+            ],
+            "domains": [{ "domain": source_range(0) }],
+            "series": series
+        }
+    })
+}
+
+#[tauri::command]
+async fn update_charts(app_handle: tauri::AppHandle, sheet_id: String, profile: Option<String>) -> Result<(), String> {
+    create_sheet_if_not_exists(app_handle.clone(), sheet_id.clone(), CHARTS_SHEET_NAME.to_string(), profile.clone()).await?;
+
+    let auth_token = get_valid_auth_token(&app_handle, profile.as_deref()).await?;
+    let client = Client::new();
+
+    // Look up the numeric ids of the source and chart tabs and any charts already placed
+    let response = client
+        .get(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}?fields=sheets(properties(sheetId,title),charts(chartId))",
+            sheet_id
+        ))
+        .bearer_auth(&auth_token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch spreadsheet: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to fetch spreadsheet: {}", response.status()));
+    }
+
+    let spreadsheet: Value = response.json().await.map_err(|e| e.to_string())?;
+    let sheets = spreadsheet["sheets"].as_array().cloned().unwrap_or_default();
+    let find_sheet = |title: &str| sheets.iter()
+        .find(|sheet| sheet["properties"]["title"].as_str() == Some(title))
+        .cloned();
+
+    let source_sheet = find_sheet(CHART_SOURCE_SHEET_NAME)
+        .ok_or(format!("Sheet {} not found", CHART_SOURCE_SHEET_NAME))?;
+    let charts_sheet = find_sheet(CHARTS_SHEET_NAME)
+        .ok_or(format!("Sheet {} not found", CHARTS_SHEET_NAME))?;
+    let source_sheet_id = source_sheet["properties"]["sheetId"].as_i64()
+        .ok_or("Source sheet ID not found")?;
+    let charts_sheet_id = charts_sheet["properties"]["sheetId"].as_i64()
+        .ok_or("Charts sheet ID not found")?;
+
+    // Size the chart to the data currently in the source tab
+    let response = client
+        .get(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}",
+            sheet_id,
+            CHART_SOURCE_SHEET_NAME
+        ))
+        .bearer_auth(&auth_token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", CHART_SOURCE_SHEET_NAME, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to read {}: {}", CHART_SOURCE_SHEET_NAME, response.status()));
+    }
+
+    let values: Value = response.json().await.map_err(|e| e.to_string())?;
+    let rows = values["values"].as_array().cloned().unwrap_or_default();
+    let row_count = rows.len();
+    let column_count = rows.first().and_then(|row| row.as_array()).map(|row| row.len()).unwrap_or(0);
+
+    if row_count < 2 || column_count < 2 {
+        println!("Not enough data in {} to draw a chart", CHART_SOURCE_SHEET_NAME);
+        return Ok(());
+    }
+
+    let spec = build_daily_time_chart_spec(source_sheet_id, row_count, column_count);
+
+    // Keep a single chart on the Charts tab: update it in place if it exists, otherwise add it
+    let existing_chart_id = charts_sheet["charts"].as_array()
+        .and_then(|charts| charts.first())
+        .and_then(|chart| chart["chartId"].as_i64());
+
+    let request = match existing_chart_id {
+        Some(chart_id) => json!({
+            "updateChartSpec": {
+                "chartId": chart_id,
+                "spec": spec
+            }
+        }),
+        None => json!({
+            "addChart": {
+                "chart": {
+                    "spec": spec,
+                    "position": {
+                        "overlayPosition": {
+                            "anchorCell": {
+                                "sheetId": charts_sheet_id,
+                                "rowIndex": 0,
+                                "columnIndex": 0
+                            },
+                            "widthPixels": 900,
+                            "heightPixels": 500
+                        }
+                    }
+                }
+            }
+        }),
+    };
+
+    batch_update_sheet(&client, &auth_token.access_token, &sheet_id, vec![request]).await?;
+    println!("Updated charts in sheet {}", sheet_id);
+
+    Ok(())
 }
 
 fn main() {
+    let context = tauri::generate_context!();
+
+    // A second launch hands its arguments to the running instance and exits,
+    // so only one instance writes to the database
+    let args: Vec<String> = env::args().skip(1).collect();
+    let instance_listener = match single_instance::acquire(&get_data_dir_for(context.config()), &args) {
+        Ok(single_instance::Instance::Primary(listener)) => Some(listener),
+        Ok(single_instance::Instance::Secondary(result)) => {
+            if let Err(e) = result {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(single_instance::Instance::Unguarded) => {
+            println!("No single instance guard on this platform, later launches start their own instance");
+            None
+        }
+        Err(e) => {
+            println!("Failed to check for another instance: {}", e);
+            None
+        }
+    };
+    // The deep link plugin's listener on Windows and Linux needs the
+    // identifier. Its own check for another instance runs after ours, so it
+    // finds none and links still reach handle_launch_args through ours
+    tauri_plugin_deep_link::prepare(&context.config().tauri.bundle.identifier);
+
     tauri::Builder::default()
         .plugin(tauri_plugin_sql::Builder::default().build())
-        .setup(|app| {
+        .system_tray(SystemTray::new().with_menu(build_tray_menu(&[], &timer::TimerState::default())))
+        .on_system_tray_event(handle_tray_event)
+        .on_window_event(|event| {
+            // The timer runs in the webview, so closing the window only hides
+            // it and the tray's Quit ends the app
+            if let WindowEvent::CloseRequested { api, .. } = event.event() {
+                let _ = event.window().hide();
+                api.prevent_close();
+            }
+        })
+        .setup(move |app| {
             // Set up a handler for the "open-external" event
             let handle = app.handle();
             let handle_clone = handle.clone();
@@ -563,11 +1038,66 @@ fn main() {
                 }
             });
 
+            let (webhook_sender, webhook_receiver) = channel();
             let app_state = Arc::new(AppState {
                 pkce_verifier: Mutex::new(None),
+                ics_feed: Mutex::new(None),
+                timer: Mutex::new(None),
+                timer_reported: Condvar::new(),
+                control_api: Mutex::new(None),
+                event_streams: Mutex::new(Vec::new()),
+                webhook_queue: Mutex::new(webhook_sender),
+                webhook_log: Mutex::new(webhooks::load_log(&handle)),
+                idle_period: Mutex::new(None),
             });
 
             app.manage(app_state);
+
+            // The webview can't pick up a session clockblocks-cli started
+            // while the app was closed, so it ends here
+            if db::get_db_path(&handle).exists() {
+                match db::open_connection(&handle).and_then(|mut conn| cli_timer::end(&mut conn)) {
+                    Ok(Some(running)) => println!("Ended session {} started from the command line", running.session_id),
+                    Ok(None) => {}
+                    Err(e) => println!("Failed to end the command line session: {}", e),
+                }
+            }
+
+            if let Some(listener) = instance_listener {
+                let handle = handle.clone();
+                single_instance::serve(listener, move |args| handle_launch_args(&handle, &args));
+            }
+            // Arguments of this launch, such as a link that started the app
+            if args.iter().any(|arg| arg.starts_with("--") || deep_link::is_deep_link(arg)) {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_launch_args(&handle, &args) {
+                        println!("Failed to handle launch arguments: {}", e);
+                    }
+                });
+            }
+            // Registers the scheme with the OS and receives links on macOS,
+            // which hands them to the running app instead of launching it
+            let link_handle = handle.clone();
+            let registered = tauri_plugin_deep_link::register(deep_link::SCHEME, move |link| {
+                let handle = link_handle.clone();
+                thread::spawn(move || {
+                    if let Err(e) = deep_link::parse(&link).and_then(|link| deep_link::handle(&handle, &link)) {
+                        println!("Failed to open {}: {}", link, e);
+                    }
+                });
+            });
+            if let Err(e) = registered {
+                println!("Failed to register {}:// links: {}", deep_link::SCHEME, e);
+            }
+            start_tray_updates(handle.clone());
+            shortcuts::register_saved_shortcuts(&handle);
+            idle::start_monitor(handle.clone());
+            pomodoro::start(handle.clone());
+            webhooks::start_worker(handle.clone(), webhook_receiver);
+            control_api::start_if_enabled(&handle);
+            ical::start_if_enabled(&handle);
+            backups::start_backup_schedule(handle);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -584,7 +1114,63 @@ fn main() {
             write_data_to_sheet,
             create_sheet_if_not_exists,
             get_sheet_id,
+            update_charts,
+            sheet_sync::preview_sheet_corrections,
+            sheet_sync::apply_sheet_corrections,
+            sheet_sync::record_sheet_sync,
+            sheet_sync::report_sync_result,
+            drive::list_spreadsheets,
+            drive::list_drive_folders,
+            drive::select_spreadsheet,
+            drive::save_sheet_folder_id,
+            drive::load_sheet_folder_id,
+            profiles::list_profiles,
+            profiles::get_active_profile,
+            profiles::create_profile,
+            profiles::set_profile_time_boxes,
+            profiles::set_active_profile,
+            profiles::delete_profile,
+            csv_export::export_csv,
+            json_backup::export_json,
+            json_backup::import_json,
+            ical::export_ics,
+            ical::start_ics_feed,
+            ical::stop_ics_feed,
+            importers::toggl::import_toggl_csv,
+            importers::clockify::import_clockify,
+            importers::harvest::import_harvest_csv,
+            timewarrior::import_timewarrior,
+            timewarrior::export_timewarrior,
+            timewarrior::timewarrior_data_dir,
+            timesheet::export_org,
+            timesheet::export_markdown,
+            xlsx_export::export_xlsx,
+            pdf_export::export_pdf,
+            pdf_export::load_invoice_settings,
+            pdf_export::save_invoice_settings,
+            backups::list_backups,
+            backups::backup_database,
+            backups::restore_backup,
+            timer::report_timer_state,
+            control_api::get_control_api,
+            control_api::enable_control_api,
+            control_api::disable_control_api,
+            control_api::regenerate_control_api_token,
+            webhooks::list_webhooks,
+            webhooks::save_webhook,
+            webhooks::delete_webhook,
+            webhooks::test_webhook,
+            webhooks::get_webhook_log,
+            shortcuts::get_shortcuts,
+            shortcuts::save_shortcuts,
+            idle::get_idle_settings,
+            idle::save_idle_settings,
+            idle::get_idle_period,
+            idle::resolve_idle_period,
+            pomodoro::get_pomodoro_settings,
+            pomodoro::save_pomodoro_settings,
+            pomodoro::get_pomodoro_state,
         ])
-        .run(tauri::generate_context!())
+        .run(context)
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
@@ -0,0 +1,25 @@
+//! Where the app keeps its files. The app passes the identifier from its Tauri
+//! config, `clockblocks-cli` the one `build.rs` reads from the same file.
+
+use std::path::PathBuf;
+
+/// `identifier` in `tauri.conf.json`, which names the app's directories
+pub const APP_IDENTIFIER: &str = env!("CLOCKBLOCKS_IDENTIFIER");
+
+/// The app config directory, which the SQL plugin resolves `sqlite:` paths
+/// against.
+pub fn config_dir(identifier: &str) -> Result<PathBuf, String> {
+    let config_dir = dirs_next::config_dir().ok_or("Failed to get config directory")?;
+    Ok(config_dir.join(identifier))
+}
+
+/// The directory of the settings files, a separate one in development.
+pub fn data_dir(identifier: &str) -> Result<PathBuf, String> {
+    if cfg!(debug_assertions) {
+        let local_data_dir = dirs_next::data_local_dir().ok_or("Failed to get local data directory")?;
+        Ok(local_data_dir.join(identifier).join("dev_data"))
+    } else {
+        let data_dir = dirs_next::data_dir().ok_or("Failed to get data directory")?;
+        Ok(data_dir.join(identifier))
+    }
+}
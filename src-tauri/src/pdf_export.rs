@@ -0,0 +1,500 @@
+//! PDF timesheets and invoices for a date range and a selection of time boxes.
+//! The invoice header and hourly rates are kept per profile, next to the token.
+
+use crate::db;
+use crate::profiles;
+use crate::report::{self, DateRange, ReportData};
+use chrono::{Local, NaiveDate};
+use printpdf::{BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+
+const INVOICE_SETTINGS_FILE_NAME: &str = "invoice_settings.json";
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 6.0;
+const FONT_SIZE: f32 = 10.0;
+const MAX_NAME_LENGTH: usize = 40;
+
+/// Header of an invoice and the rates to bill, saved from the settings page.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InvoiceSettings {
+    /// Name and address of whoever sends the invoice, one line each
+    pub sender: String,
+    /// Name and address of the client, one line each
+    pub client: String,
+    pub invoice_number: String,
+    /// YYYY-MM-DD; today when empty
+    pub issue_date: Option<String>,
+    pub due_date: Option<String>,
+    pub currency: String,
+    /// Hourly rate per time box id; boxes without a rate are listed without amount
+    pub hourly_rates: HashMap<String, f64>,
+    pub notes: String,
+}
+
+fn get_invoice_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    profiles::get_profile_dir(app_handle).join(INVOICE_SETTINGS_FILE_NAME)
+}
+
+fn read_invoice_settings(app_handle: &tauri::AppHandle) -> Result<InvoiceSettings, String> {
+    let path = get_invoice_settings_path(app_handle);
+    if !path.exists() {
+        return Ok(InvoiceSettings::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid invoice settings: {}", e))
+}
+
+#[tauri::command]
+pub fn load_invoice_settings(app_handle: tauri::AppHandle) -> Result<InvoiceSettings, String> {
+    read_invoice_settings(&app_handle)
+}
+
+#[tauri::command]
+pub fn save_invoice_settings(app_handle: tauri::AppHandle, settings: InvoiceSettings) -> Result<(), String> {
+    for date in [&settings.issue_date, &settings.due_date].into_iter().flatten() {
+        if !date.is_empty() {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| format!("Invalid date {}: {}", date, e))?;
+        }
+    }
+    if settings.hourly_rates.values().any(|rate| !rate.is_finite() || *rate < 0.0) {
+        return Err("Hourly rates must be zero or more".to_string());
+    }
+
+    let path = get_invoice_settings_path(&app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+/// Time on one box on one day, or over the whole range for the totals.
+#[derive(Debug, PartialEq)]
+pub struct TimesheetLine {
+    pub date: Option<NaiveDate>,
+    pub time_box: String,
+    pub seconds: i64,
+    pub rate: Option<f64>,
+}
+
+impl TimesheetLine {
+    pub fn hours(&self) -> f64 {
+        self.seconds as f64 / 3600.0
+    }
+
+    /// Rounded to cents.
+    pub fn amount(&self) -> Option<f64> {
+        self.rate.map(|rate| (self.hours() * rate * 100.0).round() / 100.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Timesheet {
+    pub days: Vec<TimesheetLine>,
+    pub totals: Vec<TimesheetLine>,
+}
+
+impl Timesheet {
+    /// Hours per day and box for the selected boxes, all boxes when `None`.
+    /// Break time isn't billable and is left out.
+    pub fn build(data: &ReportData, time_box_ids: Option<&[String]>, rates: &HashMap<String, f64>) -> Self {
+        let selected = |id: &String| time_box_ids.map_or(true, |ids| ids.contains(id));
+        let mut names: Vec<String> = Vec::new();
+        let mut rates_by_name: HashMap<String, f64> = HashMap::new();
+        for time_box in data.time_boxes.iter().filter(|time_box| selected(&time_box.id)) {
+            if !names.contains(&time_box.name) {
+                names.push(time_box.name.clone());
+            }
+            if let Some(rate) = rates.get(&time_box.id) {
+                rates_by_name.entry(time_box.name.clone()).or_insert(*rate);
+            }
+        }
+
+        let mut by_date = report::durations_by_date(data);
+        by_date.sort_by_key(|(date, _)| *date);
+
+        let mut days = Vec::new();
+        for (date, durations) in by_date {
+            for name in &names {
+                let seconds = durations.get(name).copied().unwrap_or(0);
+                if seconds > 0 {
+                    days.push(TimesheetLine {
+                        date: Some(date),
+                        time_box: name.clone(),
+                        seconds,
+                        rate: rates_by_name.get(name).copied(),
+                    });
+                }
+            }
+        }
+
+        let totals = names
+            .iter()
+            .map(|name| TimesheetLine {
+                date: None,
+                time_box: name.clone(),
+                seconds: days.iter().filter(|line| line.time_box == *name).map(|line| line.seconds).sum(),
+                rate: rates_by_name.get(name).copied(),
+            })
+            .filter(|line| line.seconds > 0)
+            .collect();
+
+        Timesheet { days, totals }
+    }
+
+    pub fn total_seconds(&self) -> i64 {
+        self.totals.iter().map(|line| line.seconds).sum()
+    }
+
+    pub fn total_amount(&self) -> Option<f64> {
+        let amounts: Vec<f64> = self.totals.iter().filter_map(|line| line.amount()).collect();
+        if amounts.is_empty() {
+            None
+        } else {
+            Some(amounts.iter().sum())
+        }
+    }
+
+    fn has_rates(&self) -> bool {
+        self.totals.iter().any(|line| line.rate.is_some())
+    }
+}
+
+/// Helvetica advance widths in 1/1000 em for the characters of the right
+/// aligned number columns; anything else is counted as a digit.
+fn text_width(text: &str, font_size: f32) -> f32 {
+    let units: u32 = text.chars().map(|c| match c {
+        '.' | ',' | ':' | ' ' => 278,
+        '-' => 333,
+        _ => 556,
+    }).sum();
+    // Points to millimetres
+    units as f32 / 1000.0 * font_size * 25.4 / 72.0
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_length - 3).collect();
+    truncated.push_str("...");
+    truncated
+}
+
+fn format_hours(seconds: i64) -> String {
+    format!("{:.2}", seconds as f64 / 3600.0)
+}
+
+fn format_money(amount: f64) -> String {
+    format!("{:.2}", amount)
+}
+
+fn format_setting_date(date: &Option<String>) -> Option<String> {
+    date.as_deref()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .map(|date| report::format_date(&date))
+}
+
+/// Writes top to bottom, starting a new page when the bottom margin is reached.
+struct PageWriter {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    y: f32,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+}
+
+impl PageWriter {
+    fn new(title: &str) -> Result<Self, String> {
+        let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let regular = doc.add_builtin_font(BuiltinFont::Helvetica).map_err(|e| e.to_string())?;
+        let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold).map_err(|e| e.to_string())?;
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(PageWriter { doc, layer, y: PAGE_HEIGHT - MARGIN, regular, bold })
+    }
+
+    fn advance(&mut self, height: f32) {
+        self.y -= height;
+        if self.y < MARGIN {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&self, text: &str, x: f32, size: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    fn text_right(&self, text: &str, right: f32, bold: bool) {
+        self.text(text, right - text_width(text, FONT_SIZE), FONT_SIZE, bold);
+    }
+
+    fn rule(&mut self) {
+        let y = self.y + LINE_HEIGHT - 2.0;
+        self.layer.set_outline_thickness(0.5);
+        self.layer.add_line(Line {
+            points: vec![(Point::new(Mm(MARGIN), Mm(y)), false), (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(y)), false)],
+            is_closed: false,
+        });
+    }
+
+    fn save(self) -> Result<Vec<u8>, String> {
+        let mut writer = BufWriter::new(Vec::new());
+        self.doc.save(&mut writer).map_err(|e| e.to_string())?;
+        writer.into_inner().map_err(|e| e.to_string())
+    }
+}
+
+const DATE_X: f32 = MARGIN;
+const TIME_BOX_X: f32 = 50.0;
+const HOURS_RIGHT: f32 = 130.0;
+const RATE_RIGHT: f32 = 160.0;
+const AMOUNT_RIGHT: f32 = PAGE_WIDTH - MARGIN;
+
+/// Headers of the daily table, or of the totals per box when `by_date` is off.
+fn write_table_header(writer: &mut PageWriter, by_date: bool, has_rates: bool, currency: &str) {
+    if by_date {
+        writer.text("Date", DATE_X, FONT_SIZE, true);
+        writer.text("Time box", TIME_BOX_X, FONT_SIZE, true);
+    } else {
+        writer.text("Time box", DATE_X, FONT_SIZE, true);
+    }
+    writer.text_right("Hours", HOURS_RIGHT, true);
+    if has_rates {
+        writer.text_right("Rate", RATE_RIGHT, true);
+        let amount = if currency.is_empty() { "Amount".to_string() } else { format!("Amount ({})", currency) };
+        writer.text_right(&amount, AMOUNT_RIGHT, true);
+    }
+    writer.advance(LINE_HEIGHT);
+    writer.rule();
+}
+
+fn write_line(writer: &mut PageWriter, line: &TimesheetLine, has_rates: bool) {
+    match line.date {
+        Some(date) => {
+            writer.text(&report::format_date(&date), DATE_X, FONT_SIZE, false);
+            writer.text(&truncate(&line.time_box, MAX_NAME_LENGTH), TIME_BOX_X, FONT_SIZE, false);
+        }
+        None => writer.text(&truncate(&line.time_box, MAX_NAME_LENGTH), DATE_X, FONT_SIZE, false),
+    }
+    writer.text_right(&format_hours(line.seconds), HOURS_RIGHT, false);
+    if has_rates {
+        if let (Some(rate), Some(amount)) = (line.rate, line.amount()) {
+            writer.text_right(&format_money(rate), RATE_RIGHT, false);
+            writer.text_right(&format_money(amount), AMOUNT_RIGHT, false);
+        }
+    }
+    writer.advance(LINE_HEIGHT);
+}
+
+fn write_lines(writer: &mut PageWriter, text: &str, x: f32) {
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        writer.text(line.trim(), x, FONT_SIZE, false);
+        writer.advance(LINE_HEIGHT - 1.0);
+    }
+}
+
+/// Lays out the timesheet, as an invoice with header, amounts and notes when
+/// `settings` are given.
+pub fn render_pdf(timesheet: &Timesheet, range: &DateRange, settings: Option<&InvoiceSettings>) -> Result<Vec<u8>, String> {
+    let title = if settings.is_some() { "Invoice" } else { "Timesheet" };
+    let mut writer = PageWriter::new(title)?;
+    let has_rates = settings.is_some() && timesheet.has_rates();
+    let currency = settings.map(|settings| settings.currency.as_str()).unwrap_or_default();
+
+    writer.text(title, MARGIN, 20.0, true);
+    writer.advance(LINE_HEIGHT * 2.0);
+
+    if let Some(settings) = settings {
+        let header_y = writer.y;
+        write_lines(&mut writer, &settings.sender, MARGIN);
+        let sender_end = writer.y;
+
+        // Invoice details on the right of the sender
+        writer.y = header_y;
+        let issue_date = format_setting_date(&settings.issue_date)
+            .unwrap_or_else(|| report::format_date(&Local::now().date_naive()));
+        let mut details = vec![("Invoice no.", settings.invoice_number.clone()), ("Issued", issue_date)];
+        if let Some(due_date) = format_setting_date(&settings.due_date) {
+            details.push(("Due", due_date));
+        }
+        for (label, value) in details {
+            writer.text(label, 120.0, FONT_SIZE, true);
+            writer.text(&value, 150.0, FONT_SIZE, false);
+            writer.advance(LINE_HEIGHT - 1.0);
+        }
+        writer.y = writer.y.min(sender_end);
+        writer.advance(LINE_HEIGHT);
+
+        if !settings.client.trim().is_empty() {
+            writer.text("Bill to", MARGIN, FONT_SIZE, true);
+            writer.advance(LINE_HEIGHT - 1.0);
+            write_lines(&mut writer, &settings.client, MARGIN);
+            writer.advance(LINE_HEIGHT);
+        }
+    }
+
+    writer.text(&format!("Period: {}", range.title()), MARGIN, FONT_SIZE, false);
+    writer.advance(LINE_HEIGHT * 2.0);
+
+    write_table_header(&mut writer, true, has_rates, currency);
+    if timesheet.days.is_empty() {
+        writer.text("No time tracked in this period.", DATE_X, FONT_SIZE, false);
+        writer.advance(LINE_HEIGHT);
+    }
+    for line in &timesheet.days {
+        write_line(&mut writer, line, has_rates);
+    }
+    writer.advance(LINE_HEIGHT);
+
+    write_table_header(&mut writer, false, has_rates, currency);
+    for line in &timesheet.totals {
+        write_line(&mut writer, line, has_rates);
+    }
+    writer.rule();
+    writer.text("Total", DATE_X, FONT_SIZE, true);
+    writer.text_right(&format_hours(timesheet.total_seconds()), HOURS_RIGHT, true);
+    if has_rates {
+        if let Some(total_amount) = timesheet.total_amount() {
+            writer.text_right(&format_money(total_amount), AMOUNT_RIGHT, true);
+        }
+    }
+    writer.advance(LINE_HEIGHT * 2.0);
+
+    if let Some(settings) = settings {
+        write_lines(&mut writer, &settings.notes, MARGIN);
+    }
+
+    writer.save()
+}
+
+/// Renders a timesheet, or an invoice using the saved invoice settings, for the
+/// selected boxes (all when `time_box_ids` is empty or missing).
+#[tauri::command]
+pub async fn export_pdf(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    time_box_ids: Option<Vec<String>>,
+    invoice: bool,
+) -> Result<Option<String>, String> {
+    let range = DateRange::parse(from, to)?;
+    let settings = if invoice { Some(read_invoice_settings(&app_handle)?) } else { None };
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let file_name = match &settings {
+                Some(settings) if !settings.invoice_number.is_empty() => format!("invoice-{}.pdf", settings.invoice_number),
+                Some(_) => "invoice.pdf".to_string(),
+                None => format!("timesheet-{}.pdf", Local::now().format("%Y-%m-%d")),
+            };
+            match FileDialogBuilder::new()
+                .set_title(if invoice { "Export invoice" } else { "Export timesheet" })
+                .set_file_name(&file_name)
+                .add_filter("PDF", &["pdf"])
+                .save_file()
+            {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let conn = db::open_connection(&app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    let time_box_ids = time_box_ids.filter(|ids| !ids.is_empty());
+    let rates = settings.as_ref().map(|settings| settings.hourly_rates.clone()).unwrap_or_default();
+    let timesheet = Timesheet::build(&data, time_box_ids.as_deref(), &rates);
+
+    let bytes = render_pdf(&timesheet, &range, settings.as_ref())?;
+    fs::write(&path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    println!("Exported {} to {}", if invoice { "invoice" } else { "timesheet" }, path.display());
+    Ok(Some(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Session, SessionEvent, TimeBox};
+
+    fn time_box(id: &str, name: &str) -> TimeBox {
+        TimeBox { id: id.to_string(), name: name.to_string(), is_hidden: false, is_deleted: false, colour: "#1673FF".to_string() }
+    }
+
+    fn session(id: &str, start: &str, end: &str) -> Session {
+        Session { id: id.to_string(), start_datetime: start.to_string(), end_datetime: Some(end.to_string()), duration: None }
+    }
+
+    fn event(time_box_id: &str, session_id: &str, start: &str, end: &str) -> SessionEvent {
+        SessionEvent {
+            id: format!("{}-{}", session_id, start),
+            time_box_id: time_box_id.to_string(),
+            session_id: session_id.to_string(),
+            start_datetime: start.to_string(),
+            end_datetime: end.to_string(),
+            seconds: 0,
+            pomodoro_phase: None,
+            pomodoro_cycle: None,
+        }
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn bills_rated_boxes_per_day_rounded_to_cents() {
+        // Midday sessions stay on their day in any local time zone
+        let data = ReportData {
+            time_boxes: vec![time_box("b1", "Code"), time_box("b2", "Read"), time_box("b3", "Admin")],
+            sessions: vec![
+                session("s1", "2024-08-01T12:00:00.000Z", "2024-08-01T13:00:00.000Z"),
+                session("s2", "2024-08-02T12:00:00.000Z", "2024-08-02T12:01:00.000Z"),
+            ],
+            session_events: vec![
+                event("b1", "s1", "2024-08-01T12:00:00.000Z", "2024-08-01T12:20:00.000Z"),
+                event("b2", "s1", "2024-08-01T12:20:00.000Z", "2024-08-01T12:50:00.000Z"),
+                event("b3", "s1", "2024-08-01T12:50:00.000Z", "2024-08-01T13:00:00.000Z"),
+                event("b1", "s2", "2024-08-02T12:00:00.000Z", "2024-08-02T12:01:00.000Z"),
+            ],
+        };
+        let rates = HashMap::from([("b1".to_string(), 100.0), ("b3".to_string(), 50.0)]);
+        let selected = ["b1".to_string(), "b2".to_string()];
+
+        let timesheet = Timesheet::build(&data, Some(&selected), &rates);
+        let line = |date: Option<&str>, time_box: &str, seconds: i64, rate: Option<f64>| TimesheetLine {
+            date: date.map(self::date),
+            time_box: time_box.to_string(),
+            seconds,
+            rate,
+        };
+        assert_eq!(
+            timesheet.days,
+            vec![
+                line(Some("2024-08-01"), "Code", 20 * 60, Some(100.0)),
+                line(Some("2024-08-01"), "Read", 30 * 60, None),
+                line(Some("2024-08-02"), "Code", 60, Some(100.0)),
+            ]
+        );
+        assert_eq!(timesheet.totals, vec![line(None, "Code", 21 * 60, Some(100.0)), line(None, "Read", 30 * 60, None)]);
+
+        let amounts: Vec<Option<f64>> = timesheet.days.iter().map(TimesheetLine::amount).collect();
+        assert_eq!(amounts, vec![Some(33.33), None, Some(1.67)]);
+        assert_eq!(timesheet.total_seconds(), 51 * 60);
+        assert_eq!(timesheet.total_amount(), Some(35.0));
+    }
+}
@@ -0,0 +1,464 @@
+//! Pomodoro mode. A box runs for the focus length, then the backend notifies
+//! and switches to the break box, and back to the focus box once the break is
+//! over. Every `long_break_every` focus intervals the break is a long one.
+//!
+//! Switching by hand starts a new focus interval in the chosen box, or leaves
+//! the mode idle when the chosen box is the break box. Cycles are counted per
+//! session. The cycle state is saved so a restart picks up the running
+//! interval, and each completed interval is recorded on the event that held it
+//! through the switch command, as the webview writes the events.
+
+use crate::db;
+use crate::timer::{self, TimerState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tauri::api::notification::Notification;
+use tauri::Manager;
+
+const SETTINGS_FILE_NAME: &str = "pomodoro.json";
+const STATE_FILE_NAME: &str = "pomodoro_state.json";
+const MAX_MINUTES: u32 = 240;
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stored as `focus`, `shortBreak` or `longBreak` in `sessionEvents.pomodoroPhase`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Phase {
+    Focus,
+    ShortBreak,
+    LongBreak,
+}
+
+/// An interval that ran its full length, sent with the switch that ends it.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct CompletedInterval {
+    pub phase: Phase,
+    /// The focus interval's number in the session, which its break shares
+    pub cycle: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PomodoroSettings {
+    pub enabled: bool,
+    pub focus_minutes: u32,
+    pub short_break_minutes: u32,
+    pub long_break_minutes: u32,
+    pub long_break_every: u32,
+    /// The box breaks are spent in, the Rest box when unset
+    pub break_time_box_id: Option<String>,
+}
+
+impl Default for PomodoroSettings {
+    fn default() -> Self {
+        PomodoroSettings {
+            enabled: false,
+            focus_minutes: 25,
+            short_break_minutes: 5,
+            long_break_minutes: 15,
+            long_break_every: 4,
+            break_time_box_id: None,
+        }
+    }
+}
+
+impl PomodoroSettings {
+    fn minutes(&self, phase: Phase) -> u32 {
+        match phase {
+            Phase::Focus => self.focus_minutes,
+            Phase::ShortBreak => self.short_break_minutes,
+            Phase::LongBreak => self.long_break_minutes,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (name, minutes) in [
+            ("focus", self.focus_minutes),
+            ("short break", self.short_break_minutes),
+            ("long break", self.long_break_minutes),
+        ] {
+            if minutes == 0 || minutes > MAX_MINUTES {
+                return Err(format!("The {} length must be between 1 and {} minutes", name, MAX_MINUTES));
+            }
+        }
+        if self.long_break_every == 0 {
+            return Err("Long breaks must come every 1 or more intervals".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PomodoroState {
+    pub session_id: Option<String>,
+    /// `None` while the mode isn't timing the running box
+    pub phase: Option<Phase>,
+    pub time_box_id: Option<String>,
+    pub phase_start: Option<String>,
+    /// The box to go back to after a break
+    pub focus_time_box_id: Option<String>,
+    pub completed_cycles: u32,
+}
+
+fn get_path(app_handle: &tauri::AppHandle, file_name: &str) -> PathBuf {
+    crate::get_data_dir(app_handle).join(file_name)
+}
+
+fn load<T: Default + for<'de> Deserialize<'de>>(app_handle: &tauri::AppHandle, file_name: &str) -> Result<T, String> {
+    let path = get_path(app_handle, file_name);
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid {}: {}", file_name, e))
+}
+
+fn save<T: Serialize>(app_handle: &tauri::AppHandle, file_name: &str, value: &T) -> Result<(), String> {
+    let path = get_path(app_handle, file_name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn break_time_box_id(app_handle: &tauri::AppHandle, settings: &PomodoroSettings) -> Result<String, String> {
+    match &settings.break_time_box_id {
+        Some(id) => Ok(id.clone()),
+        None => timer::break_time_box_id(app_handle),
+    }
+}
+
+/// Whether `time_box_id` is the break box, without creating one.
+fn is_break_time_box(app_handle: &tauri::AppHandle, settings: &PomodoroSettings, time_box_id: &str) -> Result<bool, String> {
+    match &settings.break_time_box_id {
+        Some(id) => Ok(id == time_box_id),
+        None => Ok(timer::find_time_box(app_handle, timer::BREAK_TIME_BOX_NAME)?.is_some_and(|time_box| time_box.id == time_box_id)),
+    }
+}
+
+fn notify(app_handle: &tauri::AppHandle, title: &str, body: &str) {
+    let identifier = app_handle.config().tauri.bundle.identifier.clone();
+    if let Err(e) = Notification::new(identifier).title(title).body(body).show() {
+        println!("Failed to show notification: {}", e);
+    }
+}
+
+/// The box the next interval runs in.
+#[derive(Clone, Debug, PartialEq)]
+enum Target {
+    /// The break box, created if there is none yet
+    Break,
+    TimeBox(String),
+}
+
+/// The switch that ends an interval which ran its full length.
+#[derive(Clone, Debug, PartialEq)]
+struct Transition {
+    completed: CompletedInterval,
+    next: Phase,
+    target: Target,
+}
+
+impl PomodoroState {
+    /// Starts a new interval in the running box when it changed since the last
+    /// tick, which means it was switched by hand.
+    fn follow(
+        &mut self,
+        timer_state: &TimerState,
+        now: DateTime<Utc>,
+        is_break_time_box: impl FnOnce(&str) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        if timer_state.session_id != self.session_id {
+            *self = PomodoroState { session_id: timer_state.session_id.clone(), ..PomodoroState::default() };
+        }
+        if timer_state.active_time_box_id == self.time_box_id {
+            return Ok(());
+        }
+        let Some(active) = timer_state.active_time_box_id.clone() else {
+            return Ok(());
+        };
+        if is_break_time_box(&active)? {
+            self.phase = None;
+        } else {
+            self.phase = Some(Phase::Focus);
+            self.focus_time_box_id = Some(active.clone());
+        }
+        self.time_box_id = Some(active);
+        self.phase_start = timer_state.event_start.clone().or_else(|| Some(db::format_datetime(&now)));
+        Ok(())
+    }
+
+    /// The switch that is due at `now`, if the running interval is over.
+    fn transition(&self, settings: &PomodoroSettings, now: DateTime<Utc>) -> Option<Transition> {
+        let phase = self.phase?;
+        let start = self.phase_start.as_deref().and_then(db::parse_datetime)?;
+        if now - start < chrono::Duration::minutes(i64::from(settings.minutes(phase))) {
+            return None;
+        }
+        match phase {
+            Phase::Focus => {
+                let cycle = self.completed_cycles + 1;
+                let next = if cycle.is_multiple_of(settings.long_break_every) { Phase::LongBreak } else { Phase::ShortBreak };
+                Some(Transition { completed: CompletedInterval { phase, cycle }, next, target: Target::Break })
+            }
+            Phase::ShortBreak | Phase::LongBreak => Some(Transition {
+                completed: CompletedInterval { phase, cycle: self.completed_cycles },
+                next: Phase::Focus,
+                target: Target::TimeBox(self.focus_time_box_id.clone()?),
+            }),
+        }
+    }
+
+    /// Moves on to the next interval once the switch to `time_box_id` is made.
+    fn start(&mut self, transition: &Transition, time_box_id: String, phase_start: String) {
+        self.completed_cycles = transition.completed.cycle;
+        self.phase = Some(transition.next);
+        self.time_box_id = Some(time_box_id);
+        self.phase_start = Some(phase_start);
+    }
+}
+
+/// Notifies and makes the switch that ends the running interval.
+fn finish_phase(
+    app_handle: &tauri::AppHandle,
+    settings: &PomodoroSettings,
+    state: &mut PomodoroState,
+    transition: Transition,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let target = match &transition.target {
+        Target::Break => break_time_box_id(app_handle, settings)?,
+        Target::TimeBox(id) => id.clone(),
+    };
+    match transition.next {
+        Phase::Focus => notify(app_handle, "Break over", "Back to focus"),
+        next => notify(
+            app_handle,
+            &format!("Focus interval {} done", transition.completed.cycle),
+            &format!("Time for a {} minute break", settings.minutes(next)),
+        ),
+    }
+    match timer::switch_completing(app_handle, &target, Some(transition.completed)) {
+        Ok(timer_state) => {
+            let phase_start = timer_state.event_start.unwrap_or_else(|| db::format_datetime(&now));
+            state.start(&transition, target, phase_start);
+            Ok(())
+        }
+        Err(e) => {
+            // Stopped rather than retried every tick
+            state.phase = None;
+            Err(e)
+        }
+    }
+}
+
+fn tick(app_handle: &tauri::AppHandle, state: &mut PomodoroState) -> Result<(), String> {
+    // The state of a restart is kept until the webview reports its own
+    let Some(timer_state) = timer::reported_state(app_handle) else {
+        return Ok(());
+    };
+    let settings: PomodoroSettings = load(app_handle, SETTINGS_FILE_NAME)?;
+    if !settings.enabled || timer_state.active_time_box_id.is_none() {
+        *state = PomodoroState::default();
+        return Ok(());
+    }
+    let now = Utc::now();
+    state.follow(&timer_state, now, |time_box_id| is_break_time_box(app_handle, &settings, time_box_id))?;
+    match state.transition(&settings, now) {
+        Some(transition) => finish_phase(app_handle, &settings, state, transition, now),
+        None => Ok(()),
+    }
+}
+
+/// Runs the intervals for as long as the app runs.
+pub fn start(app_handle: tauri::AppHandle) {
+    thread::spawn(move || {
+        let mut state: PomodoroState = load(&app_handle, STATE_FILE_NAME).unwrap_or_else(|e| {
+            println!("Failed to load Pomodoro state: {}", e);
+            PomodoroState::default()
+        });
+        let mut saved = state.clone();
+        let mut last_error = None;
+        loop {
+            thread::sleep(TICK_INTERVAL);
+            let result = tick(&app_handle, &mut state);
+            if let Err(e) = &result {
+                if last_error.as_ref() != Some(e) {
+                    println!("Pomodoro mode failed: {}", e);
+                }
+            }
+            last_error = result.err();
+
+            if state != saved {
+                match save(&app_handle, STATE_FILE_NAME, &state) {
+                    Ok(()) => saved = state.clone(),
+                    Err(e) => println!("Failed to save Pomodoro state: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_pomodoro_settings(app_handle: tauri::AppHandle) -> Result<PomodoroSettings, String> {
+    load(&app_handle, SETTINGS_FILE_NAME)
+}
+
+#[tauri::command]
+pub async fn save_pomodoro_settings(
+    app_handle: tauri::AppHandle,
+    settings: PomodoroSettings,
+) -> Result<PomodoroSettings, String> {
+    settings.validate()?;
+    if let Some(id) = &settings.break_time_box_id {
+        let conn = db::open_connection(&app_handle)?;
+        let exists = db::get_time_boxes(&conn)?.iter().any(|time_box| &time_box.id == id && !time_box.is_deleted);
+        if !exists {
+            return Err(format!("No time box {}", id));
+        }
+    }
+    save(&app_handle, SETTINGS_FILE_NAME, &settings)?;
+    Ok(settings)
+}
+
+/// The running interval and the cycles completed in this session.
+#[tauri::command]
+pub async fn get_pomodoro_state(app_handle: tauri::AppHandle) -> Result<PomodoroState, String> {
+    load(&app_handle, STATE_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREAK_BOX: &str = "rest";
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + minutes * 60, 0).unwrap()
+    }
+
+    fn settings(long_break_every: u32) -> PomodoroSettings {
+        PomodoroSettings { enabled: true, long_break_every, ..PomodoroSettings::default() }
+    }
+
+    /// The mode against a webview that makes every switch it is asked for.
+    struct Run {
+        settings: PomodoroSettings,
+        state: PomodoroState,
+        timer: TimerState,
+    }
+
+    impl Run {
+        fn new(settings: PomodoroSettings, time_box_id: &str) -> Self {
+            let mut run = Run {
+                settings,
+                state: PomodoroState::default(),
+                timer: TimerState { session_id: Some("s1".to_string()), ..TimerState::default() },
+            };
+            run.switch_by_hand(time_box_id, 0);
+            run
+        }
+
+        fn switch_by_hand(&mut self, time_box_id: &str, minute: i64) {
+            self.timer.active_time_box_id = Some(time_box_id.to_string());
+            self.timer.event_start = Some(db::format_datetime(&at(minute)));
+        }
+
+        /// Ticks once a minute up to `until`, returning the switches made and
+        /// the minute each was made at.
+        fn until(&mut self, until: i64) -> Vec<(i64, Transition)> {
+            let mut made = Vec::new();
+            for minute in 0..=until {
+                let now = at(minute);
+                self.state.follow(&self.timer, now, |time_box_id| Ok(time_box_id == BREAK_BOX)).unwrap();
+                let Some(transition) = self.state.transition(&self.settings, now) else {
+                    continue;
+                };
+                let target = match &transition.target {
+                    Target::Break => BREAK_BOX.to_string(),
+                    Target::TimeBox(id) => id.clone(),
+                };
+                self.switch_by_hand(&target, minute);
+                self.state.start(&transition, target, db::format_datetime(&now));
+                made.push((minute, transition));
+            }
+            made
+        }
+    }
+
+    fn transition(phase: Phase, cycle: u32, next: Phase, target: Target) -> Transition {
+        Transition { completed: CompletedInterval { phase, cycle }, next, target }
+    }
+
+    fn back_to(time_box_id: &str) -> Target {
+        Target::TimeBox(time_box_id.to_string())
+    }
+
+    #[test]
+    fn takes_a_long_break_every_n_intervals() {
+        let mut run = Run::new(settings(2), "code");
+        assert_eq!(run.until(100), vec![
+            (25, transition(Phase::Focus, 1, Phase::ShortBreak, Target::Break)),
+            (30, transition(Phase::ShortBreak, 1, Phase::Focus, back_to("code"))),
+            (55, transition(Phase::Focus, 2, Phase::LongBreak, Target::Break)),
+            (70, transition(Phase::LongBreak, 2, Phase::Focus, back_to("code"))),
+            (95, transition(Phase::Focus, 3, Phase::ShortBreak, Target::Break)),
+            (100, transition(Phase::ShortBreak, 3, Phase::Focus, back_to("code"))),
+        ]);
+        assert_eq!(run.state.completed_cycles, 3);
+    }
+
+    #[test]
+    fn returns_to_the_box_switched_to_by_hand() {
+        let mut run = Run::new(settings(4), "code");
+        run.until(9);
+        run.switch_by_hand("write", 10);
+        assert_eq!(run.until(40), vec![
+            (35, transition(Phase::Focus, 1, Phase::ShortBreak, Target::Break)),
+            (40, transition(Phase::ShortBreak, 1, Phase::Focus, back_to("write"))),
+        ]);
+    }
+
+    #[test]
+    fn pauses_in_the_break_box_until_switched_away() {
+        let mut run = Run::new(settings(4), "code");
+        run.until(9);
+        run.switch_by_hand(BREAK_BOX, 10);
+        assert!(run.until(60).is_empty());
+        assert_eq!(run.state.phase, None);
+
+        run.switch_by_hand("read", 61);
+        let made = run.until(86);
+        assert_eq!(made, vec![(86, transition(Phase::Focus, 1, Phase::ShortBreak, Target::Break))]);
+    }
+
+    #[test]
+    fn leaving_a_break_early_starts_a_focus_interval() {
+        let mut run = Run::new(settings(4), "code");
+        run.until(27);
+        assert_eq!(run.state.phase, Some(Phase::ShortBreak));
+        run.switch_by_hand("code", 28);
+        run.until(28);
+        assert_eq!(run.state.phase, Some(Phase::Focus));
+        assert_eq!(run.state.completed_cycles, 1);
+        assert_eq!(run.until(53).last(), Some(&(53, transition(Phase::Focus, 2, Phase::ShortBreak, Target::Break))));
+    }
+
+    #[test]
+    fn counts_cycles_per_session() {
+        let mut run = Run::new(settings(4), "code");
+        run.until(31);
+        assert_eq!(run.state.completed_cycles, 1);
+        run.timer.session_id = Some("s2".to_string());
+        run.switch_by_hand("code", 40);
+        run.until(40);
+        assert_eq!(run.state.completed_cycles, 0);
+        assert_eq!(run.state.session_id.as_deref(), Some("s2"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::get_data_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// The profile that existed before profiles did. Its files stay in the root of
+/// the data dir so existing sign-ins and sheet ids keep working.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILES_FILE_NAME: &str = "profiles.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub name: String,
+    /// Time boxes synced to this profile's spreadsheet, all of them when unset
+    #[serde(default)]
+    pub time_box_ids: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileStore {
+    pub active: String,
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for ProfileStore {
+    fn default() -> Self {
+        ProfileStore {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![Profile {
+                name: DEFAULT_PROFILE.to_string(),
+                time_box_ids: None,
+            }],
+        }
+    }
+}
+
+fn load_store(app_handle: &tauri::AppHandle) -> Result<ProfileStore, String> {
+    let path = get_data_dir(app_handle).join(PROFILES_FILE_NAME);
+    if !path.exists() {
+        return Ok(ProfileStore::default());
+    }
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| e.to_string())
+}
+
+fn save_store(app_handle: &tauri::AppHandle, store: &ProfileStore) -> Result<(), String> {
+    let data_dir = get_data_dir(app_handle);
+    fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string_pretty(store).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(PROFILES_FILE_NAME), contents).map_err(|e| e.to_string())
+}
+
+/// Profile names double as directory names, so keep them to a safe alphabet.
+fn validate_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 64 {
+        return Err("Profile name must be between 1 and 64 characters".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Profile name may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+fn profile_dir(app_handle: &tauri::AppHandle, name: &str) -> PathBuf {
+    let data_dir = get_data_dir(app_handle);
+    if name == DEFAULT_PROFILE {
+        data_dir
+    } else {
+        data_dir.join("profiles").join(name)
+    }
+}
+
+/// Directory holding the auth token, sheet id and sync state of the active profile.
+pub fn get_profile_dir(app_handle: &tauri::AppHandle) -> PathBuf {
+    let active = load_store(app_handle)
+        .map(|store| store.active)
+        .unwrap_or_else(|_| DEFAULT_PROFILE.to_string());
+    profile_dir(app_handle, &active)
+}
+
+/// The profile called `name`, or the active one when `None`.
+pub fn find_profile(app_handle: &tauri::AppHandle, name: Option<&str>) -> Result<Profile, String> {
+    let store = load_store(app_handle)?;
+    let name = name.unwrap_or(&store.active);
+    store.profiles
+        .iter()
+        .find(|profile| profile.name == name)
+        .cloned()
+        .ok_or(format!("Profile {} not found", name))
+}
+
+/// Directory of the profile called `name`, or of the active one when `None`.
+/// Sync commands take the profile as an argument so syncing one doesn't
+/// change which is active.
+pub fn resolve_profile_dir(app_handle: &tauri::AppHandle, name: Option<&str>) -> Result<PathBuf, String> {
+    match name {
+        Some(name) => find_profile(app_handle, Some(name)).map(|profile| profile_dir(app_handle, &profile.name)),
+        None => Ok(get_profile_dir(app_handle)),
+    }
+}
+
+#[tauri::command]
+pub async fn list_profiles(app_handle: tauri::AppHandle) -> Result<ProfileStore, String> {
+    load_store(&app_handle)
+}
+
+#[tauri::command]
+pub async fn get_active_profile(app_handle: tauri::AppHandle) -> Result<Profile, String> {
+    let store = load_store(&app_handle)?;
+    store.profiles
+        .into_iter()
+        .find(|profile| profile.name == store.active)
+        .ok_or(format!("Active profile {} not found", store.active))
+}
+
+#[tauri::command]
+pub async fn create_profile(app_handle: tauri::AppHandle, name: String, time_box_ids: Option<Vec<String>>) -> Result<(), String> {
+    validate_profile_name(&name)?;
+    let mut store = load_store(&app_handle)?;
+    if store.profiles.iter().any(|profile| profile.name == name) {
+        return Err(format!("Profile {} already exists", name));
+    }
+
+    fs::create_dir_all(profile_dir(&app_handle, &name)).map_err(|e| e.to_string())?;
+    store.profiles.push(Profile { name, time_box_ids });
+    save_store(&app_handle, &store)
+}
+
+#[tauri::command]
+pub async fn set_profile_time_boxes(app_handle: tauri::AppHandle, name: String, time_box_ids: Option<Vec<String>>) -> Result<(), String> {
+    let mut store = load_store(&app_handle)?;
+    let profile = store.profiles
+        .iter_mut()
+        .find(|profile| profile.name == name)
+        .ok_or(format!("Profile {} not found", name))?;
+    profile.time_box_ids = time_box_ids;
+    save_store(&app_handle, &store)
+}
+
+#[tauri::command]
+pub async fn set_active_profile(app_handle: tauri::AppHandle, name: String) -> Result<(), String> {
+    let mut store = load_store(&app_handle)?;
+    if !store.profiles.iter().any(|profile| profile.name == name) {
+        return Err(format!("Profile {} not found", name));
+    }
+    store.active = name;
+    save_store(&app_handle, &store)
+}
+
+/// Removes a profile together with its token and sheet id. The spreadsheet
+/// itself stays in the user's Drive.
+#[tauri::command]
+pub async fn delete_profile(app_handle: tauri::AppHandle, name: String) -> Result<(), String> {
+    if name == DEFAULT_PROFILE {
+        return Err("The default profile cannot be deleted".to_string());
+    }
+    let mut store = load_store(&app_handle)?;
+    let before = store.profiles.len();
+    store.profiles.retain(|profile| profile.name != name);
+    if store.profiles.len() == before {
+        return Err(format!("Profile {} not found", name));
+    }
+    if store.active == name {
+        store.active = DEFAULT_PROFILE.to_string();
+    }
+    save_store(&app_handle, &store)?;
+
+    let dir = profile_dir(&app_handle, &name);
+    if dir.exists() {
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
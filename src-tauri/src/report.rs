@@ -0,0 +1,451 @@
+//! Rust port of the tables built in `writeToGSheet.ts`, so exports that don't go
+//! through the webview produce the same numbers as the Google Sheets sync.
+
+use crate::db::{self, Session, SessionEvent, TimeBox};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+pub const BREAK_NAME: &str = "Break";
+const UNKNOWN_NAME: &str = "Unknown";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Integer(i64),
+    /// An ISO 8601 timestamp as stored in the database
+    Timestamp(String),
+    Date(NaiveDate),
+    /// A length of time in whole seconds
+    Duration(i64),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Text(text) | Cell::Timestamp(text) => write!(f, "{}", text),
+            Cell::Integer(value) => write!(f, "{}", value),
+            Cell::Date(date) => write!(f, "{}", format_date(date)),
+            Cell::Duration(seconds) => write!(f, "{}", format_duration(*seconds)),
+        }
+    }
+}
+
+pub struct Table {
+    pub name: &'static str,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// Inclusive range of local calendar days; either end may be open.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn parse(from: Option<String>, to: Option<String>) -> Result<Self, String> {
+        let parse = |value: Option<String>| -> Result<Option<NaiveDate>, String> {
+            value
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+                    .map_err(|e| format!("Invalid date {}: {}", value, e)))
+                .transpose()
+        };
+        let range = DateRange { from: parse(from)?, to: parse(to)? };
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Err("Start date is after end date".to_string());
+            }
+        }
+        Ok(range)
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from.map_or(true, |from| date >= from) && self.to.map_or(true, |to| date <= to)
+    }
+
+    /// The range in words, for report titles.
+    pub fn title(&self) -> String {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => format!("{} to {}", format_date(&from), format_date(&to)),
+            (Some(from), None) => format!("Since {}", format_date(&from)),
+            (None, Some(to)) => format!("Until {}", format_date(&to)),
+            (None, None) => "All time".to_string(),
+        }
+    }
+}
+
+pub struct ReportData {
+    pub time_boxes: Vec<TimeBox>,
+    pub sessions: Vec<Session>,
+    pub session_events: Vec<SessionEvent>,
+}
+
+impl ReportData {
+    /// Loads the sessions that started inside `range`, their events and the
+    /// time boxes that aren't deleted, mirroring what the frontend reads.
+    pub fn load(conn: &Connection, range: DateRange) -> Result<Self, String> {
+        let time_boxes = db::get_time_boxes(conn)?
+            .into_iter()
+            .filter(|time_box| !time_box.is_deleted)
+            .collect();
+        let sessions: Vec<Session> = db::get_sessions(conn)?
+            .into_iter()
+            .filter(|session| session_date(session).map_or(false, |date| range.contains(date)))
+            .collect();
+        let session_events = db::get_session_events(conn)?
+            .into_iter()
+            .filter(|event| sessions.iter().any(|session| session.id == event.session_id))
+            .collect();
+
+        Ok(ReportData { time_boxes, sessions, session_events })
+    }
+
+    fn time_box_names(&self) -> HashMap<&str, &str> {
+        self.time_boxes
+            .iter()
+            .map(|time_box| (time_box.id.as_str(), time_box.name.as_str()))
+            .collect()
+    }
+
+    /// Unique box names in display order, the column order of the summaries.
+    pub fn unique_time_box_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for time_box in &self.time_boxes {
+            if !names.contains(&time_box.name) {
+                names.push(time_box.name.clone());
+            }
+        }
+        names
+    }
+
+    /// The names the events are filed under: the listed boxes in display
+    /// order, then the others, like Unknown for deleted boxes, sorted.
+    pub fn event_time_box_names(&self) -> Vec<String> {
+        let names = self.unique_time_box_names();
+        let mut unlisted: Vec<String> = Vec::new();
+        for event in &self.session_events {
+            let name = self.time_box_name(&event.time_box_id);
+            if !names.contains(&name) && !unlisted.contains(&name) {
+                unlisted.push(name);
+            }
+        }
+        unlisted.sort();
+        names.into_iter().chain(unlisted).collect()
+    }
+
+    pub fn time_box_name(&self, time_box_id: &str) -> String {
+        self.time_box_names().get(time_box_id).copied().unwrap_or(UNKNOWN_NAME).to_string()
+    }
+}
+
+pub fn format_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds % 3600) / 60, seconds % 60)
+}
+
+pub fn format_date(date: &NaiveDate) -> String {
+    date.format("%d-%b-%Y").to_string()
+}
+
+fn timestamp_millis(value: &str) -> Option<i64> {
+    db::parse_datetime(value).map(|datetime| datetime.timestamp_millis())
+}
+
+/// The local calendar day a session started on, used to group by date.
+pub fn session_date(session: &Session) -> Option<NaiveDate> {
+    db::parse_datetime(&session.start_datetime)
+        .map(|datetime: DateTime<Utc>| datetime.with_timezone(&Local).date_naive())
+}
+
+/// Port of `calculateDurationsByTimeBox`: seconds per box name within the session,
+/// plus the time between events under `Break`.
+pub fn durations_by_time_box(data: &ReportData, session: &Session) -> HashMap<String, i64> {
+    let now = Utc::now().timestamp_millis();
+    let session_start = timestamp_millis(&session.start_datetime).unwrap_or(0);
+    let session_end = session.end_datetime.as_deref().and_then(timestamp_millis).unwrap_or(now);
+    let names = data.time_box_names();
+
+    let mut durations: HashMap<String, i64> = HashMap::new();
+    for event in data.session_events.iter().filter(|event| event.session_id == session.id) {
+        let (Some(start), Some(end)) = (timestamp_millis(&event.start_datetime), timestamp_millis(&event.end_datetime)) else {
+            continue;
+        };
+        let duration = end.min(session_end) - start.max(session_start);
+        let name = names.get(event.time_box_id.as_str()).copied().unwrap_or(UNKNOWN_NAME);
+        *durations.entry(name.to_string()).or_insert(0) += duration;
+    }
+
+    let tracked: i64 = durations.values().sum();
+    durations.insert(BREAK_NAME.to_string(), session_end - session_start - tracked);
+
+    durations
+        .into_iter()
+        .map(|(name, millis)| (name, millis.div_euclid(1000)))
+        .collect()
+}
+
+fn session_number(data: &ReportData, session_id: &str) -> i64 {
+    data.sessions
+        .iter()
+        .position(|session| session.id == session_id)
+        .map_or(0, |index| index as i64 + 1)
+}
+
+pub fn details_sessions(data: &ReportData) -> Table {
+    let mut rows = vec![header(&["Session", "Start", "Stop", "Duration"])];
+    rows.extend(data.sessions.iter().enumerate().map(|(index, session)| vec![
+        Cell::Integer(index as i64 + 1),
+        Cell::Timestamp(session.start_datetime.clone()),
+        Cell::Timestamp(session.end_datetime.clone().unwrap_or_default()),
+        Cell::Duration(session.duration.unwrap_or(0)),
+    ]));
+    Table { name: "DetailsSessions", rows }
+}
+
+pub fn details_session_events(data: &ReportData) -> Table {
+    let now = Utc::now().timestamp_millis();
+    let mut rows = vec![header(&["Event", "Session", "Start", "End", "Duration", "Event ID"])];
+    rows.extend(data.session_events.iter().map(|event| {
+        let start = timestamp_millis(&event.start_datetime).unwrap_or(now);
+        let end = timestamp_millis(&event.end_datetime).unwrap_or(now);
+        vec![
+            Cell::Text(data.time_box_name(&event.time_box_id)),
+            Cell::Integer(session_number(data, &event.session_id)),
+            Cell::Timestamp(event.start_datetime.clone()),
+            Cell::Timestamp(event.end_datetime.clone()),
+            Cell::Duration((end - start).div_euclid(1000)),
+            Cell::Text(event.id.clone()),
+        ]
+    }));
+    Table { name: "DetailsSessionEvents", rows }
+}
+
+pub fn summary_by_session(data: &ReportData) -> Table {
+    let names = data.unique_time_box_names();
+    let mut rows = vec![summary_header("Session", &names)];
+    rows.extend(data.sessions.iter().enumerate().map(|(index, session)| {
+        let durations = durations_by_time_box(data, session);
+        let mut row = vec![Cell::Integer(index as i64 + 1)];
+        row.extend(names.iter().chain(std::iter::once(&BREAK_NAME.to_string()))
+            .map(|name| Cell::Duration(durations.get(name).copied().unwrap_or(0))));
+        row
+    }));
+    Table { name: "SummaryBySession", rows }
+}
+
+/// Seconds per box name (and Break) for every day with a session, in the order
+/// the days first appear.
+pub fn durations_by_date(data: &ReportData) -> Vec<(NaiveDate, HashMap<String, i64>)> {
+    let mut days: Vec<(NaiveDate, HashMap<String, i64>)> = Vec::new();
+    for session in &data.sessions {
+        let Some(date) = session_date(session) else { continue };
+        let index = match days.iter().position(|(day, _)| *day == date) {
+            Some(index) => index,
+            None => {
+                days.push((date, HashMap::new()));
+                days.len() - 1
+            }
+        };
+        for (name, seconds) in durations_by_time_box(data, session) {
+            *days[index].1.entry(name).or_insert(0) += seconds;
+        }
+    }
+    days
+}
+
+pub fn summary_by_date(data: &ReportData) -> Table {
+    let names = data.unique_time_box_names();
+    let mut rows = vec![summary_header("Date", &names)];
+    rows.extend(durations_by_date(data).into_iter().map(|(date, durations)| {
+        let mut row = vec![Cell::Date(date)];
+        row.extend(names.iter().chain(std::iter::once(&BREAK_NAME.to_string()))
+            .map(|name| Cell::Duration(durations.get(name).copied().unwrap_or(0))));
+        row
+    }));
+    Table { name: "SummaryByDate", rows }
+}
+
+/// The Pomodoro intervals completed in the sessions, or `None` if there are
+/// none.
+pub fn pomodoro_intervals(data: &ReportData) -> Option<Table> {
+    let mut rows = vec![header(&["Date", "Session", "Time box", "Phase", "Cycle", "Start", "End", "Duration"])];
+    for event in data.session_events.iter().filter(|event| event.pomodoro_phase.is_some()) {
+        let session = data.sessions.iter().find(|session| session.id == event.session_id);
+        let (Some(date), Some(start), Some(end)) = (
+            session.and_then(session_date),
+            timestamp_millis(&event.start_datetime),
+            timestamp_millis(&event.end_datetime),
+        ) else {
+            continue;
+        };
+        rows.push(vec![
+            Cell::Date(date),
+            Cell::Integer(session_number(data, &event.session_id)),
+            Cell::Text(data.time_box_name(&event.time_box_id)),
+            Cell::Text(event.pomodoro_phase.clone().unwrap_or_default()),
+            Cell::Integer(event.pomodoro_cycle.unwrap_or(0)),
+            Cell::Timestamp(event.start_datetime.clone()),
+            Cell::Timestamp(event.end_datetime.clone()),
+            Cell::Duration((end - start).div_euclid(1000)),
+        ]);
+    }
+    (rows.len() > 1).then_some(Table { name: "PomodoroIntervals", rows })
+}
+
+/// The four tables of the sheet sync, in tab order.
+pub fn build_tables(data: &ReportData) -> Vec<Table> {
+    vec![
+        summary_by_date(data),
+        summary_by_session(data),
+        details_sessions(data),
+        details_session_events(data),
+    ]
+}
+
+/// The sync tables, then the Pomodoro intervals for files exported locally.
+pub fn export_tables(data: &ReportData) -> Vec<Table> {
+    let mut tables = build_tables(data);
+    tables.extend(pomodoro_intervals(data));
+    tables
+}
+
+/// Writes a table as RFC 4180 CSV: CRLF line endings, fields quoted only when
+/// they contain a delimiter, quote or line break.
+fn write_csv(path: &Path, table: &Table) -> Result<(), String> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .quote_style(csv::QuoteStyle::Necessary)
+        .from_path(path)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+    for row in &table.rows {
+        writer
+            .write_record(row.iter().map(|cell| cell.to_string()))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Writes the export tables to `directory` as one CSV file each, returning the
+/// written files.
+pub fn write_csv_files(data: &ReportData, directory: &Path) -> Result<Vec<String>, String> {
+    let mut written = Vec::new();
+    for table in export_tables(data) {
+        let path = directory.join(format!("{}.csv", table.name));
+        write_csv(&path, &table)?;
+        written.push(path.display().to_string());
+    }
+    Ok(written)
+}
+
+fn header(columns: &[&str]) -> Vec<Cell> {
+    columns.iter().map(|column| Cell::Text(column.to_string())).collect()
+}
+
+fn summary_header(first: &str, names: &[String]) -> Vec<Cell> {
+    let mut row = vec![Cell::Text(first.to_string())];
+    row.extend(names.iter().map(|name| Cell::Text(name.clone())));
+    row.push(Cell::Text(BREAK_NAME.to_string()));
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_box(id: &str, name: &str) -> TimeBox {
+        TimeBox { id: id.to_string(), name: name.to_string(), is_hidden: false, is_deleted: false, colour: "#1673FF".to_string() }
+    }
+
+    fn session(id: &str, start: &str, end: &str) -> Session {
+        Session { id: id.to_string(), start_datetime: start.to_string(), end_datetime: Some(end.to_string()), duration: None }
+    }
+
+    fn event(time_box_id: &str, session_id: &str, start: &str, end: &str) -> SessionEvent {
+        SessionEvent {
+            id: format!("{}-{}", session_id, start),
+            time_box_id: time_box_id.to_string(),
+            session_id: session_id.to_string(),
+            start_datetime: start.to_string(),
+            end_datetime: end.to_string(),
+            seconds: 0,
+            pomodoro_phase: None,
+            pomodoro_cycle: None,
+        }
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn sums_boxes_and_breaks_per_day() {
+        // Midday sessions stay on their day in any local time zone
+        let data = ReportData {
+            time_boxes: vec![time_box("b1", "Code"), time_box("b2", "Read")],
+            sessions: vec![
+                session("s1", "2024-08-01T11:00:00.000Z", "2024-08-01T12:00:00.000Z"),
+                session("s2", "2024-08-02T12:00:00.000Z", "2024-08-02T12:30:00.000Z"),
+                session("s3", "2024-08-01T13:00:00.000Z", "2024-08-01T13:10:00.000Z"),
+            ],
+            session_events: vec![
+                event("b1", "s1", "2024-08-01T11:00:00.000Z", "2024-08-01T11:30:00.000Z"),
+                event("b2", "s1", "2024-08-01T11:40:00.000Z", "2024-08-01T12:00:00.000Z"),
+                event("b1", "s2", "2024-08-02T12:00:00.000Z", "2024-08-02T12:30:00.000Z"),
+                // Clipped to its session, and filed under Unknown without a box
+                event("gone", "s3", "2024-08-01T12:50:00.000Z", "2024-08-01T13:05:00.000Z"),
+            ],
+        };
+
+        let days = durations_by_date(&data);
+        assert_eq!(days.iter().map(|(day, _)| *day).collect::<Vec<_>>(), vec![date("2024-08-01"), date("2024-08-02")]);
+        let first = &days[0].1;
+        assert_eq!(first.get("Code"), Some(&(30 * 60)));
+        assert_eq!(first.get("Read"), Some(&(20 * 60)));
+        assert_eq!(first.get(UNKNOWN_NAME), Some(&(5 * 60)));
+        assert_eq!(first.get(BREAK_NAME), Some(&(10 * 60 + 5 * 60)));
+        assert_eq!(days[1].1.get(BREAK_NAME), Some(&0));
+    }
+
+    #[test]
+    fn parses_date_ranges() {
+        let range = DateRange::parse(Some("2024-08-01".to_string()), Some(" 2024-08-31 ".to_string())).unwrap();
+        assert_eq!((range.from, range.to), (Some(date("2024-08-01")), Some(date("2024-08-31"))));
+        assert!(range.contains(date("2024-08-31")) && !range.contains(date("2024-09-01")));
+        assert_eq!(range.title(), "01-Aug-2024 to 31-Aug-2024");
+
+        let open = DateRange::parse(Some("".to_string()), None).unwrap();
+        assert_eq!((open.from, open.to), (None, None));
+        assert_eq!(open.title(), "All time");
+
+        assert!(DateRange::parse(Some("01/08/2024".to_string()), None).unwrap_err().starts_with("Invalid date 01/08/2024"));
+        assert_eq!(
+            DateRange::parse(Some("2024-08-02".to_string()), Some("2024-08-01".to_string())).err(),
+            Some("Start date is after end date".to_string())
+        );
+    }
+
+    #[test]
+    fn quotes_csv_fields_only_when_needed() {
+        let path = std::env::temp_dir().join(format!("clockblocks-report-{}.csv", uuid::Uuid::new_v4()));
+        let table = Table {
+            name: "Quoting",
+            rows: vec![vec![
+                Cell::Text("Plain".to_string()),
+                Cell::Text("Code, review".to_string()),
+                Cell::Text("Say \"hi\"".to_string()),
+                Cell::Text("Two\nlines".to_string()),
+                Cell::Duration(3661),
+            ]],
+        };
+        write_csv(&path, &table).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(contents, "Plain,\"Code, review\",\"Say \"\"hi\"\"\",\"Two\nlines\",01:01:01\r\n");
+    }
+}
@@ -0,0 +1,459 @@
+use crate::db;
+use crate::events::{self, SyncFinished};
+use crate::profiles::{self, resolve_profile_dir};
+use crate::{get_valid_auth_token, batch_update_sheet};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+
+pub const EVENTS_SHEET_NAME: &str = "DetailsSessionEvents";
+
+// Column layout written by `createDetailsSessionEvents` in `writeToGSheet.ts`
+const EVENT_NAME_COLUMN: usize = 0;
+const START_COLUMN: usize = 2;
+const END_COLUMN: usize = 3;
+const EVENT_ID_COLUMN: usize = 5;
+
+const SNAPSHOT_FILE_NAME: &str = "sheet_sync_snapshot.json";
+
+/// The editable part of a session event as it appears in the sheet.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncedEvent {
+    pub time_box: String,
+    pub start_datetime: String,
+    pub end_datetime: String,
+}
+
+/// What the sheet looked like right after the last sync, used as the common
+/// ancestor when deciding which side changed an event.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncSnapshot {
+    sheet_id: String,
+    synced_at: String,
+    events: HashMap<String, SyncedEvent>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetEdit {
+    pub event_id: String,
+    pub local: SyncedEvent,
+    pub sheet: SyncedEvent,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedEdit {
+    pub event_id: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetCorrectionReport {
+    /// Changed only in the sheet since the last sync
+    pub edits: Vec<SheetEdit>,
+    /// Changed in both the sheet and the local database since the last sync
+    pub conflicts: Vec<SheetEdit>,
+    pub applied: Vec<String>,
+    pub rejected: Vec<RejectedEdit>,
+}
+
+fn normalize_datetime(value: &str) -> String {
+    db::parse_datetime(value)
+        .or_else(|| {
+            // Google Sheets renders parsed dates in the spreadsheet locale
+            chrono::NaiveDateTime::parse_from_str(value.trim(), "%m/%d/%Y %H:%M:%S")
+                .ok()
+                .map(|naive| naive.and_utc())
+        })
+        .map(|datetime| db::format_datetime(&datetime))
+        .unwrap_or_else(|| value.trim().to_string())
+}
+
+fn normalize(event: SyncedEvent) -> SyncedEvent {
+    SyncedEvent {
+        time_box: event.time_box.trim().to_string(),
+        start_datetime: normalize_datetime(&event.start_datetime),
+        end_datetime: normalize_datetime(&event.end_datetime),
+    }
+}
+
+/// Three-way comparison of every event in the sheet against the local database,
+/// using the snapshot from the last sync as the base. Without a snapshot there is
+/// no way to tell which side changed, so every difference is a conflict.
+pub fn diff_events(
+    sheet: &HashMap<String, SyncedEvent>,
+    local: &HashMap<String, SyncedEvent>,
+    snapshot: Option<&HashMap<String, SyncedEvent>>,
+) -> SheetCorrectionReport {
+    let mut report = SheetCorrectionReport::default();
+
+    for (event_id, sheet_event) in sheet {
+        // Events deleted locally stay deleted; the next sync drops the row
+        let Some(local_event) = local.get(event_id) else { continue };
+        if sheet_event == local_event {
+            continue;
+        }
+
+        let edit = SheetEdit {
+            event_id: event_id.clone(),
+            local: local_event.clone(),
+            sheet: sheet_event.clone(),
+        };
+
+        match snapshot.and_then(|events| events.get(event_id)) {
+            // Only the local side moved on; the next write overwrites the sheet
+            Some(base) if base == sheet_event => {}
+            Some(base) if base == local_event => report.edits.push(edit),
+            _ => report.conflicts.push(edit),
+        }
+    }
+
+    report.edits.sort_by(|a, b| a.sheet.start_datetime.cmp(&b.sheet.start_datetime));
+    report.conflicts.sort_by(|a, b| a.sheet.start_datetime.cmp(&b.sheet.start_datetime));
+    report
+}
+
+fn snapshot_path(app_handle: &tauri::AppHandle, profile: Option<&str>) -> Result<std::path::PathBuf, String> {
+    Ok(resolve_profile_dir(app_handle, profile)?.join(SNAPSHOT_FILE_NAME))
+}
+
+fn load_snapshot(app_handle: &tauri::AppHandle, profile: Option<&str>, sheet_id: &str) -> Option<HashMap<String, SyncedEvent>> {
+    let contents = fs::read_to_string(snapshot_path(app_handle, profile).ok()?).ok()?;
+    let snapshot: SyncSnapshot = serde_json::from_str(&contents).ok()?;
+    // A snapshot of a different spreadsheet is no base for this one
+    if snapshot.sheet_id != sheet_id {
+        return None;
+    }
+    Some(snapshot.events)
+}
+
+/// Events of every box, or only of `time_box_ids` when given.
+fn load_local_events(conn: &rusqlite::Connection, time_box_ids: Option<&[String]>) -> Result<HashMap<String, SyncedEvent>, String> {
+    // Events of deleted boxes are written as "Unknown", same as the frontend does
+    let time_box_names: HashMap<String, String> = db::get_time_boxes(conn)?
+        .into_iter()
+        .filter(|time_box| !time_box.is_deleted)
+        .map(|time_box| (time_box.id, time_box.name))
+        .collect();
+
+    Ok(db::get_session_events(conn)?
+        .into_iter()
+        .filter(|event| time_box_ids.is_none_or(|ids| ids.contains(&event.time_box_id)))
+        .map(|event| {
+            let synced = normalize(SyncedEvent {
+                time_box: time_box_names.get(&event.time_box_id).cloned().unwrap_or_else(|| "Unknown".to_string()),
+                start_datetime: event.start_datetime,
+                end_datetime: event.end_datetime,
+            });
+            (event.id, synced)
+        })
+        .collect())
+}
+
+async fn load_sheet_events(
+    app_handle: &tauri::AppHandle,
+    profile: Option<&str>,
+    sheet_id: &str,
+) -> Result<HashMap<String, SyncedEvent>, String> {
+    let auth_token = get_valid_auth_token(app_handle, profile).await?;
+    let client = Client::new();
+
+    let response = client
+        .get(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}?valueRenderOption=FORMATTED_VALUE",
+            sheet_id,
+            EVENTS_SHEET_NAME
+        ))
+        .bearer_auth(&auth_token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", EVENTS_SHEET_NAME, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to read {}: {}", EVENTS_SHEET_NAME, response.status()));
+    }
+
+    let values: Value = response.json().await.map_err(|e| e.to_string())?;
+    let rows = values["values"].as_array().cloned().unwrap_or_default();
+
+    let cell = |row: &Vec<Value>, column: usize| row.get(column)
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(rows
+        .iter()
+        .skip(1)
+        .filter_map(|row| row.as_array())
+        .filter_map(|row| {
+            let event_id = cell(row, EVENT_ID_COLUMN);
+            // Rows without an id were added by hand or predate the id column
+            if event_id.is_empty() {
+                return None;
+            }
+            let event = normalize(SyncedEvent {
+                time_box: cell(row, EVENT_NAME_COLUMN),
+                start_datetime: cell(row, START_COLUMN),
+                end_datetime: cell(row, END_COLUMN),
+            });
+            Some((event_id, event))
+        })
+        .collect())
+}
+
+async fn build_report(app_handle: &tauri::AppHandle, profile: Option<&str>, sheet_id: &str) -> Result<SheetCorrectionReport, String> {
+    let sheet_events = load_sheet_events(app_handle, profile, sheet_id).await?;
+    let conn = db::open_connection(app_handle)?;
+    let local_events = load_local_events(&conn, None)?;
+    let snapshot = load_snapshot(app_handle, profile, sheet_id);
+
+    Ok(diff_events(&sheet_events, &local_events, snapshot.as_ref()))
+}
+
+fn apply_edit(conn: &rusqlite::Connection, time_boxes: &[db::TimeBox], edit: &SheetEdit) -> Result<(), String> {
+    let time_box = time_boxes
+        .iter()
+        .filter(|time_box| time_box.name == edit.sheet.time_box)
+        .min_by_key(|time_box| time_box.is_deleted)
+        .ok_or(format!("Unknown time box \"{}\"", edit.sheet.time_box))?;
+
+    let start = db::parse_datetime(&edit.sheet.start_datetime)
+        .ok_or(format!("Invalid start \"{}\"", edit.sheet.start_datetime))?;
+    let end = db::parse_datetime(&edit.sheet.end_datetime)
+        .ok_or(format!("Invalid end \"{}\"", edit.sheet.end_datetime))?;
+    if end <= start {
+        return Err("End is not after start".to_string());
+    }
+
+    let mut event = db::get_session_event(conn, &edit.event_id)?
+        .ok_or("Event no longer exists")?;
+    event.time_box_id = time_box.id.clone();
+    event.start_datetime = db::format_datetime(&start);
+    event.end_datetime = db::format_datetime(&end);
+    event.seconds = (end - start).num_seconds();
+
+    db::upsert_session_event(conn, &event)
+}
+
+#[tauri::command]
+pub async fn preview_sheet_corrections(
+    app_handle: tauri::AppHandle,
+    sheet_id: String,
+    profile: Option<String>,
+) -> Result<SheetCorrectionReport, String> {
+    build_report(&app_handle, profile.as_deref(), &sheet_id).await
+}
+
+/// Applies every clean edit from the sheet, plus the conflicts listed in
+/// `accept_sheet_for`. Remaining conflicts keep the local version, which the
+/// next write pushes back to the sheet.
+#[tauri::command]
+pub async fn apply_sheet_corrections(
+    app_handle: tauri::AppHandle,
+    sheet_id: String,
+    accept_sheet_for: Vec<String>,
+    profile: Option<String>,
+) -> Result<SheetCorrectionReport, String> {
+    let mut report = build_report(&app_handle, profile.as_deref(), &sheet_id).await?;
+
+    let mut conn = db::open_connection(&app_handle)?;
+    let time_boxes = db::get_time_boxes(&conn)?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let accepted_conflicts = report.conflicts
+        .iter()
+        .filter(|conflict| accept_sheet_for.contains(&conflict.event_id));
+
+    for edit in report.edits.iter().chain(accepted_conflicts) {
+        match apply_edit(&tx, &time_boxes, edit) {
+            Ok(()) => report.applied.push(edit.event_id.clone()),
+            Err(reason) => report.rejected.push(RejectedEdit {
+                event_id: edit.event_id.clone(),
+                reason,
+            }),
+        }
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+    println!("Applied {} corrections from sheet {}", report.applied.len(), sheet_id);
+
+    Ok(report)
+}
+
+/// Called after the events tab has been written: hides the id column and
+/// stores what was written as the base for the next three-way comparison.
+/// Only the boxes of the profile are written, so only those are recorded.
+#[tauri::command]
+pub async fn record_sheet_sync(app_handle: tauri::AppHandle, sheet_id: String, profile: Option<String>) -> Result<(), String> {
+    let profile = profiles::find_profile(&app_handle, profile.as_deref())?;
+    let auth_token = get_valid_auth_token(&app_handle, Some(&profile.name)).await?;
+    let client = Client::new();
+
+    let response = client
+        .get(&format!(
+            "https://sheets.googleapis.com/v4/spreadsheets/{}?fields=sheets(properties(sheetId,title))",
+            sheet_id
+        ))
+        .bearer_auth(&auth_token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch spreadsheet: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to fetch spreadsheet: {}", response.status()));
+    }
+
+    let spreadsheet: Value = response.json().await.map_err(|e| e.to_string())?;
+    let events_sheet_id = spreadsheet["sheets"].as_array()
+        .and_then(|sheets| sheets.iter().find(|sheet| sheet["properties"]["title"].as_str() == Some(EVENTS_SHEET_NAME)))
+        .and_then(|sheet| sheet["properties"]["sheetId"].as_i64())
+        .ok_or(format!("Sheet {} not found", EVENTS_SHEET_NAME))?;
+
+    let hide_request = json!({
+        "updateDimensionProperties": {
+            "range": {
+                "sheetId": events_sheet_id,
+                "dimension": "COLUMNS",
+                "startIndex": EVENT_ID_COLUMN,
+                "endIndex": EVENT_ID_COLUMN + 1
+            },
+            "properties": { "hiddenByUser": true },
+            "fields": "hiddenByUser"
+        }
+    });
+    batch_update_sheet(&client, &auth_token.access_token, &sheet_id, vec![hide_request]).await?;
+
+    let conn = db::open_connection(&app_handle)?;
+    let snapshot = SyncSnapshot {
+        sheet_id,
+        synced_at: db::format_datetime(&chrono::Utc::now()),
+        events: load_local_events(&conn, profile.time_box_ids.as_deref())?,
+    };
+
+    let profile_dir = resolve_profile_dir(&app_handle, Some(&profile.name))?;
+    fs::create_dir_all(&profile_dir).map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+    fs::write(snapshot_path(&app_handle, Some(&profile.name))?, contents).map_err(|e| e.to_string())
+}
+
+/// Called by the webview when a sync to the sheet succeeds or fails, so the
+/// result reaches event stream clients.
+#[tauri::command]
+pub fn report_sync_result(app_handle: tauri::AppHandle, sheet_id: Option<String>, error: Option<String>) {
+    events::publish(&app_handle, events::SYNC_FINISHED, SyncFinished {
+        sheet_id,
+        success: error.is_none(),
+        error,
+        at: db::format_datetime(&chrono::Utc::now()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time_box: &str, start: &str, end: &str) -> SyncedEvent {
+        SyncedEvent {
+            time_box: time_box.to_string(),
+            start_datetime: start.to_string(),
+            end_datetime: end.to_string(),
+        }
+    }
+
+    fn events(entries: &[(&str, SyncedEvent)]) -> HashMap<String, SyncedEvent> {
+        entries.iter().map(|(id, event)| (id.to_string(), event.clone())).collect()
+    }
+
+    fn base() -> SyncedEvent {
+        event("Code", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z")
+    }
+
+    #[test]
+    fn unchanged_events_need_nothing() {
+        let snapshot = events(&[("a", base())]);
+        let report = diff_events(&snapshot, &snapshot, Some(&snapshot));
+        assert!(report.edits.is_empty());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn sheet_only_edits_are_offered() {
+        let edited = event("Read", "2024-03-04T09:00:00.000Z", "2024-03-04T10:30:00.000Z");
+        let snapshot = events(&[("a", base())]);
+        let report = diff_events(&events(&[("a", edited.clone())]), &snapshot, Some(&snapshot));
+        assert_eq!(report.edits.len(), 1);
+        assert_eq!(report.edits[0].event_id, "a");
+        assert_eq!(report.edits[0].sheet, edited);
+        assert_eq!(report.edits[0].local, base());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn local_only_edits_are_left_for_the_next_write() {
+        let edited = event("Calls", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z");
+        let snapshot = events(&[("a", base())]);
+        let report = diff_events(&snapshot, &events(&[("a", edited)]), Some(&snapshot));
+        assert!(report.edits.is_empty());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn edits_on_both_sides_conflict() {
+        let sheet = events(&[("a", event("Read", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z"))]);
+        let local = events(&[("a", event("Calls", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z"))]);
+        let report = diff_events(&sheet, &local, Some(&events(&[("a", base())])));
+        assert!(report.edits.is_empty());
+        assert_eq!(report.conflicts.len(), 1);
+    }
+
+    #[test]
+    fn differences_without_a_snapshot_conflict() {
+        let sheet = events(&[("a", event("Read", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z"))]);
+        let report = diff_events(&sheet, &events(&[("a", base())]), None);
+        assert!(report.edits.is_empty());
+        assert_eq!(report.conflicts.len(), 1);
+    }
+
+    #[test]
+    fn rows_deleted_in_the_sheet_are_not_edits() {
+        let snapshot = events(&[("a", base()), ("b", base())]);
+        let report = diff_events(&events(&[("a", base())]), &snapshot, Some(&snapshot));
+        assert!(report.edits.is_empty());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn events_deleted_locally_stay_deleted() {
+        let edited = event("Read", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z");
+        let report = diff_events(&events(&[("a", edited)]), &HashMap::new(), Some(&events(&[("a", base())])));
+        assert!(report.edits.is_empty());
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn unknown_box_names_compare_like_any_other() {
+        // Events of deleted boxes are written and loaded as "Unknown"
+        let unknown = event("Unknown", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z");
+        let snapshot = events(&[("a", unknown.clone())]);
+        let report = diff_events(&snapshot, &snapshot, Some(&snapshot));
+        assert!(report.edits.is_empty());
+
+        let reassigned = event("Code", "2024-03-04T09:00:00.000Z", "2024-03-04T10:00:00.000Z");
+        let report = diff_events(&events(&[("a", reassigned)]), &snapshot, Some(&snapshot));
+        assert_eq!(report.edits.len(), 1);
+        assert_eq!(report.edits[0].local, unknown);
+    }
+
+    #[test]
+    fn sheet_locale_dates_match_stored_ones() {
+        let sheet = normalize(event(" Code ", "03/04/2024 09:00:00", "03/04/2024 10:00:00"));
+        let local = normalize(base());
+        assert_eq!(sheet, local);
+    }
+}
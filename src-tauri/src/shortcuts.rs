@@ -0,0 +1,158 @@
+//! Global shortcuts that switch boxes while another app has focus. The
+//! shortcuts in `time_boxes` go to the visible boxes in the order the timer
+//! shows them. The box is looked up on each press, so reordering or hiding
+//! boxes doesn't need the shortcuts registered again.
+
+use crate::db;
+use crate::timer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use tauri::{GlobalShortcutManager, Manager};
+
+const SHORTCUTS_FILE_NAME: &str = "shortcuts.json";
+/// One for each number key
+const MAX_TIME_BOX_SHORTCUTS: usize = 9;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortcutSettings {
+    /// Off until turned on, as Ctrl+Alt is AltGr on Windows and some keyboard
+    /// layouts type characters with it
+    pub enabled: bool,
+    /// Accelerators such as `Ctrl+Alt+1` for the first visible box, the
+    /// second and so on. An empty one leaves its box without a shortcut.
+    pub time_boxes: Vec<String>,
+    pub end_session: Option<String>,
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        ShortcutSettings {
+            enabled: false,
+            time_boxes: (1..=MAX_TIME_BOX_SHORTCUTS).map(|number| format!("Ctrl+Alt+{}", number)).collect(),
+            end_session: Some("Ctrl+Alt+0".to_string()),
+        }
+    }
+}
+
+fn get_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(SHORTCUTS_FILE_NAME)
+}
+
+fn load_settings(app_handle: &tauri::AppHandle) -> Result<ShortcutSettings, String> {
+    let path = get_settings_path(app_handle);
+    if !path.exists() {
+        return Ok(ShortcutSettings::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid shortcut settings: {}", e))
+}
+
+fn save_settings(app_handle: &tauri::AppHandle, settings: &ShortcutSettings) -> Result<(), String> {
+    let path = get_settings_path(app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn assigned(accelerator: &str) -> Option<&str> {
+    Some(accelerator.trim()).filter(|accelerator| !accelerator.is_empty())
+}
+
+fn validate(settings: &ShortcutSettings) -> Result<(), String> {
+    if settings.time_boxes.len() > MAX_TIME_BOX_SHORTCUTS {
+        return Err(format!("At most {} time boxes can have shortcuts", MAX_TIME_BOX_SHORTCUTS));
+    }
+    let mut used = HashSet::new();
+    for accelerator in settings.time_boxes.iter().chain(settings.end_session.iter()).filter_map(|a| assigned(a)) {
+        if !used.insert(accelerator.to_ascii_lowercase()) {
+            return Err(format!("{} is used for more than one shortcut", accelerator));
+        }
+    }
+    Ok(())
+}
+
+/// Switches to the box at `position` among the visible boxes.
+fn switch_to_position(app_handle: &tauri::AppHandle, position: usize) -> Result<(), String> {
+    let conn = db::open_connection(app_handle)?;
+    let time_box = db::get_time_boxes(&conn)?
+        .into_iter()
+        .filter(|time_box| !time_box.is_hidden && !time_box.is_deleted)
+        .nth(position)
+        .ok_or_else(|| format!("There is no time box {}", position + 1))?;
+    timer::switch_to(app_handle, &time_box.id).map(|_| ())
+}
+
+/// Shortcut handlers run on the main thread, while commands wait for the
+/// webview to confirm.
+fn run_in_background<F>(app_handle: &tauri::AppHandle, action: F)
+where
+    F: Fn(&tauri::AppHandle) -> Result<(), String> + Send + 'static,
+{
+    let handle = app_handle.clone();
+    thread::spawn(move || {
+        if let Err(e) = action(&handle) {
+            println!("Failed to run shortcut: {}", e);
+        }
+    });
+}
+
+/// Replaces the registered shortcuts with those in `settings`.
+fn register(app_handle: &tauri::AppHandle, settings: &ShortcutSettings) -> Result<(), String> {
+    let mut manager = app_handle.global_shortcut_manager();
+    manager.unregister_all().map_err(|e| e.to_string())?;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    for (position, accelerator) in settings.time_boxes.iter().enumerate() {
+        let Some(accelerator) = assigned(accelerator) else {
+            continue;
+        };
+        let handle = app_handle.clone();
+        manager
+            .register(accelerator, move || {
+                run_in_background(&handle, move |handle| switch_to_position(handle, position))
+            })
+            .map_err(|e| format!("Failed to register {}: {}", accelerator, e))?;
+    }
+    if let Some(accelerator) = settings.end_session.as_deref().and_then(assigned) {
+        let handle = app_handle.clone();
+        manager
+            .register(accelerator, move || {
+                run_in_background(&handle, |handle| timer::end_session(handle).map(|_| ()))
+            })
+            .map_err(|e| format!("Failed to register {}: {}", accelerator, e))?;
+    }
+    Ok(())
+}
+
+/// Registers the saved shortcuts when the app starts.
+pub fn register_saved_shortcuts(app_handle: &tauri::AppHandle) {
+    if let Err(e) = load_settings(app_handle).and_then(|settings| register(app_handle, &settings)) {
+        println!("Failed to register global shortcuts: {}", e);
+    }
+}
+
+#[tauri::command]
+pub async fn get_shortcuts(app_handle: tauri::AppHandle) -> Result<ShortcutSettings, String> {
+    load_settings(&app_handle)
+}
+
+/// Registers and saves `settings`. If a shortcut can't be registered, for
+/// example because another app holds it, the previous shortcuts are restored.
+#[tauri::command]
+pub async fn save_shortcuts(app_handle: tauri::AppHandle, settings: ShortcutSettings) -> Result<ShortcutSettings, String> {
+    validate(&settings)?;
+    if let Err(e) = register(&app_handle, &settings) {
+        register_saved_shortcuts(&app_handle);
+        return Err(e);
+    }
+    save_settings(&app_handle, &settings)?;
+    Ok(settings)
+}
@@ -0,0 +1,321 @@
+//! Keeps one instance of the app per data directory. The first launch listens
+//! on an endpoint in the data dir; later launches send it their arguments, get
+//! back whether they were handled, and exit. Only plain std is used here so
+//! `clockblocks-cli` can send to the same endpoint.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+const SOCKET_FILE_NAME: &str = "clockblocks.sock";
+
+pub trait InstanceStream: Read + Write + Send {}
+impl<T: Read + Write + Send> InstanceStream for T {}
+
+pub trait InstanceListener: Send {
+    fn accept(&mut self) -> io::Result<Box<dyn InstanceStream>>;
+}
+
+/// The endpoint instances meet on. Only a Unix domain socket exists; a Windows
+/// named pipe would be another implementation.
+pub trait InstanceTransport {
+    /// Claims the endpoint, failing with `AddrInUse` while another instance
+    /// holds it.
+    fn bind(&self) -> io::Result<Box<dyn InstanceListener>>;
+    fn connect(&self) -> io::Result<Box<dyn InstanceStream>>;
+}
+
+#[cfg(unix)]
+pub struct UnixSocketTransport {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+struct UnixSocketListener(std::os::unix::net::UnixListener);
+
+#[cfg(unix)]
+impl InstanceListener for UnixSocketListener {
+    fn accept(&mut self) -> io::Result<Box<dyn InstanceStream>> {
+        let (stream, _) = self.0.accept()?;
+        Ok(Box::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl InstanceTransport for UnixSocketTransport {
+    fn bind(&self) -> io::Result<Box<dyn InstanceListener>> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        if self.path.exists() {
+            if UnixStream::connect(&self.path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "Another instance is running"));
+            }
+            // Left behind by an instance that crashed
+            std::fs::remove_file(&self.path)?;
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(&self.path)?;
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Box::new(UnixSocketListener(listener)))
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn InstanceStream>> {
+        Ok(Box::new(std::os::unix::net::UnixStream::connect(&self.path)?))
+    }
+}
+
+/// The transport for this platform. On Windows this is `None`: there is no
+/// named pipe transport yet, so every launch runs its own instance and
+/// `clockblocks-cli` can't tell that the app is running.
+pub fn default_transport(data_dir: &Path) -> Option<Box<dyn InstanceTransport>> {
+    #[cfg(unix)]
+    {
+        Some(Box::new(UnixSocketTransport { path: data_dir.join(SOCKET_FILE_NAME) }))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (data_dir, SOCKET_FILE_NAME);
+        None
+    }
+}
+
+/// One line of JSON each way: the arguments, then the outcome.
+#[derive(Serialize, Deserialize)]
+struct Reply {
+    error: Option<String>,
+}
+
+/// Sends `args` to the running instance. The outer error means nothing is
+/// listening, the inner one that the instance couldn't handle the arguments.
+pub fn send_args(transport: &dyn InstanceTransport, args: &[String]) -> io::Result<Result<(), String>> {
+    let mut stream = transport.connect()?;
+    let message = serde_json::to_string(args).map_err(io::Error::other)?;
+    stream.write_all(format!("{}\n", message).as_bytes())?;
+    stream.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let reply: Reply = serde_json::from_str(&line).map_err(io::Error::other)?;
+    Ok(reply.error.map_or(Ok(()), Err))
+}
+
+fn handle_connection(
+    stream: Box<dyn InstanceStream>,
+    handler: &dyn Fn(Vec<String>) -> Result<(), String>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        // Connected only to check whether an instance is running
+        return Ok(());
+    }
+    let result = serde_json::from_str::<Vec<String>>(&line)
+        .map_err(|e| format!("Invalid arguments: {}", e))
+        .and_then(handler);
+
+    let reply = serde_json::to_string(&Reply { error: result.err() }).map_err(io::Error::other)?;
+    let mut stream = reader.into_inner();
+    stream.write_all(format!("{}\n", reply).as_bytes())?;
+    stream.flush()
+}
+
+/// Handles the arguments of later launches on a background thread, one
+/// connection at a time.
+pub fn serve<F>(mut listener: Box<dyn InstanceListener>, handler: F)
+where
+    F: Fn(Vec<String>) -> Result<(), String> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        match listener.accept() {
+            Ok(stream) => {
+                if let Err(e) = handle_connection(stream, &handler) {
+                    println!("Failed to handle another launch: {}", e);
+                }
+            }
+            Err(e) => println!("Failed to accept another launch: {}", e),
+        }
+    });
+}
+
+pub enum Instance {
+    /// This process is the only instance and should handle later launches
+    Primary(Box<dyn InstanceListener>),
+    /// Another instance was running and has been sent the arguments
+    Secondary(Result<(), String>),
+    /// The platform has no transport, so there is no guard
+    Unguarded,
+}
+
+/// Becomes the primary instance, or hands `args` to the one already running.
+pub fn acquire(data_dir: &Path, args: &[String]) -> io::Result<Instance> {
+    match default_transport(data_dir) {
+        Some(transport) => acquire_on(transport.as_ref(), args),
+        None => Ok(Instance::Unguarded),
+    }
+}
+
+fn acquire_on(transport: &dyn InstanceTransport, args: &[String]) -> io::Result<Instance> {
+    match transport.bind() {
+        Ok(listener) => Ok(Instance::Primary(listener)),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Ok(Instance::Secondary(send_args(transport, args)?)),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Reads from `input` and keeps what was written in `output`.
+    struct MemoryStream {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stream(input: &str) -> (MemoryStream, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        (MemoryStream { input: Cursor::new(input.as_bytes().to_vec()), output: output.clone() }, output)
+    }
+
+    struct NoConnections;
+
+    impl InstanceListener for NoConnections {
+        fn accept(&mut self) -> io::Result<Box<dyn InstanceStream>> {
+            Err(io::Error::other("No connections"))
+        }
+    }
+
+    /// An endpoint that is either free or held by an instance answering with
+    /// `reply`.
+    struct MemoryTransport {
+        in_use: bool,
+        reply: String,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl MemoryTransport {
+        fn new(in_use: bool, reply: &str) -> Self {
+            MemoryTransport { in_use, reply: reply.to_string(), sent: Arc::new(Mutex::new(Vec::new())) }
+        }
+
+        fn sent(&self) -> String {
+            String::from_utf8(self.sent.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl InstanceTransport for MemoryTransport {
+        fn bind(&self) -> io::Result<Box<dyn InstanceListener>> {
+            if self.in_use {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "Another instance is running"));
+            }
+            Ok(Box::new(NoConnections))
+        }
+
+        fn connect(&self) -> io::Result<Box<dyn InstanceStream>> {
+            if !self.in_use {
+                return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+            }
+            Ok(Box::new(MemoryStream { input: Cursor::new(self.reply.as_bytes().to_vec()), output: self.sent.clone() }))
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// What the running instance answers to `input`, and the arguments it got.
+    fn handle(input: &str, result: Result<(), String>) -> (String, Vec<Vec<String>>) {
+        let received = Mutex::new(Vec::new());
+        let (stream, output) = stream(input);
+        handle_connection(Box::new(stream), &|args| {
+            received.lock().unwrap().push(args);
+            result.clone()
+        })
+        .unwrap();
+        let reply = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (reply, received.into_inner().unwrap())
+    }
+
+    #[test]
+    fn first_launch_becomes_primary() {
+        let transport = MemoryTransport::new(false, "");
+        assert!(matches!(acquire_on(&transport, &args(&["--minimized"])), Ok(Instance::Primary(_))));
+        assert_eq!(transport.sent(), "");
+    }
+
+    #[test]
+    fn later_launches_hand_over_their_arguments() {
+        let transport = MemoryTransport::new(true, "{\"error\":null}\n");
+        let instance = acquire_on(&transport, &args(&["clockblocks://switch/Code"])).unwrap();
+        assert!(matches!(instance, Instance::Secondary(Ok(()))));
+        assert_eq!(transport.sent(), "[\"clockblocks://switch/Code\"]\n");
+
+        let transport = MemoryTransport::new(true, "{\"error\":\"No time box Code\"}\n");
+        let instance = acquire_on(&transport, &args(&["clockblocks://switch/Code"])).unwrap();
+        assert!(matches!(instance, Instance::Secondary(Err(e)) if e == "No time box Code"));
+    }
+
+    #[test]
+    fn sending_fails_when_nothing_listens() {
+        let transport = MemoryTransport::new(false, "");
+        let error = send_args(&transport, &args(&["--minimized"])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn passes_arguments_to_the_handler() {
+        let (reply, received) = handle("[\"a\",\"b c\"]\n", Ok(()));
+        assert_eq!(received, vec![args(&["a", "b c"])]);
+        assert_eq!(reply, "{\"error\":null}\n");
+
+        // The reply is what send_args reads back
+        let transport = MemoryTransport::new(true, &reply);
+        assert!(send_args(&transport, &args(&["a", "b c"])).unwrap().is_ok());
+    }
+
+    #[test]
+    fn replies_with_the_handler_error() {
+        let (reply, _) = handle("[\"--switch\"]\n", Err("No time box".to_string()));
+        assert_eq!(reply, "{\"error\":\"No time box\"}\n");
+
+        let transport = MemoryTransport::new(true, &reply);
+        assert_eq!(send_args(&transport, &args(&["--switch"])).unwrap(), Err("No time box".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments_without_calling_the_handler() {
+        let (reply, received) = handle("not json\n", Ok(()));
+        assert!(received.is_empty());
+        assert!(reply.starts_with("{\"error\":\"Invalid arguments"), "{}", reply);
+    }
+
+    #[test]
+    fn ignores_connections_that_only_probe() {
+        let (reply, received) = handle("", Ok(()));
+        assert!(received.is_empty());
+        assert_eq!(reply, "");
+    }
+}
@@ -0,0 +1,186 @@
+//! The chess clock runs in the webview (`App.tsx`), which also writes the
+//! sessions. The backend keeps a copy of its state, reported through
+//! `report_timer_state`, and asks it to switch boxes or end the session with
+//! the `timer-command` event, so there is a single writer for the database.
+
+use crate::db::{self, TimeBox};
+use crate::events::{self, SessionChanged, TimeBoxSwitched};
+use crate::importers;
+use crate::pomodoro::CompletedInterval;
+use crate::AppState;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
+
+/// How long to wait for the webview to confirm a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long a command sent during launch waits for the webview to load
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// The box breaks and time away go to, named apart from the reports' Break
+/// column, which is the time between events
+pub const BREAK_TIME_BOX_NAME: &str = "Rest";
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimerState {
+    pub active_time_box_id: Option<String>,
+    pub session_id: Option<String>,
+    pub session_start: Option<String>,
+    /// Start of the running event, which is only written to the database on
+    /// the next switch
+    pub event_start: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TimerCommand {
+    Switch {
+        #[serde(rename = "timeBoxId")]
+        time_box_id: String,
+        /// Recorded on the event the switch ends
+        #[serde(rename = "completedInterval", skip_serializing_if = "Option::is_none")]
+        completed_interval: Option<CompletedInterval>,
+    },
+    EndSession,
+}
+
+/// The last reported state, `None` until the webview has loaded.
+pub fn reported_state(app_handle: &tauri::AppHandle) -> Option<TimerState> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let timer = state.timer.lock().ok().and_then(|timer| timer.clone());
+    timer
+}
+
+pub fn current_state(app_handle: &tauri::AppHandle) -> TimerState {
+    reported_state(app_handle).unwrap_or_default()
+}
+
+/// Blocks until the reported state satisfies `is_done`, woken up by each
+/// `report_timer_state`.
+fn wait_for_report(
+    app_handle: &tauri::AppHandle,
+    timeout: Duration,
+    timeout_error: &str,
+    is_done: impl Fn(Option<&TimerState>) -> bool,
+) -> Result<TimerState, String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let timer = state.timer.lock().map_err(|_| "Failed to lock timer state".to_string())?;
+    let (timer, result) = state
+        .timer_reported
+        .wait_timeout_while(timer, timeout, |timer| !is_done(timer.as_ref()))
+        .map_err(|_| "Failed to lock timer state".to_string())?;
+    if result.timed_out() {
+        return Err(timeout_error.to_string());
+    }
+    Ok(timer.clone().unwrap_or_default())
+}
+
+/// Waits for the webview's first report, for commands that arrive while the
+/// app is still launching.
+fn wait_for_webview(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    wait_for_report(app_handle, STARTUP_TIMEOUT, "The timer did not load", |timer| timer.is_some()).map(|_| ())
+}
+
+/// Publishes the session and box changes between two reported states.
+fn publish_changes(app_handle: &tauri::AppHandle, previous: &TimerState, current: &TimerState) {
+    let now = db::format_datetime(&Utc::now());
+    let same_session = previous.session_id == current.session_id;
+
+    if previous.active_time_box_id.is_some() && (current.active_time_box_id.is_none() || !same_session) {
+        events::publish(app_handle, events::SESSION_ENDED, SessionChanged {
+            session_id: previous.session_id.clone(),
+            time_box_id: previous.active_time_box_id.clone(),
+            at: now.clone(),
+        });
+    }
+    let Some(time_box_id) = &current.active_time_box_id else {
+        return;
+    };
+    let is_new_session = previous.active_time_box_id.is_none() || !same_session;
+    if is_new_session {
+        events::publish(app_handle, events::SESSION_STARTED, SessionChanged {
+            session_id: current.session_id.clone(),
+            time_box_id: Some(time_box_id.clone()),
+            at: current.session_start.clone().unwrap_or_else(|| now.clone()),
+        });
+    }
+    if is_new_session || previous.active_time_box_id.as_ref() != Some(time_box_id) {
+        events::publish(app_handle, events::TIME_BOX_SWITCHED, TimeBoxSwitched {
+            time_box_id: time_box_id.clone(),
+            previous_time_box_id: if is_new_session { None } else { previous.active_time_box_id.clone() },
+            session_id: current.session_id.clone(),
+            at: current.event_start.clone().unwrap_or(now),
+        });
+    }
+}
+
+/// Called by the webview whenever the active box or session changes.
+#[tauri::command]
+pub fn report_timer_state(app_handle: tauri::AppHandle, state: TimerState) -> Result<(), String> {
+    let app_state: tauri::State<Arc<AppState>> = app_handle.state();
+    let mut timer = app_state.timer.lock().map_err(|_| "Failed to lock timer state".to_string())?;
+    let previous = timer.replace(state.clone()).unwrap_or_default();
+    drop(timer);
+    app_state.timer_reported.notify_all();
+    publish_changes(&app_handle, &previous, &state);
+    Ok(())
+}
+
+/// Finds a box that isn't deleted by id, or else by case-insensitive name.
+pub fn find_time_box(app_handle: &tauri::AppHandle, key: &str) -> Result<Option<TimeBox>, String> {
+    db::find_time_box(&db::open_connection(app_handle)?, key)
+}
+
+/// The Rest box, created with the next unused colour if there is none.
+pub fn break_time_box_id(app_handle: &tauri::AppHandle) -> Result<String, String> {
+    let conn = db::open_connection(app_handle)?;
+    let existing = db::get_time_boxes(&conn)?;
+    let mut created = Vec::new();
+    let id = importers::resolve_time_box(&existing, &mut created, BREAK_TIME_BOX_NAME.to_string());
+    for time_box in &created {
+        db::insert_time_box(&conn, time_box)?;
+    }
+    Ok(id)
+}
+
+/// Sends the command to the webview and waits until its reported state
+/// satisfies `is_done`.
+fn run_command(
+    app_handle: &tauri::AppHandle,
+    command: TimerCommand,
+    is_done: impl Fn(&TimerState) -> bool,
+) -> Result<TimerState, String> {
+    app_handle.emit_all("timer-command", command).map_err(|e| e.to_string())?;
+    wait_for_report(app_handle, COMMAND_TIMEOUT, "The timer did not respond", |timer| timer.is_some_and(&is_done))
+}
+
+/// Makes `time_box_id` the running box, starting a session if there is none.
+pub fn switch_to(app_handle: &tauri::AppHandle, time_box_id: &str) -> Result<TimerState, String> {
+    switch_completing(app_handle, time_box_id, None)
+}
+
+/// Switches like `switch_to`, marking the event that ends as `completed_interval`.
+pub fn switch_completing(
+    app_handle: &tauri::AppHandle,
+    time_box_id: &str,
+    completed_interval: Option<CompletedInterval>,
+) -> Result<TimerState, String> {
+    wait_for_webview(app_handle)?;
+    let state = current_state(app_handle);
+    if state.active_time_box_id.as_deref() == Some(time_box_id) {
+        return Ok(state);
+    }
+    let command = TimerCommand::Switch { time_box_id: time_box_id.to_string(), completed_interval };
+    run_command(app_handle, command, |state| state.active_time_box_id.as_deref() == Some(time_box_id))
+}
+
+pub fn end_session(app_handle: &tauri::AppHandle) -> Result<TimerState, String> {
+    wait_for_webview(app_handle)?;
+    let state = current_state(app_handle);
+    if state.active_time_box_id.is_none() {
+        return Ok(state);
+    }
+    run_command(app_handle, TimerCommand::EndSession, |state| state.active_time_box_id.is_none())
+}
@@ -0,0 +1,221 @@
+//! Plain text timesheets for weekly reviews: an Org-mode file with the events
+//! clocked under a heading per time box, and a Markdown report per day.
+
+use crate::db;
+use crate::report::{self, DateRange, ReportData};
+use chrono::{DateTime, Local, NaiveDate};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+
+fn org_timestamp(datetime: &DateTime<Local>) -> String {
+    datetime.format("[%Y-%m-%d %a %H:%M]").to_string()
+}
+
+/// Org's clock duration, hours and minutes of the rounded timestamps.
+fn org_clock_duration(start: &DateTime<Local>, end: &DateTime<Local>) -> String {
+    let minutes = (end.timestamp() / 60 - start.timestamp() / 60).max(0);
+    format!("{:>2}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// A heading per time box with its events as `CLOCK:` lines in a LOGBOOK
+/// drawer, newest first like Org records them. The clocktable block at the top
+/// fills in the totals when updated with `C-c C-c`.
+pub fn render_org(data: &ReportData, range: &DateRange) -> String {
+    let mut org = format!("#+TITLE: ClockBlocks timesheet, {}\n", range.title());
+    org.push_str("\n#+BEGIN: clocktable :scope file :maxlevel 1\n#+END:\n");
+
+    for name in data.event_time_box_names() {
+        let mut clocks: Vec<(DateTime<Local>, DateTime<Local>)> = data
+            .session_events
+            .iter()
+            .filter(|event| data.time_box_name(&event.time_box_id) == name)
+            .filter_map(|event| {
+                let start = db::parse_datetime(&event.start_datetime)?.with_timezone(&Local);
+                let end = db::parse_datetime(&event.end_datetime)?.with_timezone(&Local);
+                Some((start, end))
+            })
+            .collect();
+        if clocks.is_empty() {
+            continue;
+        }
+        clocks.sort_by(|a, b| b.0.cmp(&a.0));
+
+        org.push_str(&format!("\n* {}\n:LOGBOOK:\n", name));
+        for (start, end) in clocks {
+            org.push_str(&format!(
+                "CLOCK: {}--{} => {}\n",
+                org_timestamp(&start),
+                org_timestamp(&end),
+                org_clock_duration(&start, &end)
+            ));
+        }
+        org.push_str(":END:\n");
+    }
+    org
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// One table of box durations, followed by the tracked time, the Break between
+/// events and the whole session time. Boxes missing from `names`, like events
+/// filed under Unknown, come after the listed ones.
+fn markdown_table(names: &[String], durations: &HashMap<String, i64>) -> String {
+    let mut unlisted: Vec<&String> = durations.keys().filter(|name| !names.contains(name)).collect();
+    unlisted.sort();
+
+    let mut table = "| Time box | Duration |\n| --- | ---: |\n".to_string();
+    let mut tracked = 0;
+    for name in names.iter().chain(unlisted) {
+        if name == report::BREAK_NAME {
+            continue;
+        }
+        let Some(&seconds) = durations.get(name).filter(|seconds| **seconds > 0) else {
+            continue;
+        };
+        tracked += seconds;
+        table.push_str(&format!("| {} | {} |\n", escape_markdown(name), report::format_duration(seconds)));
+    }
+    let break_seconds = durations.get(report::BREAK_NAME).copied().unwrap_or(0);
+    table.push_str(&format!("| **Tracked** | **{}** |\n", report::format_duration(tracked)));
+    table.push_str(&format!("| {} | {} |\n", report::BREAK_NAME, report::format_duration(break_seconds)));
+    table.push_str(&format!("| **Total** | **{}** |\n", report::format_duration(tracked + break_seconds)));
+    table
+}
+
+fn day_heading(date: &NaiveDate) -> String {
+    format!("{} {}", date.format("%A"), report::format_date(date))
+}
+
+/// Per-day tables of time per box with the Break derived like the sheet sync
+/// does, then the totals over the whole range.
+pub fn render_markdown(data: &ReportData, range: &DateRange) -> String {
+    let names = data.unique_time_box_names();
+    let mut days = report::durations_by_date(data);
+    days.sort_by_key(|(date, _)| *date);
+
+    let mut markdown = format!("# ClockBlocks timesheet\n\n{}\n", range.title());
+    if days.is_empty() {
+        markdown.push_str("\nNo sessions in this range.\n");
+        return markdown;
+    }
+
+    let mut totals: HashMap<String, i64> = HashMap::new();
+    for (date, durations) in &days {
+        markdown.push_str(&format!("\n## {}\n\n", day_heading(date)));
+        markdown.push_str(&markdown_table(&names, durations));
+        for (name, seconds) in durations {
+            *totals.entry(name.clone()).or_insert(0) += seconds;
+        }
+    }
+
+    let day_count = if days.len() == 1 { "1 day".to_string() } else { format!("{} days", days.len()) };
+    markdown.push_str(&format!("\n## Totals\n\n{} with sessions\n\n", day_count));
+    markdown.push_str(&markdown_table(&names, &totals));
+    markdown
+}
+
+fn pick_save_path(path: Option<String>, title: &str, file_name: &str, filter: &str, extension: &str) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(PathBuf::from(path)),
+        None => FileDialogBuilder::new()
+            .set_title(title)
+            .set_file_name(file_name)
+            .add_filter(filter, &[extension])
+            .save_file(),
+    }
+}
+
+fn export(
+    app_handle: &tauri::AppHandle,
+    path: PathBuf,
+    range: DateRange,
+    render: fn(&ReportData, &DateRange) -> String,
+) -> Result<String, String> {
+    let conn = db::open_connection(app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    fs::write(&path, render(&data, &range)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    println!("Exported timesheet of {} sessions to {}", data.sessions.len(), path.display());
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+pub async fn export_org(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<String>, String> {
+    let range = DateRange::parse(from, to)?;
+    let Some(path) = pick_save_path(path, "Export Org-mode timesheet", "clockblocks.org", "Org-mode", "org") else {
+        return Ok(None);
+    };
+    export(&app_handle, path, range, render_org).map(Some)
+}
+
+#[tauri::command]
+pub async fn export_markdown(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<String>, String> {
+    let range = DateRange::parse(from, to)?;
+    let Some(path) = pick_save_path(path, "Export Markdown report", "clockblocks.md", "Markdown", "md") else {
+        return Ok(None);
+    };
+    export(&app_handle, path, range, render_markdown).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Session, SessionEvent, TimeBox};
+
+    fn event(id: &str, time_box_id: &str, start: &str, end: &str) -> SessionEvent {
+        SessionEvent {
+            id: id.to_string(),
+            time_box_id: time_box_id.to_string(),
+            session_id: "s1".to_string(),
+            start_datetime: start.to_string(),
+            end_datetime: end.to_string(),
+            seconds: 0,
+            pomodoro_phase: None,
+            pomodoro_cycle: None,
+        }
+    }
+
+    #[test]
+    fn clocks_events_of_deleted_boxes_under_unknown() {
+        // As loaded, without the deleted box b2
+        let data = ReportData {
+            time_boxes: vec![TimeBox {
+                id: "b1".to_string(),
+                name: "Code".to_string(),
+                is_hidden: false,
+                is_deleted: false,
+                colour: "#1673FF".to_string(),
+            }],
+            sessions: vec![Session {
+                id: "s1".to_string(),
+                start_datetime: "2024-08-01T09:00:00.000Z".to_string(),
+                end_datetime: Some("2024-08-01T11:00:00.000Z".to_string()),
+                duration: Some(7200),
+            }],
+            session_events: vec![
+                event("e1", "b1", "2024-08-01T09:00:00.000Z", "2024-08-01T10:00:00.000Z"),
+                event("e2", "b2", "2024-08-01T10:00:00.000Z", "2024-08-01T10:30:00.000Z"),
+            ],
+        };
+        let org = render_org(&data, &DateRange::default());
+        let code = org.find("\n* Code\n").unwrap();
+        let unknown = org.find("\n* Unknown\n").unwrap();
+        assert!(code < unknown);
+        assert_eq!(org.matches("CLOCK: ").count(), 2);
+        assert!(org[unknown..].contains("=>  0:30"));
+    }
+}
@@ -0,0 +1,505 @@
+//! Reading and writing Timewarrior's `data/YYYY-MM.data` files, one interval
+//! per line: `inc 20240801T090000Z - 20240801T100000Z # tag "other tag"`.
+//! Tags map to time boxes; ClockBlocks exports each event tagged with its box.
+
+use crate::db;
+use crate::importers::{self, ImportReport, ImportedEntry, ProjectMapping, SessionGrouping};
+use crate::report::{DateRange, ReportData};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::api::dialog::blocking::FileDialogBuilder;
+
+const DATETIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const TAGS_FILE_NAME: &str = "tags.data";
+
+/// A line of a data file. Open intervals, the one being tracked, have no end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimewarriorExportReport {
+    pub files: Vec<String>,
+    pub intervals_added: usize,
+    /// Events already in the data files with the same start, end and tag
+    pub intervals_existing: usize,
+}
+
+fn parse_datetime(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, DATETIME_FORMAT).ok().map(|datetime| datetime.and_utc())
+}
+
+fn format_datetime(datetime: &DateTime<Utc>) -> String {
+    datetime.format(DATETIME_FORMAT).to_string()
+}
+
+/// Splits a line into words, honouring double quotes and backslash escapes.
+/// The flag tells a quoted "#" tag apart from the `#` separator.
+fn tokenize(line: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => token.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+            tokens.push((token, true));
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push((token, false));
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn parse_line(line: &str) -> Result<Interval, String> {
+    let tokens = tokenize(line)?;
+    let word = |index: usize| tokens.get(index).filter(|(_, quoted)| !quoted).map(|(token, _)| token.as_str());
+
+    if word(0) != Some("inc") {
+        return Err("not an interval".to_string());
+    }
+    let start = word(1).and_then(parse_datetime).ok_or("invalid start".to_string())?;
+    let (end, mut index) = match word(2) {
+        Some("-") => (Some(word(3).and_then(parse_datetime).ok_or("invalid end".to_string())?), 4),
+        _ => (None, 2),
+    };
+
+    let mut tags = Vec::new();
+    if word(index) == Some("#") {
+        index += 1;
+        // A second `#` starts the annotation, which has no place in ClockBlocks
+        while let Some((token, quoted)) = tokens.get(index) {
+            if !quoted && token == "#" {
+                break;
+            }
+            tags.push(token.clone());
+            index += 1;
+        }
+    }
+
+    Ok(Interval { start, end, tags })
+}
+
+/// Quotes tags the way Timewarrior does, so they read back as one tag.
+fn quote_tag(tag: &str) -> String {
+    if !tag.is_empty() && !tag.chars().any(|c| c.is_whitespace() || c == '"' || c == '#' || c == '\\') {
+        return tag.to_string();
+    }
+    format!("\"{}\"", tag.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn format_line(interval: &Interval) -> String {
+    let mut line = format!("inc {}", format_datetime(&interval.start));
+    if let Some(end) = interval.end {
+        line.push_str(&format!(" - {}", format_datetime(&end)));
+    }
+    if !interval.tags.is_empty() {
+        let tags: Vec<String> = interval.tags.iter().map(|tag| quote_tag(tag)).collect();
+        line.push_str(&format!(" # {}", tags.join(" ")));
+    }
+    line
+}
+
+/// `$TIMEWARRIORDB/data`, `~/.timewarrior/data` or the XDG location used by
+/// Timewarrior 1.5, whichever exists.
+pub fn find_data_dir() -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(db) = std::env::var_os("TIMEWARRIORDB") {
+        candidates.push(PathBuf::from(db).join("data"));
+    }
+    if let Some(home) = tauri::api::path::home_dir() {
+        candidates.push(home.join(".timewarrior").join("data"));
+    }
+    if let Some(data) = tauri::api::path::data_dir() {
+        candidates.push(data.join("timewarrior").join("data"));
+    }
+    candidates.into_iter().find(|candidate| candidate.is_dir())
+}
+
+fn is_month_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_suffix(".data"))
+        .is_some_and(|month| chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_ok())
+}
+
+/// The month files to read: the file itself, or every `YYYY-MM.data` in a directory.
+fn data_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_month_file(path))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads every interval of the files. Lines that can't be read are returned
+/// as messages instead of failing the whole import.
+pub fn read_intervals(files: &[PathBuf]) -> Result<(Vec<Interval>, Vec<String>), String> {
+    let mut intervals = Vec::new();
+    let mut skipped = Vec::new();
+    for file in files {
+        let contents = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let file_name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_line(line) {
+                Ok(interval) => intervals.push(interval),
+                Err(e) => skipped.push(format!("{} line {}: {}", file_name, index + 1, e)),
+            }
+        }
+    }
+    Ok((intervals, skipped))
+}
+
+/// An interval carries any number of tags but an event belongs to one box. The
+/// first mapped tag wins, then the first naming an existing box, then the first.
+fn pick_tag(tags: &[String], mapping: &ProjectMapping, time_box_names: &[String]) -> String {
+    tags.iter()
+        .find(|tag| mapping.contains_key(*tag))
+        .or_else(|| tags.iter().find(|tag| time_box_names.iter().any(|name| name.eq_ignore_ascii_case(tag))))
+        .or_else(|| tags.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn to_entries(
+    intervals: Vec<Interval>,
+    mapping: &ProjectMapping,
+    time_box_names: &[String],
+    skipped: &mut Vec<String>,
+) -> Vec<ImportedEntry> {
+    let mut entries = Vec::new();
+    for interval in intervals {
+        match interval.end {
+            Some(end) if end > interval.start => entries.push(ImportedEntry {
+                project: pick_tag(&interval.tags, mapping, time_box_names),
+                task: None,
+                start: interval.start,
+                end,
+                laid_out: false,
+            }),
+            Some(_) => skipped.push(format!("Interval at {}: end is not after start", format_datetime(&interval.start))),
+            None => skipped.push(format!("Interval at {}: still being tracked", format_datetime(&interval.start))),
+        }
+    }
+    entries
+}
+
+/// Imports a data file, or every month file of a data directory. Without a
+/// path the user picks a file, unless `use_local_database` asks for the local
+/// Timewarrior database; see `timewarrior_data_dir`.
+#[tauri::command]
+pub async fn import_timewarrior(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    use_local_database: bool,
+    mapping: Option<ProjectMapping>,
+    grouping: SessionGrouping,
+    dry_run: bool,
+) -> Result<Option<ImportReport>, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None if use_local_database => find_data_dir().ok_or("No local Timewarrior database found".to_string())?,
+        None => match importers::pick_import_file(None, "Import Timewarrior data", &["data"]) {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let (intervals, mut skipped_rows) = read_intervals(&data_files(&path)?)?;
+    let mapping = mapping.unwrap_or_default();
+    let time_box_names: Vec<String> = db::get_time_boxes(&db::open_connection(&app_handle)?)?
+        .into_iter()
+        .filter(|time_box| !time_box.is_deleted)
+        .map(|time_box| time_box.name)
+        .collect();
+    let entries = to_entries(intervals, &mapping, &time_box_names, &mut skipped_rows);
+    let report = importers::run_import(&app_handle, entries, skipped_rows, Some(mapping), grouping, dry_run)?;
+
+    println!("Timewarrior import from {}: {:?}", path.display(), report);
+    Ok(Some(report))
+}
+
+/// One closed interval per session event, tagged with the box name.
+pub fn intervals_for(data: &ReportData) -> Vec<Interval> {
+    data.session_events
+        .iter()
+        .filter_map(|event| {
+            Some(Interval {
+                start: db::parse_datetime(&event.start_datetime)?,
+                end: Some(db::parse_datetime(&event.end_datetime)?),
+                tags: vec![data.time_box_name(&event.time_box_id)],
+            })
+        })
+        .collect()
+}
+
+/// Timewarrior keeps a count per tag in `tags.data`; new tags have to be added
+/// there or `timew tags` won't list them.
+fn update_tag_counts(directory: &Path, added: &BTreeMap<String, u64>) -> Result<(), String> {
+    let path = directory.join(TAGS_FILE_NAME);
+    let mut tags: Value = match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))?,
+        Err(_) => json!({}),
+    };
+    let Some(tags_object) = tags.as_object_mut() else {
+        return Err(format!("Invalid {}: expected an object", path.display()));
+    };
+    for (tag, count) in added {
+        let entry = tags_object.entry(tag.clone()).or_insert(json!({ "count": 0 }));
+        entry["count"] = json!(entry["count"].as_u64().unwrap_or(0) + count);
+    }
+    let contents = serde_json::to_string_pretty(&tags).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Merges the intervals into the month files of `directory`, keeping every line
+/// already there and skipping intervals that are present with the same tag.
+pub fn merge_into_data_dir(directory: &Path, intervals: Vec<Interval>) -> Result<TimewarriorExportReport, String> {
+    let mut by_month: BTreeMap<String, Vec<Interval>> = BTreeMap::new();
+    for interval in intervals {
+        by_month.entry(interval.start.format("%Y-%m").to_string()).or_default().push(interval);
+    }
+
+    let mut report = TimewarriorExportReport::default();
+    let mut added_tags: BTreeMap<String, u64> = BTreeMap::new();
+
+    for (month, intervals) in by_month {
+        let path = directory.join(format!("{}.data", month));
+        let contents = if path.exists() {
+            fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        } else {
+            String::new()
+        };
+        let mut lines: Vec<String> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect();
+        let existing: Vec<Interval> = lines.iter().filter_map(|line| parse_line(line).ok()).collect();
+
+        let mut added = 0;
+        for interval in intervals {
+            let is_present = existing.iter().any(|other| {
+                other.start == interval.start
+                    && other.end == interval.end
+                    && interval.tags.iter().all(|tag| other.tags.contains(tag))
+            });
+            if is_present {
+                report.intervals_existing += 1;
+                continue;
+            }
+            for tag in &interval.tags {
+                *added_tags.entry(tag.clone()).or_default() += 1;
+            }
+            lines.push(format_line(&interval));
+            added += 1;
+        }
+        if added == 0 {
+            continue;
+        }
+
+        // Timewarrior expects the lines of a file in start order, and the fixed
+        // width timestamp right after "inc " sorts the same as a string
+        lines.sort();
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        report.intervals_added += added;
+        report.files.push(path.display().to_string());
+    }
+
+    if !added_tags.is_empty() {
+        update_tag_counts(directory, &added_tags)?;
+    }
+    Ok(report)
+}
+
+/// Exports session events into a Timewarrior data directory. Without a
+/// `directory` the user is asked to pick one; see `timewarrior_data_dir`.
+#[tauri::command]
+pub async fn export_timewarrior(
+    app_handle: tauri::AppHandle,
+    directory: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<TimewarriorExportReport>, String> {
+    let range = DateRange::parse(from, to)?;
+
+    let directory = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => match FileDialogBuilder::new().set_title("Export to Timewarrior data folder").pick_folder() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        },
+    };
+    fs::create_dir_all(&directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+    let conn = db::open_connection(&app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    let report = merge_into_data_dir(&directory, intervals_for(&data))?;
+
+    println!("Timewarrior export to {}: {:?}", directory.display(), report);
+    Ok(Some(report))
+}
+
+/// The local Timewarrior data directory, for the frontend to suggest as target.
+#[tauri::command]
+pub fn timewarrior_data_dir() -> Option<String> {
+    find_data_dir().map(|path| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(value: &str) -> DateTime<Utc> {
+        parse_datetime(value).unwrap()
+    }
+
+    fn interval(start: &str, end: Option<&str>, tags: &[&str]) -> Interval {
+        Interval {
+            start: datetime(start),
+            end: end.map(datetime),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    /// A directory of its own under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("clockblocks-timewarrior-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn tokenizes_quotes_and_escapes() {
+        let tokens = tokenize(r##"inc  "two words" "say \"hi\"" "#" #"##).unwrap();
+        let expected = [("inc", false), ("two words", true), ("say \"hi\"", true), ("#", true), ("#", false)];
+        assert_eq!(tokens, expected.map(|(token, quoted)| (token.to_string(), quoted)));
+        assert_eq!(tokenize(r#"inc "open"#), Err("unterminated quote".to_string()));
+    }
+
+    #[test]
+    fn quotes_only_tags_that_need_it() {
+        assert_eq!(quote_tag("Code"), "Code");
+        assert_eq!(quote_tag("two words"), r#""two words""#);
+        assert_eq!(quote_tag(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_tag(r"back\slash"), r#""back\\slash""#);
+        assert_eq!(quote_tag("#"), r##""#""##);
+        assert_eq!(quote_tag(""), r#""""#);
+    }
+
+    #[test]
+    fn lines_read_back_as_written() {
+        let intervals = [
+            interval("20240801T090000Z", Some("20240801T100000Z"), &["Code"]),
+            interval("20240801T090000Z", Some("20240801T100000Z"), &["two words", r#"say "hi""#, r"back\slash", "#", ""]),
+            interval("20240801T090000Z", None, &["Code"]),
+            interval("20240801T090000Z", Some("20240801T100000Z"), &[]),
+        ];
+        for interval in intervals {
+            let line = format_line(&interval);
+            assert_eq!(parse_line(&line), Ok(interval), "{}", line);
+        }
+        assert_eq!(format_line(&interval("20240801T090000Z", None, &["Code"])), "inc 20240801T090000Z # Code");
+    }
+
+    #[test]
+    fn drops_annotations() {
+        let line = r#"inc 20240801T090000Z - 20240801T100000Z # Code "Deep work" # fixed "the" bug"#;
+        assert_eq!(parse_line(line), Ok(interval("20240801T090000Z", Some("20240801T100000Z"), &["Code", "Deep work"])));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse_line(""), Err("not an interval".to_string()));
+        assert_eq!(parse_line("exc 20240801T090000Z"), Err("not an interval".to_string()));
+        assert_eq!(parse_line("inc yesterday"), Err("invalid start".to_string()));
+        assert_eq!(parse_line("inc 20240801T090000Z - later # Code"), Err("invalid end".to_string()));
+        assert_eq!(parse_line(r#"inc 20240801T090000Z # "Code"#), Err("unterminated quote".to_string()));
+    }
+
+    #[test]
+    fn merges_sorted_into_month_files_and_counts_new_tags() {
+        let directory = TempDir::new();
+        let august = directory.0.join("2024-08.data");
+        fs::write(
+            &august,
+            "inc 20240801T100000Z - 20240801T110000Z # Code\n\ninc 20240801T080000Z - 20240801T090000Z # Read \"Chapter 2\"\n",
+        )
+        .unwrap();
+        fs::write(directory.0.join(TAGS_FILE_NAME), r#"{"Code":{"count":1},"Read":{"count":1}}"#).unwrap();
+
+        let report = merge_into_data_dir(
+            &directory.0,
+            vec![
+                interval("20240801T100000Z", Some("20240801T110000Z"), &["Code"]),
+                interval("20240801T080000Z", Some("20240801T090000Z"), &["Read"]),
+                interval("20240801T090000Z", Some("20240801T100000Z"), &["Code"]),
+                interval("20240902T090000Z", Some("20240902T093000Z"), &["two words"]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!((report.intervals_added, report.intervals_existing), (2, 2));
+        assert_eq!(
+            fs::read_to_string(&august).unwrap(),
+            "inc 20240801T080000Z - 20240801T090000Z # Read \"Chapter 2\"\n\
+             inc 20240801T090000Z - 20240801T100000Z # Code\n\
+             inc 20240801T100000Z - 20240801T110000Z # Code\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.0.join("2024-09.data")).unwrap(),
+            "inc 20240902T090000Z - 20240902T093000Z # \"two words\"\n"
+        );
+        let tags: Value = serde_json::from_str(&fs::read_to_string(directory.0.join(TAGS_FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(tags, json!({ "Code": { "count": 2 }, "Read": { "count": 1 }, "two words": { "count": 1 } }));
+
+        // Nothing is new the second time
+        let again = merge_into_data_dir(&directory.0, vec![interval("20240801T090000Z", Some("20240801T100000Z"), &["Code"])]);
+        assert_eq!(again.unwrap().intervals_added, 0);
+    }
+}
@@ -0,0 +1,496 @@
+//! Outgoing webhooks for the events in `events.rs`. Each target gets a JSON
+//! POST signed with its own secret:
+//!
+//! ```text
+//! X-ClockBlocks-Event: time-box-switched
+//! X-ClockBlocks-Delivery: <uuid>
+//! X-ClockBlocks-Signature: sha256=<hex HMAC-SHA256 of the body>
+//! ```
+//!
+//! Deliveries run on a worker thread that retries failures with backoff, and
+//! every attempt is kept in a delivery log in the data directory. Client
+//! errors other than 408 and 429 mean the receiver won't take the delivery,
+//! so those aren't retried.
+
+use crate::db;
+use crate::AppState;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::Manager;
+
+const WEBHOOKS_FILE_NAME: &str = "webhooks.json";
+const LOG_FILE_NAME: &str = "webhook_log.json";
+/// Attempts kept in the delivery log, the oldest are dropped first
+const LOG_LIMIT: usize = 200;
+/// Wait before each retry; a delivery is given up after the last one
+const RETRY_DELAYS: [Duration; 4] = [
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(30 * 60),
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_EVENT: &str = "ping";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookTarget {
+    #[serde(default)]
+    pub id: String,
+    pub url: String,
+    /// Key for the signature, generated when left empty
+    #[serde(default)]
+    pub secret: String,
+    /// Event names to send, or every event when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl WebhookTarget {
+    fn wants(&self, event: &str) -> bool {
+        self.enabled && (self.events.is_empty() || self.events.iter().any(|name| name == event))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct WebhookSettings {
+    targets: Vec<WebhookTarget>,
+}
+
+/// One event on its way to one target.
+pub struct Delivery {
+    id: String,
+    target: WebhookTarget,
+    event: String,
+    body: String,
+    attempt: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryAttempt {
+    pub delivery_id: String,
+    pub target_id: String,
+    pub url: String,
+    pub event: String,
+    /// Starts at 1
+    pub attempt: usize,
+    pub at: String,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+    /// When the next attempt is due, if there is one
+    pub retry_at: Option<String>,
+}
+
+fn get_settings_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(WEBHOOKS_FILE_NAME)
+}
+
+fn get_log_path(app_handle: &tauri::AppHandle) -> PathBuf {
+    crate::get_data_dir(app_handle).join(LOG_FILE_NAME)
+}
+
+fn load_settings(app_handle: &tauri::AppHandle) -> Result<WebhookSettings, String> {
+    let path = get_settings_path(app_handle);
+    if !path.exists() {
+        return Ok(WebhookSettings::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid webhook settings: {}", e))
+}
+
+fn save_settings(app_handle: &tauri::AppHandle, settings: &WebhookSettings) -> Result<(), String> {
+    let path = get_settings_path(app_handle);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| e.to_string())
+}
+
+fn generate_secret() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+/// Hex HMAC-SHA256 of `body`, which receivers recompute with their copy of
+/// the secret.
+pub fn sign(secret: &str, body: &str) -> Result<String, String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| e.to_string())?;
+    mac.update(body.as_bytes());
+    Ok(mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn new_delivery(target: &WebhookTarget, event: &str, data: &Value) -> Delivery {
+    let id = uuid::Uuid::new_v4().to_string();
+    let body = json!({
+        "id": id,
+        "event": event,
+        "sentAt": db::format_datetime(&Utc::now()),
+        "data": data,
+    });
+    Delivery { id, target: target.clone(), event: event.to_string(), body: body.to_string(), attempt: 0 }
+}
+
+/// Queues `data` for every target that wants `event`. Called by
+/// `events::publish`, so it never fails the change that caused it.
+pub fn notify(app_handle: &tauri::AppHandle, event: &str, data: &Value) {
+    let settings = match load_settings(app_handle) {
+        Ok(settings) => settings,
+        Err(e) => return println!("{}", e),
+    };
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let Ok(queue) = state.webhook_queue.lock() else {
+        return;
+    };
+    for target in settings.targets.iter().filter(|target| target.wants(event)) {
+        if queue.send(new_delivery(target, event, data)).is_err() {
+            println!("Webhook worker isn't running, dropped {} for {}", event, target.url);
+        }
+    }
+}
+
+/// Makes one attempt, returning the status code of the response.
+fn send(client: &reqwest::blocking::Client, delivery: &Delivery) -> Result<u16, String> {
+    let signature = sign(&delivery.target.secret, &delivery.body)?;
+    let response = client
+        .post(&delivery.target.url)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "ClockBlocks-Webhooks")
+        .header("X-ClockBlocks-Event", &delivery.event)
+        .header("X-ClockBlocks-Delivery", &delivery.id)
+        .header("X-ClockBlocks-Signature", format!("sha256={}", signature))
+        .body(delivery.body.clone())
+        .send()
+        .map_err(|e| e.to_string())?;
+    Ok(response.status().as_u16())
+}
+
+fn record_attempt(app_handle: &tauri::AppHandle, attempt: DeliveryAttempt) {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let Ok(mut log) = state.webhook_log.lock() else {
+        return;
+    };
+    log.push_front(attempt);
+    log.truncate(LOG_LIMIT);
+
+    let result = serde_json::to_string_pretty(&*log)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| fs::write(get_log_path(app_handle), contents).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        println!("Failed to save webhook log: {}", e);
+    }
+}
+
+/// The wait before retrying a failed attempt, `None` once the retries are used
+/// up or when the receiver rejected the delivery for good.
+fn retry_delay(attempt: usize, status_code: Option<u16>) -> Option<Duration> {
+    if status_code.is_some_and(|code| (400..500).contains(&code) && code != 408 && code != 429) {
+        return None;
+    }
+    RETRY_DELAYS.get(attempt - 1).copied()
+}
+
+/// Attempts the delivery and logs it. Also returns the delay before the next
+/// attempt when it failed and `can_retry`.
+fn attempt(
+    app_handle: &tauri::AppHandle,
+    client: &reqwest::blocking::Client,
+    delivery: &mut Delivery,
+    can_retry: bool,
+) -> (DeliveryAttempt, Option<Duration>) {
+    delivery.attempt += 1;
+    let (status_code, error) = match send(client, delivery) {
+        Ok(status_code) if (200..300).contains(&status_code) => (Some(status_code), None),
+        Ok(status_code) => (Some(status_code), Some(format!("Receiver answered {}", status_code))),
+        Err(e) => (None, Some(e)),
+    };
+    let retry = error
+        .as_ref()
+        .filter(|_| can_retry)
+        .and_then(|_| retry_delay(delivery.attempt, status_code));
+    let retry_at = retry
+        .and_then(|delay| chrono::Duration::from_std(delay).ok())
+        .map(|delay| db::format_datetime(&(Utc::now() + delay)));
+
+    let logged = DeliveryAttempt {
+        delivery_id: delivery.id.clone(),
+        target_id: delivery.target.id.clone(),
+        url: delivery.target.url.clone(),
+        event: delivery.event.clone(),
+        attempt: delivery.attempt,
+        at: db::format_datetime(&Utc::now()),
+        status_code,
+        success: error.is_none(),
+        error,
+        retry_at,
+    };
+    record_attempt(app_handle, logged.clone());
+    (logged, retry)
+}
+
+fn build_client() -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_else(|_| reqwest::blocking::Client::new())
+}
+
+/// Sends queued deliveries in order and holds failed ones until their retry
+/// is due. Pending retries are lost when the app quits.
+/// Points a retry at the current settings of its target. False when the target
+/// was deleted or no longer wants the event, and the delivery should be dropped.
+fn refresh_target(delivery: &mut Delivery, targets: &[WebhookTarget]) -> bool {
+    match targets.iter().find(|target| target.id == delivery.target.id) {
+        Some(target) if target.wants(&delivery.event) => {
+            delivery.target = target.clone();
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn start_worker(app_handle: tauri::AppHandle, queue: Receiver<Delivery>) {
+    thread::spawn(move || {
+        let client = build_client();
+        let mut retries: Vec<(Instant, Delivery)> = Vec::new();
+        loop {
+            let next_retry = retries.iter().map(|(due, _)| *due).min();
+            let received = match next_retry {
+                Some(due) => queue.recv_timeout(due.saturating_duration_since(Instant::now())),
+                None => queue.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let mut due: Vec<Delivery> = Vec::new();
+            match received {
+                Ok(delivery) => due.push(delivery),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            let now = Instant::now();
+            let (ready, waiting): (Vec<_>, Vec<_>) = retries.into_iter().partition(|(at, _)| *at <= now);
+            retries = waiting;
+            if !ready.is_empty() {
+                // The target may have been edited since the first attempt
+                match load_settings(&app_handle) {
+                    Ok(settings) => {
+                        for (_, mut delivery) in ready {
+                            if refresh_target(&mut delivery, &settings.targets) {
+                                due.push(delivery);
+                            } else {
+                                println!("Dropped webhook delivery {}, its target was removed or turned off", delivery.id);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!("{}", e);
+                        due.extend(ready.into_iter().map(|(_, delivery)| delivery));
+                    }
+                }
+            }
+
+            for mut delivery in due {
+                if let (_, Some(delay)) = attempt(&app_handle, &client, &mut delivery, true) {
+                    retries.push((Instant::now() + delay, delivery));
+                }
+            }
+        }
+    });
+}
+
+/// Reads the delivery log saved by a previous run.
+pub fn load_log(app_handle: &tauri::AppHandle) -> VecDeque<DeliveryAttempt> {
+    fs::read_to_string(get_log_path(app_handle))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub fn list_webhooks(app_handle: tauri::AppHandle) -> Result<Vec<WebhookTarget>, String> {
+    Ok(load_settings(&app_handle)?.targets)
+}
+
+/// Adds `target`, or replaces the one with its id. Missing ids and secrets
+/// are generated.
+#[tauri::command]
+pub fn save_webhook(app_handle: tauri::AppHandle, mut target: WebhookTarget) -> Result<WebhookTarget, String> {
+    let url = reqwest::Url::parse(&target.url).map_err(|e| format!("Invalid URL {}: {}", target.url, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Webhook URLs have to be http or https, not {}", url.scheme()));
+    }
+    if target.id.is_empty() {
+        target.id = uuid::Uuid::new_v4().to_string();
+    }
+    if target.secret.is_empty() {
+        target.secret = generate_secret();
+    }
+
+    let mut settings = load_settings(&app_handle)?;
+    match settings.targets.iter_mut().find(|existing| existing.id == target.id) {
+        Some(existing) => *existing = target.clone(),
+        None => settings.targets.push(target.clone()),
+    }
+    save_settings(&app_handle, &settings)?;
+    Ok(target)
+}
+
+#[tauri::command]
+pub fn delete_webhook(app_handle: tauri::AppHandle, id: String) -> Result<(), String> {
+    let mut settings = load_settings(&app_handle)?;
+    settings.targets.retain(|target| target.id != id);
+    save_settings(&app_handle, &settings)
+}
+
+/// Sends a `ping` event to one target right away, without retries, and
+/// returns the logged attempt.
+#[tauri::command]
+pub async fn test_webhook(app_handle: tauri::AppHandle, id: String) -> Result<DeliveryAttempt, String> {
+    let target = load_settings(&app_handle)?
+        .targets
+        .into_iter()
+        .find(|target| target.id == id)
+        .ok_or_else(|| format!("No webhook {}", id))?;
+    let mut delivery = new_delivery(&target, PING_EVENT, &json!({ "webhookId": target.id }));
+
+    // The blocking client can't run on the async runtime's own threads
+    let (logged, _) = tauri::async_runtime::spawn_blocking(move || {
+        attempt(&app_handle, &build_client(), &mut delivery, false)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(logged)
+}
+
+#[tauri::command]
+pub fn get_webhook_log(app_handle: tauri::AppHandle) -> Result<Vec<DeliveryAttempt>, String> {
+    let state: tauri::State<Arc<AppState>> = app_handle.state();
+    let log = state.webhook_log.lock().map_err(|_| "Failed to lock webhook log".to_string())?;
+    Ok(log.iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::{Response, Server};
+
+    fn target(url: String, events: &[&str]) -> WebhookTarget {
+        WebhookTarget {
+            id: "target".to_string(),
+            url,
+            secret: "secret".to_string(),
+            events: events.iter().map(|event| event.to_string()).collect(),
+            enabled: true,
+        }
+    }
+
+    struct Received {
+        result: Result<u16, String>,
+        delivery: Delivery,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    /// Sends one delivery to a receiver on a free local port that answers
+    /// `status_code`.
+    fn deliver(status_code: u16) -> Received {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        let delivery = new_delivery(&target(url, &[]), "session-ended", &json!({ "sessionId": "s1" }));
+
+        let receiver = thread::spawn(move || {
+            let mut request = server.recv().unwrap();
+            let headers = request
+                .headers()
+                .iter()
+                .map(|header| (header.field.to_string(), header.value.to_string()))
+                .collect();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            request.respond(Response::empty(status_code)).unwrap();
+            (headers, body)
+        });
+        let result = send(&build_client(), &delivery);
+        let (headers, body) = receiver.join().unwrap();
+        Received { result, delivery, headers, body }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(field, _)| field.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn signs_the_body_with_the_target_secret() {
+        let Received { result, delivery, headers, body } = deliver(204);
+        assert_eq!(result, Ok(204));
+        assert_eq!(body, delivery.body);
+        let expected = format!("sha256={}", sign("secret", &body).unwrap());
+        assert_eq!(header(&headers, "X-ClockBlocks-Signature"), Some(expected.as_str()));
+        assert_eq!(header(&headers, "X-ClockBlocks-Event"), Some("session-ended"));
+        assert_eq!(header(&headers, "X-ClockBlocks-Delivery"), Some(delivery.id.as_str()));
+        assert_ne!(sign("other", &body).unwrap(), sign("secret", &body).unwrap());
+    }
+
+    #[test]
+    fn sends_only_the_events_a_target_wants() {
+        let all = target("http://127.0.0.1/".to_string(), &[]);
+        assert!(all.wants("session-started") && all.wants(PING_EVENT));
+
+        let some = target("http://127.0.0.1/".to_string(), &["session-ended"]);
+        assert!(some.wants("session-ended"));
+        assert!(!some.wants("time-box-switched"));
+
+        let disabled = WebhookTarget { enabled: false, ..all };
+        assert!(!disabled.wants("session-ended"));
+    }
+
+    #[test]
+    fn retries_with_backoff_until_the_delays_run_out() {
+        let delays: Vec<Option<Duration>> = (1..=5).map(|attempt| retry_delay(attempt, Some(503))).collect();
+        let expected: Vec<Option<Duration>> = RETRY_DELAYS.iter().copied().map(Some).chain([None]).collect();
+        assert_eq!(delays, expected);
+        assert_eq!(retry_delay(1, None), Some(RETRY_DELAYS[0]));
+    }
+
+    #[test]
+    fn gives_up_on_client_errors_except_timeouts_and_rate_limits() {
+        assert_eq!(deliver(410).result, Ok(410));
+        for status_code in [400, 401, 404, 410, 422] {
+            assert_eq!(retry_delay(1, Some(status_code)), None, "{}", status_code);
+        }
+        for status_code in [408, 429, 500, 502] {
+            assert_eq!(retry_delay(1, Some(status_code)), Some(RETRY_DELAYS[0]), "{}", status_code);
+        }
+    }
+
+    #[test]
+    fn retries_go_to_the_target_as_it_is_now() {
+        let mut delivery = new_delivery(&target("http://old".to_string(), &[]), "session-ended", &json!({}));
+
+        let mut edited = target("http://new".to_string(), &["session-ended"]);
+        edited.secret = "rotated".to_string();
+        assert!(refresh_target(&mut delivery, &[edited.clone()]));
+        assert_eq!((delivery.target.url.as_str(), delivery.target.secret.as_str()), ("http://new", "rotated"));
+
+        let disabled = WebhookTarget { enabled: false, ..edited.clone() };
+        assert!(!refresh_target(&mut delivery, &[disabled]));
+        let unsubscribed = target("http://new".to_string(), &["time-box-switched"]);
+        assert!(!refresh_target(&mut delivery, &[unsubscribed]));
+        assert!(!refresh_target(&mut delivery, &[]));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::db;
+use crate::report::{self, Cell, DateRange, ReportData, Table};
+use chrono::Local;
+use rust_xlsxwriter::{Color, Format, FormatBorder, Workbook, Worksheet, XlsxError};
+use std::path::PathBuf;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+struct Formats {
+    header: Format,
+    date: Format,
+    timestamp: Format,
+    duration: Format,
+}
+
+impl Formats {
+    fn new() -> Self {
+        Formats {
+            header: Format::new()
+                .set_bold()
+                .set_font_color(Color::White)
+                .set_background_color(Color::RGB(0x1673FF))
+                .set_border_bottom(FormatBorder::Thin),
+            date: Format::new().set_num_format("dd-mmm-yyyy"),
+            timestamp: Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+            // Elapsed hours, so totals over a day don't wrap around
+            duration: Format::new().set_num_format("[h]:mm:ss"),
+        }
+    }
+}
+
+/// Writes a cell as the Excel type matching its meaning: dates and timestamps
+/// as serial dates in local time, durations as fractions of a day.
+fn write_cell(worksheet: &mut Worksheet, row: u32, column: u16, cell: &Cell, formats: &Formats) -> Result<(), XlsxError> {
+    match cell {
+        Cell::Text(text) => worksheet.write_string(row, column, text)?,
+        Cell::Integer(value) => worksheet.write_number(row, column, *value as f64)?,
+        Cell::Date(date) => worksheet.write_date_with_format(row, column, date, &formats.date)?,
+        Cell::Timestamp(value) => match db::parse_datetime(value) {
+            Some(datetime) => worksheet.write_datetime_with_format(
+                row,
+                column,
+                datetime.with_timezone(&Local).naive_local(),
+                &formats.timestamp,
+            )?,
+            // Sessions still running have no end yet
+            None => worksheet.write_string(row, column, value)?,
+        },
+        Cell::Duration(seconds) => {
+            worksheet.write_number_with_format(row, column, *seconds as f64 / SECONDS_PER_DAY, &formats.duration)?
+        }
+    };
+    Ok(())
+}
+
+fn write_table(workbook: &mut Workbook, table: &Table, formats: &Formats) -> Result<(), XlsxError> {
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name(table.name)?;
+
+    for (row_index, row) in table.rows.iter().enumerate() {
+        for (column_index, cell) in row.iter().enumerate() {
+            let (row_index, column_index) = (row_index as u32, column_index as u16);
+            if row_index == 0 {
+                worksheet.write_string_with_format(row_index, column_index, cell.to_string(), &formats.header)?;
+            } else {
+                write_cell(worksheet, row_index, column_index, cell, formats)?;
+            }
+        }
+    }
+
+    worksheet.set_freeze_panes(1, 0)?;
+    worksheet.autofit();
+
+    // Autofit measures the underlying serial number rather than the formatted
+    // value, so typed columns get the width of their format or their header
+    let (Some(header), Some(first_row)) = (table.rows.first(), table.rows.get(1)) else {
+        return Ok(());
+    };
+    for (column_index, (title, cell)) in header.iter().zip(first_row).enumerate() {
+        if let Some(width) = format_width(cell) {
+            let width = width.max(title.to_string().chars().count() as f64 + 2.0);
+            worksheet.set_column_width(column_index as u16, width)?;
+        }
+    }
+    Ok(())
+}
+
+fn format_width(cell: &Cell) -> Option<f64> {
+    match cell {
+        Cell::Date(_) => Some(12.0),
+        Cell::Timestamp(_) => Some(20.0),
+        Cell::Duration(_) => Some(10.0),
+        Cell::Text(_) | Cell::Integer(_) => None,
+    }
+}
+
+/// Builds the workbook with the four tabs of the Sheets sync, in tab order,
+/// and a tab of Pomodoro intervals if there are any.
+pub fn build_workbook(data: &ReportData) -> Result<Workbook, XlsxError> {
+    let formats = Formats::new();
+    let mut workbook = Workbook::new();
+    for table in report::export_tables(data) {
+        write_table(&mut workbook, &table, &formats)?;
+    }
+    Ok(workbook)
+}
+
+/// Exports the sync tables as an `.xlsx` workbook, for use without a
+/// Google account. Without a `path` the user is asked where to save it.
+#[tauri::command]
+pub async fn export_xlsx(
+    app_handle: tauri::AppHandle,
+    path: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Option<String>, String> {
+    let range = DateRange::parse(from, to)?;
+
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let file_name = format!("clockblocks-{}.xlsx", Local::now().format("%Y-%m-%d"));
+            match FileDialogBuilder::new()
+                .set_title("Export workbook")
+                .set_file_name(&file_name)
+                .add_filter("Excel workbook", &["xlsx"])
+                .save_file()
+            {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let conn = db::open_connection(&app_handle)?;
+    let data = ReportData::load(&conn, range)?;
+    let mut workbook = build_workbook(&data).map_err(|e| e.to_string())?;
+    workbook.save(&path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    println!("Exported workbook to {}", path.display());
+    Ok(Some(path.display().to_string()))
+}
@@ -1,14 +1,16 @@
 import { invoke } from "@tauri-apps/api/tauri";
-import { useState, useEffect } from "react";
+import { listen } from "@tauri-apps/api/event";
+import { useState, useEffect, useRef } from "react";
 import { v4 as uuidv4 } from 'uuid';
 import TimerPage from './pages/TimerPage';
 import ChartPage from './pages/ChartPage';
 import SettingsPage from './pages/SettingsPage';
 import NavigationBar from './components/NavigationBar';
-import { TimeBox, SessionEvent, Session, AuthToken } from "./lib/types";
+import { TimeBox, SessionEvent, Session, AuthToken, TimerCommand, ChartRange, NavigateRequest, IdlePeriod, IdleResolution, PomodoroPhase } from "./lib/types";
 import { getTimeBoxes, getSessionEvents, upsertSessionEvent, upsertSession, maybeInitializeDatabase, startTransaction, commitTransaction, rollbackTransaction } from "./lib/dbInteraction";
 import { handleSyncData } from "./lib/writeToGSheet";
 import RoundedBox from "./components/RoundedBox";
+import IdlePrompt from "./components/IdlePrompt";
 import { SessionProvider, useSession } from './context/SessionContext';
 
 function AppContent() {
@@ -18,6 +20,8 @@ function AppContent() {
   const [timeBoxes, setTimeBoxes] = useState<TimeBox[]>([]);
   const [activeBox, setActiveBox] = useState<string | null>(null);
   const [activePage, setActivePage] = useState('timer');
+  const [requestedChartRange, setRequestedChartRange] = useState<{ range: ChartRange } | null>(null);
+  const [idlePeriod, setIdlePeriod] = useState<IdlePeriod | null>(null);
   const [activeSession, setActiveSession] = useState<Session>(createNewSession());
   const [isAuthenticated, setIsAuthenticated] = useState<boolean | null>(null);
 
@@ -66,7 +70,8 @@ function AppContent() {
       await invoke('save_auth_token', { 
         accessToken: tokens.access_token,
         refreshToken: tokens.refresh_token,
-        expiry: tokens.expiry
+        expiry: tokens.expiry,
+        scope: tokens.scope
       });
       return true;
     } catch (error) {
@@ -110,9 +115,80 @@ function AppContent() {
   useEffect(() => {
     updateTimeBoxes();
   }, [sessionEvents])
+
+  // Mirror the timer in the backend, which drives it through "timer-command"
+  useEffect(() => {
+    const runningEvent = sessionEvents[sessionEvents.length - 1];
+    invoke('report_timer_state', {
+      state: {
+        activeTimeBoxId: activeBox,
+        sessionId: activeBox ? activeSession.id : null,
+        sessionStart: activeBox ? activeSession.startDatetime : null,
+        eventStart: activeBox && runningEvent && !runningEvent.endDatetime ? runningEvent.startDatetime : null
+      }
+    }).catch((error) => console.error("Failed to report timer state:", error));
+  }, [activeBox, activeSession.id, activeSession.startDatetime, sessionEvents.length]);
+
+  // Handlers change every render, the listener below always calls the latest
+  const timerCommandHandler = useRef<(command: TimerCommand) => void>(() => {});
+  timerCommandHandler.current = (command: TimerCommand) => {
+    if (command.type === 'switch' && command.timeBoxId !== activeBox) {
+      // Pomodoro mode creates the Rest box the first time it is needed
+      loadMissingTimeBoxes(command.timeBoxId)
+        .then(() => handleTimeBoxClick(command.timeBoxId, command.completedInterval))
+        .catch((error) => console.error("Failed to switch time box:", error));
+    } else if (command.type === 'endSession' && activeBox) {
+      resetAllTimers();
+    }
+  };
+
+  useEffect(() => {
+    const unlisten = listen<TimerCommand>('timer-command', (event) => timerCommandHandler.current(event.payload));
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<NavigateRequest>('navigate', (event) => {
+      setActivePage(event.payload.page);
+      if (event.payload.range) {
+        setRequestedChartRange({ range: event.payload.range });
+      }
+    });
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    // The backend keeps a period detected before the webview loaded
+    invoke<IdlePeriod | null>('get_idle_period')
+      .then(setIdlePeriod)
+      .catch((error) => console.error("Failed to load idle time:", error));
+    const unlisten = listen<IdlePeriod>('idle-detected', (event) => setIdlePeriod(event.payload));
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    // A restored backup replaces everything the UI has loaded
+    const unlisten = listen('database-restored', () => window.location.reload());
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
     
+  const loadMissingTimeBoxes = async (timeBoxId: string) => {
+    if (!timeBoxes.some(box => box.id === timeBoxId)) {
+      const savedBoxes = await getTimeBoxes();
+      setTimeBoxes(prevBoxes => [...prevBoxes, ...savedBoxes.filter(box => !prevBoxes.some(prevBox => prevBox.id === box.id))]);
+    }
+  };
+
   // Event handlers
-  const handleTimeBoxClick = (activeId: string) => {
+  const handleTimeBoxClick = (activeId: string, completedInterval?: { phase: PomodoroPhase; cycle: number }) => {
     const currentTime = new Date();
     const currentTimeISO = currentTime.toISOString();
 
@@ -131,7 +207,8 @@ function AppContent() {
         const updatedLastEvent = {
           ...lastEvent,
           endDatetime: currentTime.toISOString(),
-          seconds: Math.round((currentTime.getTime() - new Date(lastEvent.startDatetime).getTime()) / 1000)
+          seconds: Math.round((currentTime.getTime() - new Date(lastEvent.startDatetime).getTime()) / 1000),
+          ...(completedInterval && { pomodoroPhase: completedInterval.phase, pomodoroCycle: completedInterval.cycle })
         };
         return [...sessionEvents.slice(0, -1), updatedLastEvent, newEvent];
       }
@@ -183,6 +260,74 @@ function AppContent() {
     saveToDatabase(updatedSessionEvents, updatedSession);
   };
 
+  // Moves start..end to another box by splitting the event that holds it
+  const reassignTime = async (start: string, end: string, timeBoxId: string) => {
+    const startTime = new Date(start).getTime();
+    const index = sessionEvents.findIndex(event => {
+      const eventEnd = event.endDatetime ? new Date(event.endDatetime).getTime() : Infinity;
+      return new Date(event.startDatetime).getTime() <= startTime && startTime < eventEnd;
+    });
+    if (index === -1) {
+      console.error("No session event holds the time to reassign");
+      return;
+    }
+
+    const event = sessionEvents[index];
+    const eventStart = new Date(event.startDatetime).getTime();
+    const eventEnd = event.endDatetime ? new Date(event.endDatetime).getTime() : null;
+    const splitEnd = eventEnd === null ? new Date(end).getTime() : Math.min(new Date(end).getTime(), eventEnd);
+    const pieces = [
+      { start: eventStart, end: startTime, timeBoxId: event.timeBoxId },
+      { start: startTime, end: splitEnd, timeBoxId },
+      // Still running when the split event was
+      { start: splitEnd, end: eventEnd, timeBoxId: event.timeBoxId },
+    ].filter(piece => piece.end === null || piece.end > piece.start);
+    const splitEvents: SessionEvent[] = pieces.map((piece, pieceIndex) => ({
+      ...event,
+      id: pieceIndex === 0 ? event.id : uuidv4(),
+      timeBoxId: piece.timeBoxId,
+      startDatetime: new Date(piece.start).toISOString(),
+      endDatetime: piece.end === null ? null : new Date(piece.end).toISOString(),
+      seconds: Math.round(((piece.end ?? Date.now()) - piece.start) / 1000)
+    }));
+    const updatedSessionEvents = [...sessionEvents.slice(0, index), ...splitEvents, ...sessionEvents.slice(index + 1)];
+
+    setSessionEvents(updatedSessionEvents);
+    if (eventEnd === null) {
+      setActiveSession(prevSession => ({ ...prevSession, sessionEvents: updatedSessionEvents }));
+    }
+
+    // Like a switch, the running event is only written once it ends
+    let db;
+    try {
+      db = await startTransaction();
+      for (const splitEvent of splitEvents.filter(splitEvent => splitEvent.endDatetime)) {
+        await upsertSessionEvent(splitEvent, db);
+      }
+      await commitTransaction(db);
+    } catch (error) {
+      if (db) {
+        await rollbackTransaction(db);
+      }
+      console.error("Failed to save reassigned time:", error);
+    }
+  };
+
+  const resolveIdlePeriod = async (resolution: IdleResolution) => {
+    if (!idlePeriod) return;
+    try {
+      const targetId = await invoke<string | null>('resolve_idle_period', { id: idlePeriod.id, resolution });
+      if (targetId) {
+        // The backend creates the Rest box the first time it is needed
+        await loadMissingTimeBoxes(targetId);
+        await reassignTime(idlePeriod.start, idlePeriod.end, targetId);
+      }
+    } catch (error) {
+      console.error("Failed to resolve idle time:", error);
+    }
+    setIdlePeriod(null);
+  };
+
   const resetAllTimers = () => {
     console.log("resetAllTimers");
     const activeTimeBox = timeBoxes.find(box => box.isActive);
@@ -258,6 +403,7 @@ function AppContent() {
           {activePage === 'chart' && (
             <ChartPage 
               timeBoxes={timeBoxes.filter(box => !box.isHidden)}
+              requestedRange={requestedChartRange}
             />
           )}
           {activePage === 'settings' && (
@@ -288,6 +434,14 @@ function AppContent() {
           </RoundedBox>
         )}
       </div>
+      {idlePeriod && (
+        <div className="fixed inset-0 z-50 flex items-center justify-center">
+          <div className="absolute inset-0 bg-black bg-opacity-50 backdrop-blur-sm"></div>
+          <div className="z-10">
+            <IdlePrompt period={idlePeriod} timeBoxes={timeBoxes} onResolve={resolveIdlePeriod} />
+          </div>
+        </div>
+      )}
     </div>
   );
 
@@ -0,0 +1,54 @@
+import { useState } from 'react';
+import PrimaryButton from './PrimaryButton';
+import Dropdown from './DropDownButton';
+import { IdlePeriod, IdleResolution, TimeBox } from '../lib/types';
+import { formatSeconds } from '../lib/utils';
+
+interface IdlePromptProps {
+  period: IdlePeriod;
+  timeBoxes: TimeBox[];
+  onResolve: (resolution: IdleResolution) => void;
+}
+
+const formatTimeFromISO = (isoString: string) =>
+  new Date(isoString).toLocaleTimeString('en-GB', { hour: '2-digit', minute: '2-digit', hour12: false });
+
+export default function IdlePrompt({ period, timeBoxes, onResolve }: IdlePromptProps) {
+  const otherBoxes = timeBoxes.filter(box => box.id !== period.timeBoxId && !box.isHidden);
+  const [selectedTimeBoxId, setSelectedTimeBoxId] = useState(otherBoxes[0]?.id ?? '');
+  const runningBoxName = timeBoxes.find(box => box.id === period.timeBoxId)?.name ?? 'the running box';
+
+  return (
+    <div className="flex p-6 flex-col items-start gap-6 rounded-2xl bg-black backdrop-blur-[40px] w-[420px] border border-[#5E5E5E] border-opacity-30">
+      <div className="flex flex-col items-start gap-0 self-stretch">
+        <p className="text-[#D9D9D9] leading-trim text-edge-cap text-3xl font-normal leading-normal">
+          You were away
+        </p>
+        <p className="text-[rgba(255,255,255,0.50)] leading-trim text-edge-cap text-sm font-normal leading-normal">
+          {formatSeconds(period.seconds)} from {formatTimeFromISO(period.start)} to {formatTimeFromISO(period.end)} went to {runningBoxName}
+        </p>
+      </div>
+      <div className="flex items-center gap-4 self-stretch">
+        <PrimaryButton isActive={true} isClickable={true} onClick={() => onResolve({ type: 'keep' })}>Keep</PrimaryButton>
+        <PrimaryButton isActive={false} isClickable={true} onClick={() => onResolve({ type: 'break' })}>Rest</PrimaryButton>
+      </div>
+      {otherBoxes.length > 0 && (
+        <div className="flex justify-between items-center self-stretch gap-4">
+          <Dropdown
+            options={otherBoxes.map(box => ({ id: box.id, name: box.name }))}
+            value={selectedTimeBoxId}
+            onChange={(id) => setSelectedTimeBoxId(id)}
+            textSize="text-sm"
+          />
+          <PrimaryButton
+            isActive={false}
+            isClickable={selectedTimeBoxId !== ''}
+            onClick={() => onResolve({ type: 'assign', timeBoxId: selectedTimeBoxId })}
+          >
+            Assign
+          </PrimaryButton>
+        </div>
+      )}
+    </div>
+  );
+}
@@ -70,6 +70,7 @@ export async function maybeInitializeDatabase() {
 
   if (tablesExist[0].count > 0) {
     console.log('Existing tables found');
+    await migrateSchema(db);
     return;
   }
 
@@ -102,6 +103,8 @@ export async function maybeInitializeDatabase() {
       startDatetime TEXT NOT NULL,
       endDatetime TEXT NOT NULL,
       seconds INTEGER NOT NULL,
+      pomodoroPhase TEXT,
+      pomodoroCycle INTEGER,
       FOREIGN KEY (timeBoxId) REFERENCES ${TABLES.TIME_BOXES}(id),
       FOREIGN KEY (sessionId) REFERENCES ${TABLES.SESSIONS}(id)
     )
@@ -130,6 +133,16 @@ export async function maybeInitializeDatabase() {
   console.log('Database setup completed.');
 }
 
+// Adds the columns of later versions to existing tables. The backend does the
+// same when it opens the file first, in `migrate` in db.rs.
+async function migrateSchema(db: Database) {
+  const columns = await db.select<{ name: string }[]>(`SELECT name FROM pragma_table_info('${TABLES.SESSION_EVENTS}')`);
+  if (columns.length > 0 && !columns.some(column => column.name === 'pomodoroPhase')) {
+    await executeQuery(db, `ALTER TABLE ${TABLES.SESSION_EVENTS} ADD COLUMN pomodoroPhase TEXT`);
+    await executeQuery(db, `ALTER TABLE ${TABLES.SESSION_EVENTS} ADD COLUMN pomodoroCycle INTEGER`);
+    console.log('Added Pomodoro columns to sessionEvents');
+  }
+}
 
 async function getActualDbPath(): Promise<string> {
   const isDev = await checkDevMode();
@@ -277,14 +290,17 @@ export async function upsertSessionEvent(event: SessionEvent, transaction?: Data
     const eventId = event.id && event.id.trim() !== '' ? event.id : uuidv4();
 
     await db.execute(`
-      INSERT INTO sessionEvents (id, timeBoxId, sessionId, startDatetime, endDatetime, seconds)
-      VALUES ($1, $2, $3, $4, $5, $6)
+      INSERT INTO sessionEvents (id, timeBoxId, sessionId, startDatetime, endDatetime, seconds, pomodoroPhase, pomodoroCycle)
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
       ON CONFLICT (id) DO UPDATE SET
         timeBoxId = EXCLUDED.timeBoxId,
         startDatetime = EXCLUDED.startDatetime,
         endDatetime = EXCLUDED.endDatetime,
-        seconds = EXCLUDED.seconds
-    `, [eventId, event.timeBoxId, event.sessionId, event.startDatetime, event.endDatetime, event.seconds]);
+        seconds = EXCLUDED.seconds,
+        pomodoroPhase = COALESCE(EXCLUDED.pomodoroPhase, pomodoroPhase),
+        pomodoroCycle = COALESCE(EXCLUDED.pomodoroCycle, pomodoroCycle)
+    `, [eventId, event.timeBoxId, event.sessionId, event.startDatetime, event.endDatetime, event.seconds,
+        event.pomodoroPhase ?? null, event.pomodoroCycle ?? null]);
 
     // Update session duration
     await updateSessionDuration(event.sessionId, db);
@@ -15,8 +15,13 @@ export interface SessionEvent {
     startDatetime: string;
     endDatetime: string | null;
     seconds: number;
+    // Set on the event that completed a Pomodoro interval
+    pomodoroPhase?: PomodoroPhase | null;
+    pomodoroCycle?: number | null;
 } 
 
+export type PomodoroPhase = 'focus' | 'shortBreak' | 'longBreak';
+
 export interface Session {
     id: string;
     startDatetime: string | null;
@@ -29,4 +34,31 @@ export interface AuthToken {
     access_token: string;
     refresh_token: string;
     expiry: number;
-  }
\ No newline at end of file
+    scope?: string;
+  }
+export type TimerCommand =
+    | { type: 'switch'; timeBoxId: string; completedInterval?: { phase: PomodoroPhase; cycle: number } }
+    | { type: 'endSession' };
+
+export type ChartRange = 'Week' | 'Month' | 'All';
+
+// Time away from the computer while a box was running, sent as "idle-detected"
+export interface IdlePeriod {
+    id: string;
+    timeBoxId: string;
+    sessionId: string | null;
+    start: string;
+    end: string;
+    seconds: number;
+}
+
+export type IdleResolution =
+    | { type: 'keep' }
+    | { type: 'break' }
+    | { type: 'assign'; timeBoxId: string };
+
+// Sent by the backend when a clockblocks://open link is followed
+export interface NavigateRequest {
+    page: 'timer' | 'chart' | 'settings';
+    range: ChartRange | null;
+}
@@ -1,16 +1,39 @@
 import { invoke } from "@tauri-apps/api/tauri";
+import { ask } from '@tauri-apps/api/dialog';
 import { SessionEvent, Session } from "./types";
 import { getTimeBoxes, getSessions, getSessionEvents } from "./dbInteraction";
 
 
-export const handleSyncData = async (): Promise<string | undefined> => {
+interface Profile {
+  name: string;
+  timeBoxIds: string[] | null;
+}
+
+interface ProfileStore {
+  active: string;
+  profiles: Profile[];
+}
+
+export const handleSyncData = async (profileName?: string): Promise<string | undefined> => {
+  let sheetId: string | null = null;
   try {
-    const timeBoxes = await getTimeBoxes();
-    const sessions = await getSessions();
-    const sessionEvents = await getSessionEvents();
+    // Every command gets the profile, so syncing one leaves the active profile as it is
+    const store: ProfileStore = await invoke('list_profiles');
+    const profile = store.profiles.find(profile => profile.name === (profileName ?? store.active));
+    if (!profile) {
+      throw new Error(`Profile ${profileName ?? store.active} not found`);
+    }
 
-    const sheetId = await invoke('get_or_create_new_sheet', { title: "ClockBlocks Data" });
+    sheetId = await invoke<string>('get_or_create_new_sheet', { title: "ClockBlocks Data", profile: profile.name });
     console.log('Syncing sheet with ID:', sheetId);
+
+    await importSheetCorrections(sheetId, profile.name);
+
+    // Only push the boxes this profile is meant to see
+    const inProfile = (timeBoxId: string) => !profile.timeBoxIds || profile.timeBoxIds.includes(timeBoxId);
+    const timeBoxes = (await getTimeBoxes()).filter(box => inProfile(box.id));
+    const sessionEvents = (await getSessionEvents()).filter(event => inProfile(event.timeBoxId));
+    const sessions = (await getSessions()).filter(session => sessionEvents.some(event => event.sessionId === session.id));
     
     const timeBoxMap = timeBoxes.reduce((acc, box) => {
       acc[box.id] = box.name;
@@ -26,36 +49,77 @@ export const handleSyncData = async (): Promise<string | undefined> => {
     await invoke('write_data_to_sheet', { 
       sheetId: sheetId, 
       sheetName: 'SummaryByDate', 
-      data: summaryByDate 
+      data: summaryByDate,
+      profile: profile.name
     });
 
     await invoke('write_data_to_sheet', { 
       sheetId: sheetId, 
       sheetName: 'SummaryBySession', 
-      data: summaryBySession 
+      data: summaryBySession,
+      profile: profile.name
     });
 
     await invoke('write_data_to_sheet', { 
       sheetId: sheetId, 
       sheetName: 'DetailsSessions', 
-      data: detailsSessions 
+      data: detailsSessions,
+      profile: profile.name
     });
 
     await invoke('write_data_to_sheet', { 
       sheetId: sheetId, 
       sheetName: 'DetailsSessionEvents', 
-      data: detailsSessionEvents 
+      data: detailsSessionEvents,
+      profile: profile.name
     });
 
+    await invoke('record_sheet_sync', { sheetId: sheetId, profile: profile.name });
+    await invoke('update_charts', { sheetId: sheetId, profile: profile.name });
 
     console.log('Data synced successfully');
+    await invoke('report_sync_result', { sheetId, error: null });
     return `https://docs.google.com/spreadsheets/d/${sheetId}`;
   } catch (error) {
     console.error('Error syncing data:', error);
+    await invoke('report_sync_result', { sheetId, error: String(error) }).catch(() => {});
     return undefined;
   }
 };
 
+interface SheetEdit {
+  eventId: string;
+}
+
+interface SheetCorrectionReport {
+  edits: SheetEdit[];
+  conflicts: SheetEdit[];
+  applied: string[];
+  rejected: { eventId: string, reason: string }[];
+}
+
+// Pull edits made in the DetailsSessionEvents tab into the database before it gets overwritten
+async function importSheetCorrections(sheetId: string, profile: string): Promise<void> {
+  const preview: SheetCorrectionReport = await invoke('preview_sheet_corrections', { sheetId, profile });
+  let acceptSheetFor: string[] = [];
+
+  if (preview.conflicts.length > 0) {
+    const keepSheet = await ask(
+      `${preview.conflicts.length} event(s) were changed both in the sheet and in ClockBlocks since the last sync. Keep the versions from the sheet?`,
+      { title: 'Sync conflicts', type: 'warning' }
+    );
+    if (keepSheet) {
+      acceptSheetFor = preview.conflicts.map(conflict => conflict.eventId);
+    }
+  }
+
+  if (preview.edits.length === 0 && acceptSheetFor.length === 0) return;
+
+  const report: SheetCorrectionReport = await invoke('apply_sheet_corrections', { sheetId, acceptSheetFor, profile });
+  console.log(`Imported ${report.applied.length} corrections from the sheet`);
+  report.rejected.forEach(rejected => console.warn('Rejected sheet correction:', rejected.eventId, rejected.reason));
+}
+
 // Helper functions
 function formatDuration(seconds: number): string {
   const hours = Math.floor(seconds / 3600);
@@ -127,7 +191,7 @@ function createDetailsSessions(sessions: Session[]): string[][] {
 
 function createDetailsSessionEvents(sessions: Session[], sessionEvents: SessionEvent[], timeBoxMap: Record<string, string>): string[][] {
   return [
-    ['Event', 'Session', 'Start', 'End', 'Duration'],
+    ['Event', 'Session', 'Start', 'End', 'Duration', 'Event ID'],
     ...sessionEvents.map(event => {
       const startTime = new Date(event.startDatetime).getTime();
       const endTime = event.endDatetime ? new Date(event.endDatetime).getTime() : Date.now();
@@ -138,7 +202,8 @@ function createDetailsSessionEvents(sessions: Session[], sessionEvents: SessionE
         (sessions.findIndex(s => s.id === event.sessionId) + 1).toString(),
         event.startDatetime,
         event.endDatetime || '',
-        duration
+        duration,
+        event.id
       ];
     })
   ];
@@ -1,15 +1,16 @@
 import { useState, useEffect, useCallback, useMemo } from "react";
 import { BarChart, Bar, XAxis, YAxis, Tooltip, Legend, ResponsiveContainer, ReferenceLine } from 'recharts';
-import { SessionEvent, TimeBox } from "../lib/types";
+import { ChartRange, SessionEvent, TimeBox } from "../lib/types";
 import SortingPanel from "../components/ChartSorting";
 import ChartSessionPanel from "../components/ChartSessionPanel";
 import { formatSeconds, formatTime } from "../lib/utils";
 import { useSession } from '../context/SessionContext';
 
-function ChartPage({ timeBoxes }: { timeBoxes: TimeBox[] }) {
+// requestedRange is a new object for each request, so repeating a link applies it again
+function ChartPage({ timeBoxes, requestedRange }: { timeBoxes: TimeBox[], requestedRange?: { range: ChartRange } | null }) {
     const { sessionEvents } = useSession(); // Use the sessionEvents from context
     const [chartType, setChartType] = useState<'session' | 'date'>('date');
-    const [groupBy, setGroupBy] = useState<'Week' | 'Month' | 'All'>('Week');
+    const [groupBy, setGroupBy] = useState<ChartRange>(requestedRange?.range ?? 'Week');
     const [currentPeriod, setCurrentPeriod] = useState<Date>(new Date());
     const [filteredEvents, setFilteredEvents] = useState<SessionEvent[]>([]);
     const [selectedBarData, setSelectedBarData] = useState<{ barData: { title: string; time: number; color: string; }[], sessionId: string, sessionStart: string, sessionNumber: string, title: string }>({ barData: [], sessionId: '', sessionStart: '', sessionNumber: '', title: '' });
@@ -135,6 +136,13 @@ function ChartPage({ timeBoxes }: { timeBoxes: TimeBox[] }) {
         filterEvents();
     }, [groupBy, currentPeriod, sessionEvents]);
 
+    useEffect(() => {
+        if (requestedRange) {
+            setGroupBy(requestedRange.range);
+            setCurrentPeriod(new Date());
+        }
+    }, [requestedRange]);
+
     useEffect(() => {
         // Update selected bar data when chart data changes
         if (selectedBarData.sessionId) {
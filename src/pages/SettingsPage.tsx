@@ -230,7 +230,7 @@ function SettingsPage({
             <h4 className="text-m text-white text-center">Google Sheets Integration</h4>
             {!isAuthenticated ? (
             <div className="max-w-[400px] text-center">
-              <p className="text-white text-[12px]">This integration allows ClockBlocks to create a new Google Sheet and export your data so you can create your own charts. ClockBlocks does not access anything else.</p>
+              <p className="text-white text-[12px]">This integration allows ClockBlocks to create a new Google Sheet and export your data so you can create your own charts. ClockBlocks does not access anything else, so it can only sync to spreadsheets it created, not ones you made in Google Sheets yourself.</p>
             </div>
             ): <></>}
             {!isAuthenticated ? (